@@ -0,0 +1,852 @@
+//! Block device model for auto_mount
+//!
+//! This module describes a block device with its attributes read once from sysfs,
+//! so later stages of the pipeline don't need to re-query the system
+
+use crate::device_discovery::{
+    classify_disk_name, multipath_node, transport_from_sysfs_path, MultipathNode, Transport,
+};
+use crate::SystemRoot;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use strum::{Display, EnumString};
+
+/// Errors that can occur while reading block device information
+#[derive(Debug, thiserror::Error)]
+pub enum BlockDeviceError {
+    #[error("IO error: {0}")]
+    IoError(io::Error),
+    #[error("Invalid device path: {0}")]
+    InvalidDevicePath(String),
+    #[error("Device not found in sysfs: {0}")]
+    NotFound(String),
+    #[error("Unsupported device: {0}")]
+    UnsupportedDevice(String),
+    #[error("Missing sysfs attribute {attribute} for {device}")]
+    MissingAttribute { device: String, attribute: String },
+    #[error("Invalid value for {attribute} of {device}: {value}")]
+    InvalidAttribute {
+        device: String,
+        attribute: String,
+        value: String,
+    },
+}
+
+impl From<io::Error> for BlockDeviceError {
+    fn from(error: io::Error) -> Self {
+        BlockDeviceError::IoError(error)
+    }
+}
+
+/// Kernel major:minor device number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeviceNumber {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl fmt::Display for DeviceNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for DeviceNumber {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (major, minor) = s.trim().split_once(':').ok_or(())?;
+        Ok(DeviceNumber {
+            major: major.parse().map_err(|_| ())?,
+            minor: minor.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+/// Zone model of a disk, from `queue/zoned`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ZonedModel {
+    /// A conventional disk
+    None,
+    /// SMR disk that accepts random writes but performs best written sequentially
+    HostAware,
+    /// SMR or ZNS disk that only accepts sequential writes within each zone
+    HostManaged,
+}
+
+impl ZonedModel {
+    pub fn is_zoned(&self) -> bool {
+        *self != ZonedModel::None
+    }
+}
+
+/// Block device information collected from sysfs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDevice {
+    /// Device node path, e.g. `/dev/sda`
+    pub path: String,
+    /// Kernel name, e.g. `sda`
+    pub name: String,
+    /// Kernel name of the whole disk if this device is a partition
+    pub parent: Option<String>,
+    pub device_number: DeviceNumber,
+    pub size_bytes: u64,
+    pub logical_sector_size: u32,
+    pub physical_sector_size: u32,
+    /// Bytes by which the device's natural alignment is offset from sector 0
+    pub alignment_offset: u32,
+    /// Smallest preferred I/O size in bytes, e.g. a RAID chunk; 0 when unknown
+    pub minimum_io_size: u32,
+    /// Preferred I/O size in bytes, e.g. a RAID stripe; 0 when unknown
+    pub optimal_io_size: u32,
+    pub model: Option<String>,
+    pub vendor: Option<String>,
+    pub serial: Option<String>,
+    pub wwn: Option<String>,
+    /// `/dev/disk/by-id/...` links pointing at this device, WWN links included
+    pub by_id: Vec<String>,
+    /// `/dev/disk/by-path/...` links pointing at this device
+    pub by_path: Vec<String>,
+    /// Kernel names of the paths (`sdb`, `sdc`) behind a dm-multipath device;
+    /// empty for ordinary disks
+    pub multipath_paths: Vec<String>,
+    pub transport: Transport,
+    pub rotational: bool,
+    pub zoned: ZonedModel,
+    pub removable: bool,
+    pub read_only: bool,
+}
+
+impl BlockDevice {
+    /// Read a block device from sysfs by its kernel name (`sda`, `nvme0n1p1`, ...)
+    pub fn from_name(name: &str) -> Result<BlockDevice, BlockDeviceError> {
+        BlockDevice::from_name_in(&SystemRoot::default(), name)
+    }
+
+    /// Read a block device by kernel name from the sysfs below the given root
+    pub fn from_name_in(root: &SystemRoot, name: &str) -> Result<BlockDevice, BlockDeviceError> {
+        let sysfs_dir = root.sys_class_block().join(name);
+        if name.is_empty() || !sysfs_dir.exists() {
+            return Err(BlockDeviceError::NotFound(name.to_string()));
+        }
+        if let Some(node) = multipath_node(root, name) {
+            return read_multipath(root, name, node);
+        }
+        let mut device = read_from_sysfs(&sysfs_dir, &format!("/dev/{}", name))?;
+        device.by_id = device_links(root, "by-id", name);
+        device.by_path = device_links(root, "by-path", name);
+        Ok(device)
+    }
+
+    /// Read a block device from any identifier [`resolve_device_path`] accepts
+    pub fn from_identifier(identifier: &str) -> Result<BlockDevice, BlockDeviceError> {
+        BlockDevice::from_identifier_in(&SystemRoot::default(), identifier)
+    }
+
+    /// Read a block device from any identifier, resolved below the given root
+    pub fn from_identifier_in(
+        root: &SystemRoot,
+        identifier: &str,
+    ) -> Result<BlockDevice, BlockDeviceError> {
+        let path = resolve_device_path_in(root, identifier)?;
+        BlockDevice::from_path_in(root, &path)
+    }
+
+    /// Read a block device from sysfs by its device node path (`/dev/sda`)
+    pub fn from_path(path: &str) -> Result<BlockDevice, BlockDeviceError> {
+        BlockDevice::from_path_in(&SystemRoot::default(), path)
+    }
+
+    /// Read a block device by device node path from the sysfs below the given root;
+    /// `/dev/mapper/<name>` paths are followed to their `dm-N` node
+    pub fn from_path_in(root: &SystemRoot, path: &str) -> Result<BlockDevice, BlockDeviceError> {
+        if path.starts_with("/dev/mapper/") {
+            let resolved = resolve_device_path_in(root, path)?;
+            return BlockDevice::from_path_in(root, &resolved);
+        }
+        let name = path
+            .strip_prefix("/dev/")
+            .filter(|name| !name.is_empty() && !name.contains('/'))
+            .ok_or_else(|| BlockDeviceError::InvalidDevicePath(path.to_string()))?;
+        BlockDevice::from_name_in(root, name)
+    }
+
+    /// Whether this device is a partition of another block device
+    pub fn is_partition(&self) -> bool {
+        self.parent.is_some()
+    }
+
+    /// The identifier least likely to change between reboots: a WWN link,
+    /// another by-id link, a by-path link, or the kernel path as a last resort
+    pub fn stable_id(&self) -> &str {
+        self.by_id
+            .iter()
+            .find(|link| link.starts_with("/dev/disk/by-id/wwn-"))
+            .or_else(|| self.by_id.first())
+            .or_else(|| self.by_path.first())
+            .unwrap_or(&self.path)
+    }
+
+    /// Size in whole gigabytes (GiB)
+    pub fn size_gb(&self) -> u64 {
+        self.size_bytes / (1024 * 1024 * 1024)
+    }
+}
+
+/// Resolve a device identifier to the current `/dev/<kernel name>` path.
+///
+/// Accepts `/dev/sdb`, `sdb`, `/dev/disk/by-id/...` and `/dev/disk/by-path/...`
+/// links or their bare link names, and WWNs (`wwn-0x5000c500a1b2c3d4`,
+/// `0x5000c500a1b2c3d4`, `naa.5000c500a1b2c3d4`).
+pub fn resolve_device_path(identifier: &str) -> Result<String, BlockDeviceError> {
+    resolve_device_path_in(&SystemRoot::default(), identifier)
+}
+
+/// Resolve a device identifier below the given system root
+pub fn resolve_device_path_in(
+    root: &SystemRoot,
+    identifier: &str,
+) -> Result<String, BlockDeviceError> {
+    let candidates = if identifier.starts_with("/dev/") {
+        vec![identifier.to_string()]
+    } else {
+        vec![
+            format!("/dev/disk/by-id/{}", identifier),
+            format!("/dev/disk/by-path/{}", identifier),
+            format!("/dev/disk/by-id/wwn-{}", identifier),
+            format!("/dev/{}", identifier),
+        ]
+    };
+
+    let known = |name: &String| !name.is_empty() && root.sys_class_block().join(name).exists();
+    candidates
+        .iter()
+        .filter_map(|candidate| fs::canonicalize(root.dev_path(candidate)).ok())
+        .map(|node| file_name(&node))
+        .find(known)
+        .or_else(|| find_by_wwn(root, identifier))
+        .map(|name| format!("/dev/{}", name))
+        .ok_or_else(|| BlockDeviceError::NotFound(identifier.to_string()))
+}
+
+/// Resolve every identifier, failing on the first unknown one
+pub(crate) fn resolve_device_paths_in(
+    root: &SystemRoot,
+    identifiers: &[String],
+) -> Result<Vec<String>, BlockDeviceError> {
+    identifiers
+        .iter()
+        .map(|identifier| resolve_device_path_in(root, identifier))
+        .collect()
+}
+
+/// Kernel name of the disk whose sysfs WWN matches, ignoring `naa.`/`0x` style prefixes
+fn find_by_wwn(root: &SystemRoot, wwn: &str) -> Option<String> {
+    let wanted = normalize_wwn(wwn);
+    fs::read_dir(root.sys_block())
+        .ok()?
+        .flatten()
+        .find(|entry| {
+            let dir = entry.path();
+            read_attribute(&dir, "device/wwid")
+                .or_else(|| read_attribute(&dir, "wwid"))
+                .map_or(false, |wwid| normalize_wwn(&wwid) == wanted)
+        })
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+}
+
+/// A WWN without case, `wwn-`, `naa.`/`eui.`/`t10.` or `0x` decoration
+pub(crate) fn normalize_wwn(wwn: &str) -> String {
+    let wwn = wwn.trim().to_ascii_lowercase();
+    let wwn = wwn.strip_prefix("wwn-").unwrap_or(&wwn);
+    let wwn = ["naa.", "eui.", "t10.", "0x"]
+        .iter()
+        .find_map(|prefix| wwn.strip_prefix(prefix))
+        .unwrap_or(wwn);
+    wwn.to_string()
+}
+
+/// Full paths of the `/dev/disk/<kind>` links resolving to a kernel name
+fn device_links(root: &SystemRoot, kind: &str, name: &str) -> Vec<String> {
+    let mut links: Vec<String> = fs::read_dir(root.dev().join("disk").join(kind))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            fs::canonicalize(entry.path()).map_or(false, |target| file_name(&target) == name)
+        })
+        .map(|entry| format!("/dev/disk/{}/{}", kind, entry.file_name().to_string_lossy()))
+        .collect();
+    links.sort();
+    links
+}
+
+/// Attributes probed directly from sysfs, without lsblk or sudo
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceAttributes {
+    pub rotational: bool,
+    pub removable: bool,
+    pub read_only: bool,
+    pub size_bytes: u64,
+    pub logical_block_size: u32,
+    /// Not every transport reports a model (virtio doesn't)
+    pub model: Option<String>,
+}
+
+/// Probe rotational/removable/ro/size/block size/model of a device by kernel name
+pub fn probe_attributes(name: &str) -> Result<DeviceAttributes, BlockDeviceError> {
+    probe_attributes_in(&SystemRoot::default(), name)
+}
+
+/// Probe device attributes from the sysfs below the given root
+pub fn probe_attributes_in(
+    root: &SystemRoot,
+    name: &str,
+) -> Result<DeviceAttributes, BlockDeviceError> {
+    let sysfs_dir = root.sys_class_block().join(name);
+    if name.is_empty() || !sysfs_dir.exists() {
+        return Err(BlockDeviceError::NotFound(name.to_string()));
+    }
+    SysfsNode::resolve(&sysfs_dir)?.probe()
+}
+
+/// A device's sysfs directory together with the directory of its whole disk
+struct SysfsNode {
+    name: String,
+    dir: PathBuf,
+    disk_dir: PathBuf,
+    parent: Option<String>,
+}
+
+impl SysfsNode {
+    fn resolve(sysfs_dir: &Path) -> Result<SysfsNode, BlockDeviceError> {
+        let dir = fs::canonicalize(sysfs_dir)?;
+        let name = file_name(&dir);
+
+        // Partitions live inside the directory of their whole disk
+        if dir.join("partition").exists() {
+            let disk_dir = dir
+                .parent()
+                .map(Path::to_path_buf)
+                .ok_or_else(|| BlockDeviceError::NotFound(name.clone()))?;
+            Ok(SysfsNode {
+                parent: Some(file_name(&disk_dir)),
+                name,
+                dir,
+                disk_dir,
+            })
+        } else {
+            Ok(SysfsNode {
+                parent: None,
+                name,
+                disk_dir: dir.clone(),
+                dir,
+            })
+        }
+    }
+
+    /// Queue and device attributes belong to the whole disk, ro and size to the node itself
+    fn probe(&self) -> Result<DeviceAttributes, BlockDeviceError> {
+        // The size attribute is always in 512-byte units regardless of sector size
+        let size_sectors: u64 = self.required_number(&self.dir, "size")?;
+
+        Ok(DeviceAttributes {
+            rotational: self.required_flag(&self.disk_dir, "queue/rotational")?,
+            removable: self.required_flag(&self.disk_dir, "removable")?,
+            read_only: self.required_flag(&self.dir, "ro")?,
+            size_bytes: size_sectors * 512,
+            logical_block_size: self.required_number(&self.disk_dir, "queue/logical_block_size")?,
+            model: read_attribute(&self.disk_dir, "device/model"),
+        })
+    }
+
+    fn required(&self, dir: &Path, attribute: &str) -> Result<String, BlockDeviceError> {
+        read_attribute(dir, attribute).ok_or_else(|| BlockDeviceError::MissingAttribute {
+            device: self.name.clone(),
+            attribute: attribute.to_string(),
+        })
+    }
+
+    fn required_number<T: FromStr>(
+        &self,
+        dir: &Path,
+        attribute: &str,
+    ) -> Result<T, BlockDeviceError> {
+        let value = self.required(dir, attribute)?;
+        value
+            .parse()
+            .map_err(|_| invalid_attribute(&self.name, attribute, &value))
+    }
+
+    fn required_flag(&self, dir: &Path, attribute: &str) -> Result<bool, BlockDeviceError> {
+        match self.required(dir, attribute)?.as_str() {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            value => Err(invalid_attribute(&self.name, attribute, value)),
+        }
+    }
+}
+
+/// Read a block device from its sysfs directory
+fn read_from_sysfs(sysfs_dir: &Path, path: &str) -> Result<BlockDevice, BlockDeviceError> {
+    let node = SysfsNode::resolve(sysfs_dir)?;
+    let disk_name = node.parent.clone().unwrap_or_else(|| node.name.clone());
+    let name_transport = classify_disk_name(&disk_name)
+        .ok_or_else(|| BlockDeviceError::UnsupportedDevice(disk_name.clone()))?;
+    let transport = transport_from_sysfs_path(&node.disk_dir).unwrap_or(name_transport);
+
+    let attributes = node.probe()?;
+    let dev = node.required(&node.dir, "dev")?;
+    let device_number = dev
+        .parse()
+        .map_err(|_| invalid_attribute(&node.name, "dev", &dev))?;
+    let physical_sector_size =
+        parse_attribute(&node.disk_dir, &node.name, "queue/physical_block_size")?
+            .unwrap_or(attributes.logical_block_size);
+    let zoned =
+        parse_attribute(&node.disk_dir, &node.name, "queue/zoned")?.unwrap_or(ZonedModel::None);
+    let io_size = |attribute| -> Result<u32, BlockDeviceError> {
+        Ok(parse_attribute(&node.disk_dir, &node.name, attribute)?.unwrap_or(0))
+    };
+
+    Ok(BlockDevice {
+        path: path.to_string(),
+        name: node.name.clone(),
+        parent: node.parent.clone(),
+        device_number,
+        size_bytes: attributes.size_bytes,
+        logical_sector_size: attributes.logical_block_size,
+        physical_sector_size,
+        alignment_offset: parse_attribute(&node.dir, &node.name, "alignment_offset")?.unwrap_or(0),
+        minimum_io_size: io_size("queue/minimum_io_size")?,
+        optimal_io_size: io_size("queue/optimal_io_size")?,
+        model: attributes.model,
+        vendor: read_attribute(&node.disk_dir, "device/vendor"),
+        serial: read_attribute(&node.disk_dir, "device/serial")
+            .or_else(|| read_attribute(&node.disk_dir, "serial")),
+        wwn: read_attribute(&node.disk_dir, "device/wwid")
+            .or_else(|| read_attribute(&node.disk_dir, "wwid")),
+        by_id: Vec::new(),
+        by_path: Vec::new(),
+        multipath_paths: Vec::new(),
+        transport,
+        rotational: attributes.rotational,
+        zoned,
+        removable: attributes.removable,
+        read_only: attributes.read_only,
+    })
+}
+
+/// Read a dm-multipath map or one of its kpartx partitions as a single device
+/// opened through `/dev/mapper`; model, serial and transport come from the first path
+fn read_multipath(
+    root: &SystemRoot,
+    name: &str,
+    node: MultipathNode,
+) -> Result<BlockDevice, BlockDeviceError> {
+    let dir = root.sys_class_block().join(name);
+    let (mut device, parent, paths) = match node {
+        MultipathNode::Map(paths) => (BlockDevice::from_name_in(root, &paths[0])?, None, paths),
+        MultipathNode::Partition(map) => {
+            let device = BlockDevice::from_name_in(root, &map)?;
+            let paths = device.multipath_paths.clone();
+            (device, Some(map), paths)
+        }
+    };
+
+    let missing = |attribute: &str| BlockDeviceError::MissingAttribute {
+        device: name.to_string(),
+        attribute: attribute.to_string(),
+    };
+    let dm_name = read_attribute(&dir, "dm/name").ok_or_else(|| missing("dm/name"))?;
+    let dev = read_attribute(&dir, "dev").ok_or_else(|| missing("dev"))?;
+    let sectors: u64 = parse_attribute(&dir, name, "size")?.ok_or_else(|| missing("size"))?;
+
+    device.path = format!("/dev/mapper/{}", dm_name);
+    device.name = name.to_string();
+    device.parent = parent;
+    device.device_number = dev
+        .parse()
+        .map_err(|_| invalid_attribute(name, "dev", &dev))?;
+    device.size_bytes = sectors * 512;
+    device.read_only = read_attribute(&dir, "ro").as_deref() == Some("1");
+    device.by_id = device_links(root, "by-id", name);
+    device.by_path = Vec::new();
+    device.multipath_paths = paths;
+    Ok(device)
+}
+
+/// Read a trimmed sysfs attribute, treating missing or empty files as absent
+fn read_attribute(dir: &Path, attribute: &str) -> Option<String> {
+    let value = fs::read_to_string(dir.join(attribute)).ok()?;
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// Parse a numeric sysfs attribute if it is present
+fn parse_attribute<T: FromStr>(
+    dir: &Path,
+    device: &str,
+    attribute: &str,
+) -> Result<Option<T>, BlockDeviceError> {
+    match read_attribute(dir, attribute) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| invalid_attribute(device, attribute, &value)),
+        None => Ok(None),
+    }
+}
+
+fn invalid_attribute(device: &str, attribute: &str, value: &str) -> BlockDeviceError {
+    BlockDeviceError::InvalidAttribute {
+        device: device.to_string(),
+        attribute: attribute.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+impl BlockDevice {
+    /// Minimal whole-disk device for unit tests
+    pub(crate) fn fake(name: &str, size_bytes: u64) -> BlockDevice {
+        BlockDevice {
+            path: format!("/dev/{}", name),
+            name: name.to_string(),
+            parent: None,
+            device_number: DeviceNumber { major: 8, minor: 0 },
+            size_bytes,
+            logical_sector_size: 512,
+            physical_sector_size: 512,
+            alignment_offset: 0,
+            minimum_io_size: 0,
+            optimal_io_size: 0,
+            model: None,
+            vendor: None,
+            serial: None,
+            wwn: None,
+            by_id: Vec::new(),
+            by_path: Vec::new(),
+            multipath_paths: Vec::new(),
+            transport: classify_disk_name(name).unwrap_or(Transport::Sata),
+            rotational: true,
+            zoned: ZonedModel::None,
+            removable: false,
+            read_only: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
+
+    #[test]
+    fn test_device_number_parsing() {
+        let number: DeviceNumber = "8:16\n".parse().unwrap();
+        assert_eq!(
+            number,
+            DeviceNumber {
+                major: 8,
+                minor: 16
+            }
+        );
+        assert_eq!(number.to_string(), "8:16");
+        assert!("8".parse::<DeviceNumber>().is_err());
+        assert!("a:b".parse::<DeviceNumber>().is_err());
+    }
+
+    #[test]
+    fn test_from_path_rejects_invalid_paths() {
+        assert!(matches!(
+            BlockDevice::from_path("sda"),
+            Err(BlockDeviceError::InvalidDevicePath(_))
+        ));
+        assert!(matches!(
+            BlockDevice::from_path("/dev/"),
+            Err(BlockDeviceError::InvalidDevicePath(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_disk_and_partition_from_sysfs() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").size_bytes(1 << 30))
+            .disk(
+                FakeDisk::new("sdb")
+                    .size_bytes(7814037168 * 512)
+                    .sector_sizes(512, 4096)
+                    .attribute("queue/optimal_io_size", "786432")
+                    .model("WDC WD40EFRX-68N")
+                    .vendor("ATA     ")
+                    .wwid("naa.50014ee2b5c8e0d1")
+                    .partition(2048 * 512)
+                    .partition_attribute("ro", "1"),
+            )
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        let device = BlockDevice::from_path_in(root, "/dev/sdb").unwrap();
+        assert_eq!(device.path, "/dev/sdb");
+        assert_eq!(device.name, "sdb");
+        assert_eq!(device.parent, None);
+        assert_eq!(
+            device.device_number,
+            DeviceNumber {
+                major: 8,
+                minor: 16
+            }
+        );
+        assert_eq!(device.size_bytes, 7814037168 * 512);
+        assert_eq!(device.logical_sector_size, 512);
+        assert_eq!(device.physical_sector_size, 4096);
+        assert_eq!(device.alignment_offset, 0);
+        assert_eq!(device.minimum_io_size, 0);
+        assert_eq!(device.optimal_io_size, 786432);
+        assert_eq!(device.model.as_deref(), Some("WDC WD40EFRX-68N"));
+        assert_eq!(device.vendor.as_deref(), Some("ATA"));
+        assert_eq!(device.serial, None);
+        assert_eq!(device.wwn.as_deref(), Some("naa.50014ee2b5c8e0d1"));
+        assert_eq!(device.transport, Transport::Sata);
+        assert!(device.rotational);
+        assert_eq!(device.zoned, ZonedModel::None);
+        assert!(!device.removable);
+        assert!(!device.read_only);
+
+        let partition = BlockDevice::from_name_in(root, "sdb1").unwrap();
+        assert!(partition.is_partition());
+        assert_eq!(partition.parent.as_deref(), Some("sdb"));
+        assert_eq!(partition.size_bytes, 2048 * 512);
+        assert_eq!(partition.physical_sector_size, 4096);
+        assert!(partition.rotational);
+        assert!(partition.read_only);
+
+        assert!(matches!(
+            BlockDevice::from_name_in(root, "sdc"),
+            Err(BlockDeviceError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_multipath_devices() {
+        let fixture = FixtureBuilder::new()
+            .disk(
+                FakeDisk::new("sdb")
+                    .size_bytes(1 << 40)
+                    .model("MSA 2050 SAN")
+                    .wwid("naa.600c0ff0001234560000000000000000"),
+            )
+            .disk(FakeDisk::new("sdc").size_bytes(1 << 40))
+            .multipath("dm-0", "mpatha", &["sdb", "sdc"])
+            .dm("dm-1", "mpatha-part1", &["dm-0"])
+            .file(
+                "sys/devices/virtual/block/dm-1/dm/uuid",
+                "part1-mpath-mpatha\n",
+            )
+            .symlink("dev/disk/by-id/dm-name-mpatha", "../../dm-0")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        let map = BlockDevice::from_path_in(root, "/dev/mapper/mpatha").unwrap();
+        assert_eq!(map.name, "dm-0");
+        assert_eq!(map.path, "/dev/mapper/mpatha");
+        assert_eq!(map.multipath_paths, vec!["sdb", "sdc"]);
+        assert_eq!(map.size_bytes, 1 << 40);
+        assert_eq!(map.model.as_deref(), Some("MSA 2050 SAN"));
+        assert_eq!(map.stable_id(), "/dev/disk/by-id/dm-name-mpatha");
+        assert!(!map.is_partition());
+
+        let partition = BlockDevice::from_name_in(root, "dm-1").unwrap();
+        assert_eq!(partition.path, "/dev/mapper/mpatha-part1");
+        assert_eq!(partition.parent.as_deref(), Some("dm-0"));
+        assert_eq!(partition.multipath_paths, vec!["sdb", "sdc"]);
+        assert_eq!(
+            crate::device_discovery::multipath_partition(root, "dm-0", 1).as_deref(),
+            Some("dm-1")
+        );
+    }
+
+    #[test]
+    fn test_stable_identifiers() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda"))
+            .disk(
+                FakeDisk::new("sdb")
+                    .wwid("naa.5000c500a1b2c3d4")
+                    .partition(1 << 30),
+            )
+            .symlink("dev/disk/by-id/ata-ST4000VN008_ZDH1", "../../sdb")
+            .symlink("dev/disk/by-id/wwn-0x5000c500a1b2c3d4", "../../sdb")
+            .symlink("dev/disk/by-id/ata-ST4000VN008_ZDH1-part1", "../../sdb1")
+            .symlink("dev/disk/by-path/pci-0000:00:1f.2-ata-2", "../../sdb")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        let device = BlockDevice::from_name_in(root, "sdb").unwrap();
+        assert_eq!(
+            device.by_id,
+            vec![
+                "/dev/disk/by-id/ata-ST4000VN008_ZDH1",
+                "/dev/disk/by-id/wwn-0x5000c500a1b2c3d4",
+            ]
+        );
+        assert_eq!(
+            device.by_path,
+            vec!["/dev/disk/by-path/pci-0000:00:1f.2-ata-2"]
+        );
+        assert_eq!(device.stable_id(), "/dev/disk/by-id/wwn-0x5000c500a1b2c3d4");
+        assert_eq!(
+            BlockDevice::from_name_in(root, "sdb1").unwrap().stable_id(),
+            "/dev/disk/by-id/ata-ST4000VN008_ZDH1-part1"
+        );
+        assert_eq!(
+            BlockDevice::from_name_in(root, "sda").unwrap().stable_id(),
+            "/dev/sda"
+        );
+
+        for identifier in [
+            "/dev/sdb",
+            "sdb",
+            "/dev/disk/by-id/ata-ST4000VN008_ZDH1",
+            "ata-ST4000VN008_ZDH1",
+            "pci-0000:00:1f.2-ata-2",
+            "wwn-0x5000c500a1b2c3d4",
+            "0x5000c500a1b2c3d4",
+            "naa.5000C500A1B2C3D4",
+        ] {
+            assert_eq!(
+                resolve_device_path_in(root, identifier).unwrap(),
+                "/dev/sdb",
+                "{}",
+                identifier
+            );
+        }
+        assert_eq!(
+            resolve_device_path_in(root, "ata-ST4000VN008_ZDH1-part1").unwrap(),
+            "/dev/sdb1"
+        );
+        assert!(matches!(
+            resolve_device_path_in(root, "/dev/disk/by-id/missing"),
+            Err(BlockDeviceError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_probe_attributes() {
+        let fixture = FixtureBuilder::new()
+            .disk(
+                FakeDisk::new("sdc")
+                    .size_bytes(8 << 40)
+                    .removable(true)
+                    .sector_sizes(4096, 4096)
+                    .model("ST8000NM000A")
+                    .partition(1 << 30),
+            )
+            .disk(FakeDisk::new("vda").rotational(false).read_only(true))
+            .build()
+            .unwrap();
+
+        let attributes = probe_attributes_in(fixture.root(), "sdc").unwrap();
+        assert_eq!(
+            attributes,
+            DeviceAttributes {
+                rotational: true,
+                removable: true,
+                read_only: false,
+                size_bytes: 8 << 40,
+                logical_block_size: 4096,
+                model: Some("ST8000NM000A".to_string()),
+            }
+        );
+
+        let partition = probe_attributes_in(fixture.root(), "sdc1").unwrap();
+        assert_eq!(partition.size_bytes, 1 << 30);
+        assert!(partition.removable);
+
+        let virtio = probe_attributes_in(fixture.root(), "vda").unwrap();
+        assert!(!virtio.rotational);
+        assert!(virtio.read_only);
+        assert_eq!(virtio.model, None);
+    }
+
+    #[test]
+    fn test_missing_attribute_is_named() {
+        for attribute in [
+            "queue/rotational",
+            "removable",
+            "ro",
+            "size",
+            "queue/logical_block_size",
+        ] {
+            let fixture = FixtureBuilder::new()
+                .disk(FakeDisk::new("sdd").without_attribute(attribute))
+                .build()
+                .unwrap();
+
+            match probe_attributes_in(fixture.root(), "sdd") {
+                Err(BlockDeviceError::MissingAttribute {
+                    device,
+                    attribute: missing,
+                }) => {
+                    assert_eq!(device, "sdd");
+                    assert_eq!(missing, attribute);
+                }
+                other => panic!("unexpected result for {}: {:?}", attribute, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_attribute_is_reported() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("vda").attribute("size", "lots"))
+            .build()
+            .unwrap();
+
+        match BlockDevice::from_name_in(fixture.root(), "vda") {
+            Err(BlockDeviceError::InvalidAttribute { attribute, .. }) => {
+                assert_eq!(attribute, "size")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_zoned_model() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").attribute("queue/zoned", "host-managed"))
+            .disk(FakeDisk::new("sdc").attribute("queue/zoned", "host-aware"))
+            .disk(FakeDisk::new("sdd").attribute("queue/zoned", "sideways"))
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        let zoned = |name| BlockDevice::from_name_in(root, name).map(|device| device.zoned);
+        assert_eq!(zoned("sdb").unwrap(), ZonedModel::HostManaged);
+        assert_eq!(zoned("sdc").unwrap(), ZonedModel::HostAware);
+        assert!(zoned("sdc").unwrap().is_zoned());
+        assert!(matches!(
+            zoned("sdd"),
+            Err(BlockDeviceError::InvalidAttribute { .. })
+        ));
+        assert_eq!(ZonedModel::HostManaged.to_string(), "host-managed");
+    }
+}
@@ -0,0 +1,498 @@
+//! Device detach module for auto_mount
+//!
+//! This module takes a provisioned disk out of service so it can be pulled:
+//! it unmounts the disk's partitions, retires their fstab entries, flushes
+//! caches and deletes the SCSI device, reporting every step
+
+use crate::block_device::resolve_device_path_in;
+use crate::device_discovery::partition_names;
+use crate::device_usage::{unescape, UsageTable};
+use crate::mount_manager::create_fstab_backup;
+use crate::system_disk::{describe_roles, find_system_disks_in, resolve_spec, system_roles};
+use crate::{
+    BlockDevice, BlockDeviceError, BusyReason, DeviceUsageError, MountError, SystemDiskError,
+    SystemRole, SystemRoot,
+};
+use std::fmt;
+use std::fs::{self, File};
+use std::path::Path;
+use std::process::Command;
+
+/// Prefix of fstab lines commented out by a detach
+const DETACHED_PREFIX: &str = "# Detached by auto_mount: ";
+
+/// Errors that can occur before a detach starts
+#[derive(Debug, thiserror::Error)]
+pub enum DetachError {
+    #[error("Not a whole disk: {0}")]
+    NotWholeDisk(String),
+    #[error("Block device error: {0}")]
+    BlockDevice(#[from] BlockDeviceError),
+    #[error("Device usage detection failed: {0}")]
+    Usage(#[from] DeviceUsageError),
+    #[error("System disk detection failed: {0}")]
+    SystemDiskDetection(#[from] SystemDiskError),
+    #[error("IO error: {0}")]
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for DetachError {
+    fn from(error: std::io::Error) -> Self {
+        DetachError::IoError(error)
+    }
+}
+
+/// What to do with the fstab entries of a detached disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FstabAction {
+    /// Keep the lines, commented out, so the disk is easy to put back
+    CommentOut,
+    Remove,
+}
+
+/// Detach configuration
+#[derive(Debug, Clone)]
+pub struct DetachConfig {
+    pub fstab_action: FstabAction,
+    pub backup_fstab: bool,
+    /// Only fstab entries mounted below this directory are managed and retired;
+    /// a disk with other fstab entries is refused
+    pub mount_base_path: String,
+    /// Where sysfs, procfs, /dev and /etc are read from
+    pub root: SystemRoot,
+}
+
+impl Default for DetachConfig {
+    fn default() -> Self {
+        Self {
+            fstab_action: FstabAction::CommentOut,
+            backup_fstab: true,
+            mount_base_path: "/mnt".to_string(),
+            root: SystemRoot::default(),
+        }
+    }
+}
+
+/// One step of a detach, in the order they run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DetachStep {
+    /// Make sure no system mount, swap, LVM, dm-crypt or md RAID depends on the disk
+    CheckHolders,
+    Unmount {
+        mount_point: String,
+    },
+    /// Retire the fstab entries of the disk's partitions
+    UpdateFstab {
+        entries: usize,
+    },
+    FlushCaches,
+    /// Tear down the dm-multipath map before its paths go away
+    FlushMultipath {
+        map: String,
+    },
+    /// Write `1` to `/sys/block/<name>/device/delete`
+    DeleteDevice {
+        name: String,
+    },
+}
+
+impl fmt::Display for DetachStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DetachStep::CheckHolders => write!(f, "check holders"),
+            DetachStep::Unmount { mount_point } => write!(f, "unmount {}", mount_point),
+            DetachStep::UpdateFstab { entries } => write!(f, "update {} fstab entries", entries),
+            DetachStep::FlushCaches => write!(f, "flush caches"),
+            DetachStep::FlushMultipath { map } => write!(f, "flush multipath map {}", map),
+            DetachStep::DeleteDevice { name } => write!(f, "delete SCSI device {}", name),
+        }
+    }
+}
+
+/// Outcome of one detach step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetachStepResult {
+    pub step: DetachStep,
+    pub success: bool,
+    pub error_message: Option<String>,
+}
+
+/// Result of a detach; steps stop at the first failure
+#[derive(Debug, Clone)]
+pub struct DetachResult {
+    pub device: BlockDevice,
+    pub steps: Vec<DetachStepResult>,
+    /// Whether every step succeeded and the disk can be pulled
+    pub detached: bool,
+}
+
+/// Detach a disk given by any identifier `resolve_device_path` accepts
+pub fn detach_device(device: &str) -> Result<DetachResult, DetachError> {
+    detach_device_with_config(device, &DetachConfig::default())
+}
+
+/// Detach a disk with custom configuration
+pub fn detach_device_with_config(
+    device: &str,
+    config: &DetachConfig,
+) -> Result<DetachResult, DetachError> {
+    let root = &config.root;
+    let device = BlockDevice::from_path_in(root, &resolve_device_path_in(root, device)?)?;
+    if device.is_partition() {
+        return Err(DetachError::NotWholeDisk(device.path));
+    }
+
+    let mut result = DetachResult {
+        device,
+        steps: Vec::new(),
+        detached: false,
+    };
+
+    // Step 1: Nothing but plain mounts of the disk itself may use it; managed
+    // fstab entries don't count, retiring them is part of the detach
+    let reasons = UsageTable::read(root)?.busy_reasons(root, &result.device.name);
+    let mut system_roles = system_roles(root, &find_system_disks_in(root)?, &result.device.name);
+    system_roles.retain(|role| match role {
+        SystemRole::Fstab(mount_point) => !is_managed(config, mount_point),
+        _ => true,
+    });
+    let check = if !system_roles.is_empty() {
+        Err(format!("system disk: {}", describe_roles(&system_roles)))
+    } else {
+        blocking_reasons(&reasons)
+    };
+    if !record(&mut result, DetachStep::CheckHolders, check) {
+        return Ok(result);
+    }
+
+    // Step 2: Unmount every partition
+    let mut mount_points: Vec<String> = Vec::new();
+    for reason in &reasons {
+        if let BusyReason::Mounted { mount_point, .. } = reason {
+            if !mount_points.contains(mount_point) {
+                mount_points.push(mount_point.clone());
+            }
+        }
+    }
+    // Nested mount points go first
+    mount_points.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    for mount_point in &mount_points {
+        let step = DetachStep::Unmount {
+            mount_point: mount_point.clone(),
+        };
+        if !record(&mut result, step, run("umount", &[mount_point])) {
+            return Ok(result);
+        }
+    }
+
+    // Step 3: Retire fstab entries so the next boot doesn't wait for the disk
+    let names = device_names(root, &result.device);
+    let fstab = update_fstab(config, &names, &mount_points);
+    let entries = *fstab.as_ref().unwrap_or(&0);
+    if !record(&mut result, DetachStep::UpdateFstab { entries }, fstab) {
+        return Ok(result);
+    }
+
+    // Step 4: Flush dirty pages and the disk's write cache
+    let flush = File::open(root.dev_node(&result.device.name))
+        .and_then(|node| node.sync_all())
+        .map_err(|error| error.to_string());
+    if !record(&mut result, DetachStep::FlushCaches, flush) {
+        return Ok(result);
+    }
+
+    // Step 5: Remove the device from the SCSI layer
+    let scsi_devices = if result.device.multipath_paths.is_empty() {
+        vec![result.device.name.clone()]
+    } else {
+        let map = result
+            .device
+            .path
+            .trim_start_matches("/dev/mapper/")
+            .to_string();
+        let flush = run("multipath", &["-f", &map]);
+        if !record(&mut result, DetachStep::FlushMultipath { map }, flush) {
+            return Ok(result);
+        }
+        result.device.multipath_paths.clone()
+    };
+    for name in scsi_devices {
+        let delete = fs::write(root.sys_block().join(&name).join("device/delete"), "1")
+            .map_err(|error| error.to_string());
+        if !record(&mut result, DetachStep::DeleteDevice { name }, delete) {
+            return Ok(result);
+        }
+    }
+
+    result.detached = true;
+    Ok(result)
+}
+
+/// Append a step to the result, returning whether it succeeded
+fn record<T>(result: &mut DetachResult, step: DetachStep, outcome: Result<T, String>) -> bool {
+    let success = outcome.is_ok();
+    result.steps.push(DetachStepResult {
+        step,
+        success,
+        error_message: outcome.err(),
+    });
+    success
+}
+
+/// Busy reasons a detach can't resolve by unmounting
+fn blocking_reasons(reasons: &[BusyReason]) -> Result<(), String> {
+    let blocking: Vec<String> = reasons
+        .iter()
+        .filter(|reason| !matches!(reason, BusyReason::Mounted { .. }))
+        .map(ToString::to_string)
+        .collect();
+    if blocking.is_empty() {
+        Ok(())
+    } else {
+        Err(blocking.join(", "))
+    }
+}
+
+/// Kernel names of the disk and its partitions
+fn device_names(root: &SystemRoot, device: &BlockDevice) -> Vec<String> {
+    let mut names = vec![device.name.clone()];
+    if device.multipath_paths.is_empty() {
+        names.extend(partition_names(root, &device.name));
+    } else {
+        // kpartx partitions hang off the map as holders
+        let holders = fs::read_dir(root.sys_class_block().join(&device.name).join("holders"));
+        names.extend(
+            holders
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().to_string()),
+        );
+    }
+    names
+}
+
+/// Comment out or remove the managed fstab entries whose device resolves to one
+/// of `names` or which mount one of `mount_points`, returning how many changed
+fn update_fstab(
+    config: &DetachConfig,
+    names: &[String],
+    mount_points: &[String],
+) -> Result<usize, String> {
+    let fstab_path = config.root.fstab();
+    let fstab = match fs::read_to_string(&fstab_path) {
+        Ok(fstab) => fstab,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(error) => return Err(error.to_string()),
+    };
+
+    let mut changed = 0;
+    let mut lines = Vec::new();
+    for line in fstab.lines() {
+        if !is_entry_for(config, line, names, mount_points) {
+            lines.push(line.to_string());
+            continue;
+        }
+        changed += 1;
+        if config.fstab_action == FstabAction::CommentOut {
+            lines.push(format!("{}{}", DETACHED_PREFIX, line));
+        }
+    }
+    if changed == 0 {
+        return Ok(0);
+    }
+
+    if config.backup_fstab {
+        create_fstab_backup(&fstab_path).map_err(|error: MountError| error.to_string())?;
+    }
+    write_atomically(&fstab_path, &lines).map_err(|error| error.to_string())?;
+    Ok(changed)
+}
+
+fn is_entry_for(
+    config: &DetachConfig,
+    line: &str,
+    names: &[String],
+    mount_points: &[String],
+) -> bool {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return false;
+    }
+    let mut fields = trimmed.split_whitespace().map(unescape);
+    let (spec, mount_point) = match (fields.next(), fields.next()) {
+        (Some(spec), Some(mount_point)) => (spec, mount_point),
+        _ => return false,
+    };
+    is_managed(config, &mount_point)
+        && (mount_points.contains(&mount_point)
+            || resolve_spec(&config.root, &spec).map_or(false, |name| names.contains(&name)))
+}
+
+/// Whether a mount point lies below the managed mount base
+fn is_managed(config: &DetachConfig, mount_point: &str) -> bool {
+    let base = config.mount_base_path.trim_end_matches('/');
+    mount_point
+        .strip_prefix(base)
+        .map_or(false, |rest| rest.starts_with('/'))
+}
+
+fn write_atomically(path: &Path, lines: &[String]) -> std::io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    fs::write(&temp_path, contents)?;
+    File::open(&temp_path)?.sync_all()?;
+    fs::rename(&temp_path, path)
+}
+
+/// Run a command with sudo, turning a failure into its stderr
+fn run(program: &str, args: &[&str]) -> Result<(), String> {
+    let output = Command::new("sudo")
+        .arg(program)
+        .args(args)
+        .output()
+        .map_err(|error| error.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
+
+    fn config(root: &SystemRoot) -> DetachConfig {
+        DetachConfig {
+            backup_fstab: false,
+            root: root.clone(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_detach_unmounted_disk() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").partition(1 << 30))
+            .disk(FakeDisk::new("sdb").partition(1 << 30))
+            .mount("sda1", "/", "ext4")
+            .symlink("dev/disk/by-uuid/0b6c1a52", "../../sdb1")
+            .file("sys/block/sdb/device/delete", "")
+            .file(
+                "etc/fstab",
+                "UUID=4f2e-root  /  ext4  defaults  0 1\n\
+                 UUID=0b6c1a52  /mnt/sdb1  ext4    rw,acl    0   0\n",
+            )
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        let result = detach_device_with_config("/dev/sdb", &config(root)).unwrap();
+        assert!(result.detached);
+        let steps: Vec<String> = result.steps.iter().map(|s| s.step.to_string()).collect();
+        assert_eq!(
+            steps,
+            vec![
+                "check holders",
+                "update 1 fstab entries",
+                "flush caches",
+                "delete SCSI device sdb",
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(root.fstab()).unwrap(),
+            "UUID=4f2e-root  /  ext4  defaults  0 1\n\
+             # Detached by auto_mount: UUID=0b6c1a52  /mnt/sdb1  ext4    rw,acl    0   0\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.sys_block().join("sdb/device/delete")).unwrap(),
+            "1"
+        );
+    }
+
+    #[test]
+    fn test_detach_refuses_held_and_system_disks() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").partition(1 << 30))
+            .disk(FakeDisk::new("sdb").partition(1 << 30))
+            .dm("dm-0", "vg0-data", &["sdb1"])
+            .mount("sda1", "/", "ext4")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        let result = detach_device_with_config("sdb", &config(root)).unwrap();
+        assert!(!result.detached);
+        assert_eq!(result.steps.len(), 1);
+        assert_eq!(
+            result.steps[0].error_message.as_deref(),
+            Some("sdb1 is held by device-mapper target vg0-data")
+        );
+
+        let result = detach_device_with_config("sda", &config(root)).unwrap();
+        assert_eq!(
+            result.steps[0].error_message.as_deref(),
+            Some("system disk: backs /")
+        );
+        assert!(matches!(
+            detach_device_with_config("sdb1", &config(root)),
+            Err(DetachError::NotWholeDisk(_))
+        ));
+    }
+
+    #[test]
+    fn test_remove_fstab_entries() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdc").partition(1 << 30).partition(1 << 30))
+            .file(
+                "etc/fstab",
+                "# static file system information\n\
+                 /dev/sdc1  /mnt/data  xfs  defaults  0 0\n\
+                 /dev/sdc2  /mnt/old\\040logs  xfs  defaults  0 0\n\
+                 /dev/sdc1  /srv/data  none  bind  0 0\n\
+                 tmpfs  /tmp  tmpfs  defaults  0 0\n",
+            )
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let config = DetachConfig {
+            fstab_action: FstabAction::Remove,
+            ..config(root)
+        };
+        let names = vec!["sdc".to_string(), "sdc1".to_string()];
+
+        assert_eq!(
+            update_fstab(&config, &names, &["/mnt/old logs".to_string()]),
+            Ok(2)
+        );
+        assert_eq!(
+            fs::read_to_string(root.fstab()).unwrap(),
+            "# static file system information\n\
+             /dev/sdc1  /srv/data  none  bind  0 0\n\
+             tmpfs  /tmp  tmpfs  defaults  0 0\n"
+        );
+    }
+
+    #[test]
+    fn test_detach_refuses_unmanaged_fstab_entries() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdc").partition(1 << 30))
+            .file("etc/fstab", "/dev/sdc1  /data  xfs  defaults  0 0\n")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        let result = detach_device_with_config("sdc", &config(root)).unwrap();
+        assert!(!result.detached);
+        assert_eq!(
+            result.steps[0].error_message.as_deref(),
+            Some("system disk: backs fstab entry /data")
+        );
+        assert!(!is_managed(&config(root), "/mnt"));
+        assert!(!is_managed(&config(root), "/mntx/sdc1"));
+        assert!(is_managed(&config(root), "/mnt/sdc1"));
+    }
+}
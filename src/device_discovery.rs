@@ -1,11 +1,14 @@
 //! Device discovery module for auto_mount
 //!
-//! This module handles the discovery of connected SATA devices with proper error handling
+//! This module handles the discovery of connected block devices (SATA, NVMe, virtio,
+//! Xen and MMC) with proper error handling
 
+use crate::{BlockDevice, BlockDeviceError, SystemRoot};
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Component, Path};
 use std::process::{Command, Output};
+use strum::{Display, EnumIter, EnumString};
 
 /// Errors that can occur during device discovery
 #[derive(Debug, thiserror::Error)]
@@ -22,6 +25,8 @@ pub enum DeviceDiscoveryError {
     DevDirectoryNotFound,
     #[error("No SATA devices found")]
     NoDevicesFound,
+    #[error("Block device error: {0}")]
+    BlockDevice(#[from] BlockDeviceError),
 }
 
 impl From<io::Error> for DeviceDiscoveryError {
@@ -30,42 +35,122 @@ impl From<io::Error> for DeviceDiscoveryError {
     }
 }
 
+/// Transport a block device is attached through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumString, Display, EnumIter)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum Transport {
+    /// Native SATA/ATA ports; also assumed for `sdX` nodes whose sysfs path is inconclusive
+    Sata,
+    /// NVMe namespaces (`nvmeXnY`)
+    Nvme,
+    /// virtio-blk disks (`vdX`) and virtio-scsi disks
+    Virtio,
+    /// Xen virtual block devices (`xvdX`)
+    Xen,
+    /// SD/eMMC cards (`mmcblkN`)
+    Mmc,
+    /// Disks behind USB bridges and docks
+    Usb,
+    /// SAS HBAs and expanders
+    Sas,
+    /// iSCSI sessions
+    Iscsi,
+}
+
 /// Find connected SATA devices with robust error handling
 pub fn find_connected_satas() -> Result<Vec<String>, DeviceDiscoveryError> {
+    let root = SystemRoot::default();
+
+    // Try primary method first (using /sys/block)
+    match find_sata_devices_in(&root) {
+        Ok(devices) if !devices.is_empty() => {
+            Ok(devices.into_iter().map(|device| device.path).collect())
+        }
+        Err(DeviceDiscoveryError::DevDirectoryNotFound) => {
+            Err(DeviceDiscoveryError::DevDirectoryNotFound)
+        }
+        _ => find_devices_via_find_command(&root),
+    }
+}
+
+/// Find connected SATA devices with their sysfs attributes
+pub fn find_sata_devices() -> Result<Vec<BlockDevice>, DeviceDiscoveryError> {
+    find_sata_devices_in(&SystemRoot::default())
+}
+
+/// Find connected SCSI disk nodes (`sdX`) below the given system root, whatever
+/// they are attached through; check [`BlockDevice::transport`] to tell a native
+/// SATA port from a USB dock, SAS HBA or iSCSI session. Multipath LUNs are
+/// returned once, as their `/dev/mapper` device.
+pub fn find_sata_devices_in(root: &SystemRoot) -> Result<Vec<BlockDevice>, DeviceDiscoveryError> {
+    let is_scsi_disk = |name: &String| classify_disk_name(name) == Some(Transport::Sata);
+    let devices: Vec<BlockDevice> = find_block_devices_in(root)?
+        .into_iter()
+        .filter(|device| {
+            is_scsi_disk(&device.name) || device.multipath_paths.iter().any(is_scsi_disk)
+        })
+        .collect();
+
+    if devices.is_empty() {
+        Err(DeviceDiscoveryError::NoDevicesFound)
+    } else {
+        Ok(devices)
+    }
+}
+
+/// Find every whole-disk block device in /sys/block, tagged with its transport;
+/// the paths of a dm-multipath map are replaced by the map itself
+pub fn find_block_devices() -> Result<Vec<BlockDevice>, DeviceDiscoveryError> {
+    find_block_devices_in(&SystemRoot::default())
+}
+
+/// Find every whole-disk block device below the given system root
+pub fn find_block_devices_in(root: &SystemRoot) -> Result<Vec<BlockDevice>, DeviceDiscoveryError> {
     // Check if /dev directory exists
-    if !Path::new("/dev").exists() {
+    if !root.dev().exists() {
         return Err(DeviceDiscoveryError::DevDirectoryNotFound);
     }
 
-    // Try primary method first (using /sys/block)
-    match find_devices_via_sysblock() {
-        Ok(devices) if !devices.is_empty() => Ok(devices),
-        Ok(_) => find_devices_via_find_command(),
-        Err(_) => find_devices_via_find_command(),
-    }
+    find_devices_via_sysblock(root)
 }
 
-/// Find SATA devices using /sys/block directory (preferred method)
-fn find_devices_via_sysblock() -> Result<Vec<String>, DeviceDiscoveryError> {
+/// Find block devices using /sys/block directory (preferred method)
+fn find_devices_via_sysblock(root: &SystemRoot) -> Result<Vec<BlockDevice>, DeviceDiscoveryError> {
     let mut devices = Vec::new();
+    let mut multipath_members = Vec::new();
 
-    let entries = fs::read_dir("/sys/block")?;
+    let entries = fs::read_dir(root.sys_block())?;
 
     for entry in entries {
         let entry = entry?;
         let name = entry.file_name();
         let name_str = name.to_string_lossy();
 
-        // Check for SATA devices (sd + single letter: sda, sdb, etc.)
-        if name_str.starts_with("sd") && name_str.len() == 3 {
-            let device_path = format!("/dev/{}", name_str);
-            if Path::new(&device_path).exists() {
-                devices.push(device_path);
+        if node_kind(root, &name_str) == Some(NodeKind::Partition) {
+            continue;
+        }
+
+        let is_disk = if let Some(MultipathNode::Map(paths)) = multipath_node(root, &name_str) {
+            multipath_members.extend(paths);
+            true
+        } else {
+            classify_disk_name(&name_str).is_some() && root.dev_node(&name_str).exists()
+        };
+
+        // A disk whose sysfs attributes can't be read (e.g. one going away
+        // mid-scan) is skipped rather than hiding every other disk
+        if is_disk {
+            if let Ok(device) = BlockDevice::from_name_in(root, &name_str) {
+                devices.push(device);
             }
         }
     }
 
-    devices.sort();
+    // Each path of a multipath LUN is the same disk; only the map may be touched
+    devices.retain(|device| !multipath_members.contains(&device.name));
+
+    // Kernel naming order: sdz comes before sdaa
+    devices.sort_by(|a, b| (a.name.len(), &a.name).cmp(&(b.name.len(), &b.name)));
 
     if devices.is_empty() {
         Err(DeviceDiscoveryError::NoDevicesFound)
@@ -74,35 +159,218 @@ fn find_devices_via_sysblock() -> Result<Vec<String>, DeviceDiscoveryError> {
     }
 }
 
+/// Classify a /sys/block entry name, returning `None` for non-disk devices
+/// (loop, ram, dm, NVMe multipath controller paths, eMMC boot areas, ...)
+pub(crate) fn classify_disk_name(name: &str) -> Option<Transport> {
+    if let Some(rest) = name.strip_prefix("nvme") {
+        // nvme<ctrl>n<ns>; nvme<ctrl>c<path>n<ns> are hidden multipath paths
+        let (controller, namespace) = rest.split_once('n')?;
+        return (is_digits(controller) && is_digits(namespace)).then_some(Transport::Nvme);
+    }
+
+    if let Some(rest) = name.strip_prefix("mmcblk") {
+        return is_digits(rest).then_some(Transport::Mmc);
+    }
+
+    if let Some(rest) = name.strip_prefix("xvd") {
+        return is_letters(rest).then_some(Transport::Xen);
+    }
+
+    if let Some(rest) = name.strip_prefix("vd") {
+        return is_letters(rest).then_some(Transport::Virtio);
+    }
+
+    // SATA devices (sda .. sdz, sdaa .. sdzz, sdaaa, ...)
+    if let Some(rest) = name.strip_prefix("sd") {
+        return is_letters(rest).then_some(Transport::Sata);
+    }
+
+    None
+}
+
+/// Tell the transport from the canonical sysfs path of a whole disk, e.g.
+/// `/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/.../block/sdb`.
+/// Bridges are checked first: a SATA disk in a USB dock is a USB disk.
+pub(crate) fn transport_from_sysfs_path(path: &Path) -> Option<Transport> {
+    let components: Vec<&str> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+    let has = |matches: &dyn Fn(&str) -> bool| components.iter().any(|name| matches(name));
+
+    if has(&|name| numbered(name, "usb")) {
+        Some(Transport::Usb)
+    } else if has(&|name| numbered(name, "session")) {
+        Some(Transport::Iscsi)
+    } else if has(&|name| name.starts_with("end_device-") || name.starts_with("expander-")) {
+        Some(Transport::Sas)
+    } else if has(&|name| numbered(name, "ata")) {
+        Some(Transport::Sata)
+    } else if has(&|name| numbered(name, "nvme") || name.starts_with("nvme-subsys")) {
+        Some(Transport::Nvme)
+    } else if has(&|name| numbered(name, "virtio")) {
+        Some(Transport::Virtio)
+    } else if has(&|name| name.starts_with("vbd-")) {
+        Some(Transport::Xen)
+    } else if has(&|name| name == "mmc_host") {
+        Some(Transport::Mmc)
+    } else {
+        None
+    }
+}
+
+/// `prefix` followed by digits only (`usb2`, `ata1`, `session3`)
+fn numbered(name: &str, prefix: &str) -> bool {
+    name.strip_prefix(prefix).map_or(false, is_digits)
+}
+
+/// Role of a device-mapper node in a dm-multipath stack, told by its `dm/uuid`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum MultipathNode {
+    /// The map itself (`mpath-<wwid>`) with the kernel names of its paths
+    Map(Vec<String>),
+    /// A kpartx partition (`part<N>-mpath-<wwid>`) of the given map
+    Partition(String),
+}
+
+/// Classify a device-mapper node as part of a multipath stack, returning
+/// `None` for any other device (LVM, dm-crypt, plain disks)
+pub(crate) fn multipath_node(root: &SystemRoot, name: &str) -> Option<MultipathNode> {
+    let dir = root.sys_class_block().join(name);
+    let uuid = fs::read_to_string(dir.join("dm/uuid")).ok()?;
+    let uuid = uuid.trim();
+
+    let mut slaves: Vec<String> = fs::read_dir(dir.join("slaves"))
+        .ok()?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    slaves.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
+
+    if uuid.starts_with("mpath-") && !slaves.is_empty() {
+        Some(MultipathNode::Map(slaves))
+    } else if uuid.starts_with("part") && uuid.contains("-mpath-") && slaves.len() == 1 {
+        slaves.pop().map(MultipathNode::Partition)
+    } else {
+        None
+    }
+}
+
+/// Kernel name of kpartx partition `number` of a multipath map, found among
+/// the map's holders by its `part<N>-mpath-` uuid
+pub(crate) fn multipath_partition(root: &SystemRoot, map: &str, number: u32) -> Option<String> {
+    let prefix = format!("part{}-", number);
+    fs::read_dir(root.sys_class_block().join(map).join("holders"))
+        .ok()?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .find(|holder| {
+            let uuid = fs::read_to_string(root.sys_class_block().join(holder).join("dm/uuid"));
+            multipath_node(root, holder) == Some(MultipathNode::Partition(map.to_string()))
+                && uuid.map_or(false, |uuid| uuid.starts_with(&prefix))
+        })
+}
+
+/// Kind of a block device node according to sysfs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NodeKind {
+    WholeDisk,
+    Partition,
+}
+
+/// Tell whole disks from partitions by sysfs structure: only partitions carry a
+/// `partition` attribute. Returns `None` if sysfs doesn't know the device.
+pub(crate) fn node_kind(root: &SystemRoot, name: &str) -> Option<NodeKind> {
+    let dir = root.sys_class_block().join(name);
+    if name.is_empty() || name.contains('/') || !dir.exists() {
+        None
+    } else if dir.join("partition").exists() {
+        Some(NodeKind::Partition)
+    } else {
+        Some(NodeKind::WholeDisk)
+    }
+}
+
+/// List the kernel names of the partitions of a whole disk
+pub(crate) fn partition_names(root: &SystemRoot, disk: &str) -> Vec<String> {
+    let mut partitions: Vec<String> = fs::read_dir(root.sys_class_block().join(disk))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().join("partition").exists())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+
+    partitions.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
+    partitions
+}
+
+/// Kernel name of a disk's partition by number, read from
+/// `/sys/block/<disk>/<part>/partition` instead of guessed from the disk name
+pub(crate) fn partition_by_number(root: &SystemRoot, disk: &str, number: u32) -> Option<String> {
+    fs::read_dir(root.sys_block().join(disk))
+        .ok()?
+        .flatten()
+        .find(|entry| {
+            fs::read_to_string(entry.path().join("partition"))
+                .map_or(false, |value| value.trim() == number.to_string())
+        })
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+}
+
+/// Whether a kernel name refers to a whole disk, asking sysfs first and falling
+/// back to the kernel naming scheme when sysfs doesn't know the device
+pub(crate) fn is_whole_disk(root: &SystemRoot, name: &str) -> bool {
+    match node_kind(root, name) {
+        Some(kind) => kind == NodeKind::WholeDisk,
+        None => classify_disk_name(name).is_some(),
+    }
+}
+
+fn is_digits(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn is_letters(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_lowercase())
+}
+
 /// Find SATA devices using find command (fallback method)
-fn find_devices_via_find_command() -> Result<Vec<String>, DeviceDiscoveryError> {
+fn find_devices_via_find_command(root: &SystemRoot) -> Result<Vec<String>, DeviceDiscoveryError> {
     // Try without sudo first
-    match try_find_without_sudo() {
+    match try_find_without_sudo(root) {
         Ok(devices) => Ok(devices),
-        Err(_) => try_find_with_sudo(),
+        Err(_) => try_find_with_sudo(root),
     }
 }
 
 /// Try to find devices without sudo privileges
-fn try_find_without_sudo() -> Result<Vec<String>, DeviceDiscoveryError> {
+fn try_find_without_sudo(root: &SystemRoot) -> Result<Vec<String>, DeviceDiscoveryError> {
     let output = Command::new("find")
-        .args(["/dev", "-name", "sd?"])
+        .args(["/dev", "-maxdepth", "1", "-name", "sd*"])
         .output()?;
 
-    process_find_output(output)
+    process_find_output(root, output)
 }
 
 /// Try to find devices with sudo privileges
-fn try_find_with_sudo() -> Result<Vec<String>, DeviceDiscoveryError> {
+fn try_find_with_sudo(root: &SystemRoot) -> Result<Vec<String>, DeviceDiscoveryError> {
     let output = Command::new("sudo")
-        .args(["find", "/dev", "-name", "sd?"])
+        .args(["find", "/dev", "-maxdepth", "1", "-name", "sd*"])
         .output()?;
 
-    process_find_output(output)
+    process_find_output(root, output)
 }
 
-/// Process the output from find command
-fn process_find_output(output: Output) -> Result<Vec<String>, DeviceDiscoveryError> {
+/// Process the output from find command, telling partitions apart through the
+/// sysfs below the given root
+fn process_find_output(
+    root: &SystemRoot,
+    output: Output,
+) -> Result<Vec<String>, DeviceDiscoveryError> {
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(DeviceDiscoveryError::CommandFailed(stderr.to_string()));
@@ -110,13 +378,18 @@ fn process_find_output(output: Output) -> Result<Vec<String>, DeviceDiscoveryErr
 
     let stdout = String::from_utf8(output.stdout).map_err(|_| DeviceDiscoveryError::InvalidUtf8)?;
 
+    // `sd*` also matches partitions, keep only whole disks
     let mut devices: Vec<String> = stdout
         .lines()
         .filter(|line| !line.is_empty())
+        .filter(|line| {
+            let name = line.rsplit('/').next().unwrap_or_default();
+            is_whole_disk(root, name)
+        })
         .map(|line| line.to_string())
         .collect();
 
-    devices.sort();
+    devices.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
 
     if devices.is_empty() {
         Err(DeviceDiscoveryError::NoDevicesFound)
@@ -128,22 +401,153 @@ fn process_find_output(output: Output) -> Result<Vec<String>, DeviceDiscoveryErr
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder, SystemFixture};
 
     #[test]
     fn test_device_name_validation() {
         // Unit test for device name validation logic
-        let valid_names = vec!["sda", "sdb", "sdz"];
-        let invalid_names = vec!["sd", "sda1", "nvme0n1", "loop0", "sdaa"];
+        let valid_names = vec!["sda", "sdb", "sdz", "sdaa", "sdaj", "sdzz"];
+        let invalid_names = vec!["sd", "sda1", "sdaa1", "loop0", "sdA"];
 
         for name in valid_names {
-            assert!(name.starts_with("sd") && name.len() == 3);
+            assert_eq!(classify_disk_name(name), Some(Transport::Sata));
         }
 
         for name in invalid_names {
-            assert!(!(name.starts_with("sd") && name.len() == 3));
+            assert_eq!(classify_disk_name(name), None);
+        }
+    }
+
+    #[test]
+    fn test_classify_non_sata_disks() {
+        assert_eq!(classify_disk_name("nvme0n1"), Some(Transport::Nvme));
+        assert_eq!(classify_disk_name("nvme12n3"), Some(Transport::Nvme));
+        assert_eq!(classify_disk_name("vda"), Some(Transport::Virtio));
+        assert_eq!(classify_disk_name("xvdb"), Some(Transport::Xen));
+        assert_eq!(classify_disk_name("mmcblk0"), Some(Transport::Mmc));
+    }
+
+    #[test]
+    fn test_classify_rejects_non_disks() {
+        for name in [
+            "nvme0",
+            "nvme0n1p1",
+            "nvme0c0n1",
+            "vda1",
+            "xvdb2",
+            "mmcblk0p1",
+            "mmcblk0boot0",
+            "mmcblk0rpmb",
+            "loop0",
+            "ram0",
+            "dm-0",
+            "md0",
+            "sr0",
+        ] {
+            assert_eq!(
+                classify_disk_name(name),
+                None,
+                "{} should be rejected",
+                name
+            );
         }
     }
 
+    #[test]
+    fn test_transport_display() {
+        assert_eq!(Transport::Sata.to_string(), "sata");
+        assert_eq!("NVMe".parse::<Transport>().unwrap(), Transport::Nvme);
+    }
+
+    #[test]
+    fn test_transport_from_sysfs_path() {
+        let cases = [
+            (
+                "/sys/devices/pci0000:00/0000:00:1f.2/ata3/host2/target2:0:0/2:0:0:0/block/sdc",
+                Some(Transport::Sata),
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb",
+                Some(Transport::Usb),
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:01.0/0000:01:00.0/host0/port-0:0/expander-0:0/port-0:0:4/end_device-0:0:4/target0:0:4/0:0:4:0/block/sdd",
+                Some(Transport::Sas),
+            ),
+            (
+                "/sys/devices/platform/host3/session1/target3:0:0/3:0:0:0/block/sde",
+                Some(Transport::Iscsi),
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:1d.0/0000:3d:00.0/nvme/nvme0/nvme0n1",
+                Some(Transport::Nvme),
+            ),
+            (
+                "/sys/devices/pci0000:00/0000:00:05.0/virtio2/host0/target0:0:1/0:0:1:0/block/sdf",
+                Some(Transport::Virtio),
+            ),
+            ("/sys/devices/vbd-51712/block/xvda", Some(Transport::Xen)),
+            (
+                "/sys/devices/platform/soc/mmc0/mmc_host/mmc0/mmc0:0001/block/mmcblk0",
+                Some(Transport::Mmc),
+            ),
+            ("/sys/devices/virtual/block/loop0", None),
+        ];
+        for (path, transport) in cases {
+            assert_eq!(
+                transport_from_sysfs_path(Path::new(path)),
+                transport,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_usb_disks_are_tagged() {
+        let fixture =
+            FixtureBuilder::new()
+                .disk(FakeDisk::new("sda"))
+                .disk(FakeDisk::new("sdb").device_path(
+                    "pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0",
+                ))
+                .build()
+                .unwrap();
+
+        let devices = find_sata_devices_in(fixture.root()).unwrap();
+        let transports: Vec<Transport> = devices.iter().map(|device| device.transport).collect();
+        assert_eq!(transports, vec![Transport::Sata, Transport::Usb]);
+    }
+
+    #[test]
+    fn test_multipath_paths_collapse_into_map() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda"))
+            .disk(FakeDisk::new("sdb").size_bytes(4 << 40))
+            .disk(FakeDisk::new("sdc").size_bytes(4 << 40))
+            .multipath("dm-0", "mpatha", &["sdb", "sdc"])
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        let devices = find_sata_devices_in(root).unwrap();
+        let paths: Vec<&str> = devices.iter().map(|device| device.path.as_str()).collect();
+        assert_eq!(paths, vec!["/dev/sda", "/dev/mapper/mpatha"]);
+        assert_eq!(devices[1].name, "dm-0");
+        assert_eq!(devices[1].multipath_paths, vec!["sdb", "sdc"]);
+        assert_eq!(devices[1].size_bytes, 4 << 40);
+        assert_eq!(devices[1].transport, Transport::Sata);
+
+        assert_eq!(
+            multipath_node(root, "dm-0"),
+            Some(MultipathNode::Map(vec![
+                "sdb".to_string(),
+                "sdc".to_string()
+            ]))
+        );
+        assert_eq!(multipath_node(root, "sdb"), None);
+    }
+
     #[test]
     fn test_process_find_output_success() {
         use std::process::Command;
@@ -155,7 +559,8 @@ mod tests {
             .unwrap();
 
         if output.status.success() {
-            let result = process_find_output(output).unwrap();
+            let fixture = FixtureBuilder::new().build().unwrap();
+            let result = process_find_output(fixture.root(), output).unwrap();
             assert_eq!(result.len(), 2);
             assert!(result.contains(&"/dev/sda".to_string()));
             assert!(result.contains(&"/dev/sdb".to_string()));
@@ -170,8 +575,105 @@ mod tests {
         let output = Command::new("echo").arg("").output().unwrap();
 
         if output.status.success() {
-            let result = process_find_output(output);
+            let fixture = FixtureBuilder::new().build().unwrap();
+            let result = process_find_output(fixture.root(), output);
             assert!(matches!(result, Err(DeviceDiscoveryError::NoDevicesFound)));
         }
     }
+
+    fn jbod_fixture() -> SystemFixture {
+        FixtureBuilder::new()
+            .disk(FakeDisk::new("sdz").size_bytes(1 << 40))
+            .disk(
+                FakeDisk::new("sdaa")
+                    .size_bytes(1 << 40)
+                    .partition(1 << 30)
+                    .partition(1 << 30),
+            )
+            .disk(FakeDisk::new("sdzz").size_bytes(1 << 40))
+            .disk(
+                FakeDisk::new("nvme0n1")
+                    .size_bytes(1 << 40)
+                    .rotational(false),
+            )
+            .disk(FakeDisk::new("vda").size_bytes(1 << 30))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_node_kind_from_sysfs_structure() {
+        let fixture = jbod_fixture();
+        let root = fixture.root();
+
+        assert_eq!(node_kind(root, "sdaa"), Some(NodeKind::WholeDisk));
+        assert_eq!(node_kind(root, "sdzz"), Some(NodeKind::WholeDisk));
+        assert_eq!(node_kind(root, "sdaa1"), Some(NodeKind::Partition));
+        assert_eq!(node_kind(root, "sdab"), None);
+
+        assert!(is_whole_disk(root, "sdaa"));
+        assert!(is_whole_disk(root, "sdzz"));
+        assert!(!is_whole_disk(root, "sdaa1"));
+        assert_eq!(partition_names(root, "sdaa"), vec!["sdaa1", "sdaa2"]);
+        assert!(partition_names(root, "sdzz").is_empty());
+    }
+
+    #[test]
+    fn test_find_block_devices_in_fixture() {
+        let fixture = jbod_fixture();
+
+        let devices = find_block_devices_in(fixture.root()).unwrap();
+        let names: Vec<&str> = devices.iter().map(|device| device.name.as_str()).collect();
+        assert_eq!(names, vec!["sdz", "vda", "sdaa", "sdzz", "nvme0n1"]);
+        assert_eq!(devices[1].transport, Transport::Virtio);
+        assert_eq!(devices[4].transport, Transport::Nvme);
+
+        let satas = find_sata_devices_in(fixture.root()).unwrap();
+        let paths: Vec<&str> = satas.iter().map(|device| device.path.as_str()).collect();
+        assert_eq!(paths, vec!["/dev/sdz", "/dev/sdaa", "/dev/sdzz"]);
+    }
+
+    #[test]
+    fn test_unreadable_disk_is_skipped() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda"))
+            .disk(FakeDisk::new("sdb").without_attribute("size"))
+            .disk(FakeDisk::new("sdc"))
+            .build()
+            .unwrap();
+
+        let devices = find_block_devices_in(fixture.root()).unwrap();
+        let names: Vec<&str> = devices.iter().map(|device| device.name.as_str()).collect();
+        assert_eq!(names, vec!["sda", "sdc"]);
+    }
+
+    #[test]
+    fn test_find_block_devices_in_empty_fixture() {
+        let fixture = FixtureBuilder::new().build().unwrap();
+        assert!(matches!(
+            find_block_devices_in(fixture.root()),
+            Err(DeviceDiscoveryError::NoDevicesFound)
+        ));
+    }
+
+    #[test]
+    fn test_process_find_output_keeps_whole_disks_only() {
+        use std::process::Command;
+
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").partition(1 << 30))
+            .disk(FakeDisk::new("sdaa").partition(1 << 30))
+            .disk(FakeDisk::new("sdzz"))
+            .build()
+            .unwrap();
+        let output = Command::new("echo")
+            .arg("/dev/sdaa\n/dev/sdaa1\n/dev/sdb\n/dev/sdzz\n/dev/sdb1")
+            .output()
+            .unwrap();
+
+        if output.status.success() {
+            let result = process_find_output(fixture.root(), output).unwrap();
+            assert_eq!(result, vec!["/dev/sdb", "/dev/sdaa", "/dev/sdzz"]);
+        }
+    }
 }
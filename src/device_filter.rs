@@ -1,8 +1,16 @@
 //! Device filtering module for auto_mount
 //!
-//! This module handles filtering of devices based on type (HDD) and mount status
+//! This module handles filtering of devices by their attributes and usage
 
-use std::process::Command;
+use crate::block_device::{normalize_wwn, probe_attributes_in, resolve_device_path_in};
+use crate::device_usage::UsageTable;
+use crate::system_disk::{find_system_disks_in, system_roles};
+use crate::{
+    BlockDevice, BlockDeviceError, BusyReason, DeviceUsageError, HealthProblem, SystemDiskError,
+    SystemRole, SystemRoot, Transport, ZonedModel,
+};
+use regex::Regex;
+use std::fmt;
 
 /// Errors that can occur during device filtering
 #[derive(Debug, thiserror::Error)]
@@ -15,6 +23,14 @@ pub enum DeviceFilterError {
     SystemInfoError,
     #[error("IO error: {0}")]
     IoError(std::io::Error),
+    #[error("Block device error: {0}")]
+    BlockDevice(#[from] BlockDeviceError),
+    #[error("Device usage check failed: {0}")]
+    Usage(#[from] DeviceUsageError),
+    #[error("System disk detection failed: {0}")]
+    SystemDisk(#[from] SystemDiskError),
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
 }
 
 impl From<std::io::Error> for DeviceFilterError {
@@ -29,33 +45,392 @@ pub struct DeviceInfo {
     pub path: String,
     pub is_rotational: bool,
     pub is_mounted: bool,
+    /// Everything keeping the device busy, empty when it is free to use
+    pub busy_reasons: Vec<BusyReason>,
+}
+
+/// Devices accepted by a filter, and why each of the others was rejected
+#[derive(Debug, Clone, Default)]
+pub struct FilterReport {
+    pub accepted: Vec<BlockDevice>,
+    pub rejected: Vec<RejectedDevice>,
+}
+
+/// A device left out by a filter
+#[derive(Debug, Clone)]
+pub struct RejectedDevice {
+    pub device: BlockDevice,
+    pub reason: RejectReason,
+}
+
+/// Why a filter left a device out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The running system depends on the disk
+    SystemDisk(Vec<SystemRole>),
+    /// The disk or something stacked on it is in use
+    Busy(Vec<BusyReason>),
+    /// The disk is attached over a transport the filter doesn't accept
+    Transport(Transport),
+    /// The disk's rotational flag doesn't match
+    Rotational(bool),
+    /// The disk size in bytes is outside the accepted range
+    Size(u64),
+    /// The model doesn't match the pattern
+    Model(Option<String>),
+    /// The vendor doesn't match the pattern
+    Vendor(Option<String>),
+    /// The serial number is denied or missing from the allow-list
+    Serial(Option<String>),
+    /// The WWN is denied or missing from the allow-list
+    Wwn(Option<String>),
+    /// The removable flag doesn't match
+    Removable(bool),
+    /// The read-only flag doesn't match
+    ReadOnly(bool),
+    /// The disk failed the SMART health check
+    Unhealthy(Vec<HealthProblem>),
+    /// The disk is zoned and the zoned policy refuses it
+    Zoned(ZonedModel),
+    /// MBR was asked for, but the disk of this many bytes is over the 2 TiB an MBR can address
+    TooLargeForMbr(u64),
+    /// MBR was asked for, but the layout has this many partitions and an MBR holds 4
+    TooManyPartitionsForMbr(usize),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::SystemDisk(roles) => write!(f, "system disk: {}", join(roles)),
+            RejectReason::Busy(reasons) => write!(f, "in use: {}", join(reasons)),
+            RejectReason::Transport(transport) => write!(f, "{} transport not accepted", transport),
+            RejectReason::Rotational(true) => write!(f, "rotational disk"),
+            RejectReason::Rotational(false) => write!(f, "not a rotational disk"),
+            RejectReason::Size(size_bytes) => write!(f, "size {} bytes out of range", size_bytes),
+            RejectReason::Model(model) => write!(f, "model {} not accepted", or_unknown(model)),
+            RejectReason::Vendor(vendor) => {
+                write!(f, "vendor {} not accepted", or_unknown(vendor))
+            }
+            RejectReason::Serial(serial) => {
+                write!(f, "serial {} not accepted", or_unknown(serial))
+            }
+            RejectReason::Wwn(wwn) => write!(f, "WWN {} not accepted", or_unknown(wwn)),
+            RejectReason::Removable(true) => write!(f, "removable disk"),
+            RejectReason::Removable(false) => write!(f, "not a removable disk"),
+            RejectReason::ReadOnly(true) => write!(f, "read-only disk"),
+            RejectReason::ReadOnly(false) => write!(f, "writable disk"),
+            RejectReason::Unhealthy(problems) => write!(f, "unhealthy: {}", join(problems)),
+            RejectReason::Zoned(model) => write!(f, "{} zoned disk refused", model),
+            RejectReason::TooLargeForMbr(size_bytes) => {
+                write!(f, "{} bytes is too large for an MBR", size_bytes)
+            }
+            RejectReason::TooManyPartitionsForMbr(partitions) => {
+                write!(f, "{} partitions don't fit in an MBR", partitions)
+            }
+        }
+    }
+}
+
+fn or_unknown(value: &Option<String>) -> &str {
+    value.as_deref().unwrap_or("(unknown)")
+}
+
+fn join<T: ToString>(items: &[T]) -> String {
+    let items: Vec<String> = items.iter().map(ToString::to_string).collect();
+    items.join(", ")
+}
+
+/// Composable device filter; a device is accepted when every predicate holds.
+/// System disks and busy devices are always rejected.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    transports: Vec<Transport>,
+    rotational: Option<bool>,
+    min_size_bytes: Option<u64>,
+    max_size_bytes: Option<u64>,
+    model: Option<Regex>,
+    vendor: Option<Regex>,
+    allowed_serials: Vec<String>,
+    denied_serials: Vec<String>,
+    allowed_wwns: Vec<String>,
+    denied_wwns: Vec<String>,
+    removable: Option<bool>,
+    read_only: Option<bool>,
+}
+
+impl DeviceFilter {
+    /// A filter accepting every unused, non-system disk
+    pub fn new() -> Self {
+        DeviceFilter::default()
+    }
+
+    /// Unused rotational disks: what the smart mount pipeline formats by default
+    pub fn unmounted_hdd() -> Self {
+        DeviceFilter::new().rotational(true)
+    }
+
+    /// Accept disks on this transport; may be given several times
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transports.push(transport);
+        self
+    }
+
+    pub fn rotational(mut self, rotational: bool) -> Self {
+        self.rotational = Some(rotational);
+        self
+    }
+
+    pub fn min_size_bytes(mut self, size_bytes: u64) -> Self {
+        self.min_size_bytes = Some(size_bytes);
+        self
+    }
+
+    pub fn max_size_bytes(mut self, size_bytes: u64) -> Self {
+        self.max_size_bytes = Some(size_bytes);
+        self
+    }
+
+    /// Require the model to match a regular expression
+    pub fn model(mut self, pattern: &str) -> Result<Self, DeviceFilterError> {
+        self.model = Some(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Require the vendor to match a regular expression
+    pub fn vendor(mut self, pattern: &str) -> Result<Self, DeviceFilterError> {
+        self.vendor = Some(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Accept only disks with allow-listed serial numbers
+    pub fn allow_serial(mut self, serial: &str) -> Self {
+        self.allowed_serials.push(serial.to_string());
+        self
+    }
+
+    pub fn deny_serial(mut self, serial: &str) -> Self {
+        self.denied_serials.push(serial.to_string());
+        self
+    }
+
+    /// Accept only disks with allow-listed WWNs
+    pub fn allow_wwn(mut self, wwn: &str) -> Self {
+        self.allowed_wwns.push(wwn.to_string());
+        self
+    }
+
+    pub fn deny_wwn(mut self, wwn: &str) -> Self {
+        self.denied_wwns.push(wwn.to_string());
+        self
+    }
+
+    pub fn removable(mut self, removable: bool) -> Self {
+        self.removable = Some(removable);
+        self
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = Some(read_only);
+        self
+    }
+
+    /// Split devices into accepted and rejected ones
+    pub fn apply(&self, devices: Vec<BlockDevice>) -> Result<FilterReport, DeviceFilterError> {
+        self.apply_in(&SystemRoot::default(), devices)
+    }
+
+    /// Split devices into accepted and rejected ones, reading usage below the given root
+    pub fn apply_in(
+        &self,
+        root: &SystemRoot,
+        devices: Vec<BlockDevice>,
+    ) -> Result<FilterReport, DeviceFilterError> {
+        let system_disks = find_system_disks_in(root)?;
+        let usage = UsageTable::read(root)?;
+        let mut report = FilterReport::default();
+
+        for device in devices {
+            let roles = system_roles(root, &system_disks, &device.name);
+            let busy_reasons = usage.busy_reasons(root, &device.name);
+            let reason = if !roles.is_empty() {
+                Some(RejectReason::SystemDisk(roles))
+            } else if !busy_reasons.is_empty() {
+                Some(RejectReason::Busy(busy_reasons))
+            } else {
+                self.reject_reason(&device)
+            };
+
+            match reason {
+                Some(reason) => report.rejected.push(RejectedDevice { device, reason }),
+                None => report.accepted.push(device),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// The first predicate the device fails, if any
+    fn reject_reason(&self, device: &BlockDevice) -> Option<RejectReason> {
+        if !self.transports.is_empty() && !self.transports.contains(&device.transport) {
+            return Some(RejectReason::Transport(device.transport));
+        }
+        if self
+            .rotational
+            .map_or(false, |rotational| rotational != device.rotational)
+        {
+            return Some(RejectReason::Rotational(device.rotational));
+        }
+        if self
+            .min_size_bytes
+            .map_or(false, |min| device.size_bytes < min)
+            || self
+                .max_size_bytes
+                .map_or(false, |max| device.size_bytes > max)
+        {
+            return Some(RejectReason::Size(device.size_bytes));
+        }
+        if !matches_pattern(&self.model, &device.model) {
+            return Some(RejectReason::Model(device.model.clone()));
+        }
+        if !matches_pattern(&self.vendor, &device.vendor) {
+            return Some(RejectReason::Vendor(device.vendor.clone()));
+        }
+        if !is_listed(
+            &self.allowed_serials,
+            &self.denied_serials,
+            &device.serial,
+            normalize_serial,
+        ) {
+            return Some(RejectReason::Serial(device.serial.clone()));
+        }
+        if !is_listed(
+            &self.allowed_wwns,
+            &self.denied_wwns,
+            &device.wwn,
+            normalize_wwn,
+        ) {
+            return Some(RejectReason::Wwn(device.wwn.clone()));
+        }
+        if self
+            .removable
+            .map_or(false, |removable| removable != device.removable)
+        {
+            return Some(RejectReason::Removable(device.removable));
+        }
+        if self
+            .read_only
+            .map_or(false, |read_only| read_only != device.read_only)
+        {
+            return Some(RejectReason::ReadOnly(device.read_only));
+        }
+        None
+    }
+}
+
+/// A missing pattern accepts anything; a missing value matches no pattern
+fn matches_pattern(pattern: &Option<Regex>, value: &Option<String>) -> bool {
+    match (pattern, value) {
+        (None, _) => true,
+        (Some(pattern), Some(value)) => pattern.is_match(value),
+        (Some(_), None) => false,
+    }
+}
+
+/// Denied values are rejected; with a non-empty allow-list only listed values pass.
+/// Both sides are normalised before comparing
+fn is_listed(
+    allowed: &[String],
+    denied: &[String],
+    value: &Option<String>,
+    normalize: fn(&str) -> String,
+) -> bool {
+    let contains =
+        |list: &[String], value: &str| list.iter().any(|listed| normalize(listed) == value);
+    match value.as_deref().map(normalize) {
+        Some(value) if contains(denied, &value) => false,
+        Some(value) => allowed.is_empty() || contains(allowed, &value),
+        None => allowed.is_empty(),
+    }
+}
+
+fn normalize_serial(serial: &str) -> String {
+    serial.trim().to_string()
 }
 
 /// Filter unmounted HDD devices with proper error handling
 pub fn filter_unmounted_hdd_devices(
     devices: Vec<String>,
 ) -> Result<Vec<String>, DeviceFilterError> {
-    let device_infos = collect_device_infos(devices)?;
+    let root = SystemRoot::default();
+    let devices = devices
+        .iter()
+        .map(|device| BlockDevice::from_identifier_in(&root, device))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let filtered_devices: Vec<String> = device_infos
+    let filtered_devices = filter_unmounted_hdd_block_devices_in(&root, devices)?
         .into_iter()
-        .filter(|info| info.is_rotational && !info.is_mounted)
-        .map(|info| info.path)
+        .map(|device| device.path)
         .collect();
 
     Ok(filtered_devices)
 }
 
+/// Filter unmounted HDD block devices using their sysfs attributes
+pub fn filter_unmounted_hdd_block_devices(
+    devices: Vec<BlockDevice>,
+) -> Result<Vec<BlockDevice>, DeviceFilterError> {
+    filter_unmounted_hdd_block_devices_in(&SystemRoot::default(), devices)
+}
+
+/// Filter HDD block devices that are neither system disks, mounted, swap nor
+/// held by dm/md devices, reading usage below the given system root
+pub fn filter_unmounted_hdd_block_devices_in(
+    root: &SystemRoot,
+    devices: Vec<BlockDevice>,
+) -> Result<Vec<BlockDevice>, DeviceFilterError> {
+    Ok(report_unmounted_hdd_block_devices_in(root, devices)?.accepted)
+}
+
+/// Filter unused HDD block devices, reporting why the others were rejected
+pub fn report_unmounted_hdd_block_devices(
+    devices: Vec<BlockDevice>,
+) -> Result<FilterReport, DeviceFilterError> {
+    report_unmounted_hdd_block_devices_in(&SystemRoot::default(), devices)
+}
+
+/// Filter unused HDD block devices below the given system root, reporting why
+/// the others were rejected
+pub fn report_unmounted_hdd_block_devices_in(
+    root: &SystemRoot,
+    devices: Vec<BlockDevice>,
+) -> Result<FilterReport, DeviceFilterError> {
+    DeviceFilter::unmounted_hdd().apply_in(root, devices)
+}
+
 /// Collect detailed information about devices
 pub fn collect_device_infos(devices: Vec<String>) -> Result<Vec<DeviceInfo>, DeviceFilterError> {
-    let system = create_disk_info()?;
+    collect_device_infos_in(&SystemRoot::default(), devices)
+}
+
+/// Collect detailed information about devices below the given system root
+pub fn collect_device_infos_in(
+    root: &SystemRoot,
+    devices: Vec<String>,
+) -> Result<Vec<DeviceInfo>, DeviceFilterError> {
+    let usage = UsageTable::read(root)?;
     let mut device_infos = Vec::new();
 
     for device in devices {
+        let device = resolve_device_path_in(root, &device)?;
+        let name = device_name(&device)?;
+        let busy_reasons = usage.busy_reasons(root, name);
         let info = DeviceInfo {
-            path: device.clone(),
-            is_rotational: is_rotational_device(&device)?,
-            is_mounted: is_device_mounted(&device, &system)?,
+            is_rotational: probe_attributes_in(root, name)?.rotational,
+            is_mounted: busy_reasons
+                .iter()
+                .any(|reason| matches!(reason, BusyReason::Mounted { .. })),
+            busy_reasons,
+            path: device,
         };
         device_infos.push(info);
     }
@@ -63,48 +438,16 @@ pub fn collect_device_infos(devices: Vec<String>) -> Result<Vec<DeviceInfo>, Dev
     Ok(device_infos)
 }
 
-/// Check if a device is rotational (HDD)
-fn is_rotational_device(device: &str) -> Result<bool, DeviceFilterError> {
-    let output = Command::new("sudo")
-        .args(["lsblk", "-d", "-o", "rota", device])
-        .output()?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(DeviceFilterError::CommandFailed(stderr.to_string()));
-    }
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let lines: Vec<&str> = stdout.lines().collect();
-
-    // Expected format: header line + data line
-    if lines.len() < 2 {
-        return Err(DeviceFilterError::InvalidOutputFormat);
-    }
-
-    let data_line = lines[1].trim();
-    Ok(data_line == "1")
-}
-
-/// Check if a device is currently mounted
-fn is_device_mounted(device: &str, disks: &sysinfo::Disks) -> Result<bool, DeviceFilterError> {
-    let is_mounted = disks.iter().any(|disk| {
-        let disk_name = disk.name().to_string_lossy();
-        disk_name.contains(device)
-    });
-
-    Ok(is_mounted)
-}
-
-/// Create and initialize system information
-fn create_disk_info() -> Result<sysinfo::Disks, DeviceFilterError> {
-    let disks = sysinfo::Disks::new_with_refreshed_list();
-    Ok(disks)
+fn device_name(device: &str) -> Result<&str, BlockDeviceError> {
+    device
+        .strip_prefix("/dev/")
+        .ok_or_else(|| BlockDeviceError::InvalidDevicePath(device.to_string()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
 
     #[test]
     fn test_device_info_creation() {
@@ -112,6 +455,7 @@ mod tests {
             path: "/dev/sda".to_string(),
             is_rotational: true,
             is_mounted: false,
+            busy_reasons: Vec::new(),
         };
 
         assert_eq!(info.path, "/dev/sda");
@@ -126,6 +470,217 @@ mod tests {
         assert_eq!(name, "sda");
     }
 
+    #[test]
+    fn test_collect_device_infos_in_fixture() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").rotational(false).partition(1 << 30))
+            .disk(FakeDisk::new("sdb"))
+            .mount("sda1", "/", "ext4")
+            .build()
+            .unwrap();
+
+        let infos = collect_device_infos_in(
+            fixture.root(),
+            vec!["/dev/sda".to_string(), "/dev/sdb".to_string()],
+        )
+        .unwrap();
+        assert!(!infos[0].is_rotational && infos[0].is_mounted);
+        assert_eq!(infos[0].busy_reasons.len(), 1);
+        assert!(infos[1].is_rotational && !infos[1].is_mounted);
+        assert!(infos[1].busy_reasons.is_empty());
+
+        let missing = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdc").without_attribute("queue/rotational"))
+            .build()
+            .unwrap();
+        let error = collect_device_infos_in(missing.root(), vec!["/dev/sdc".to_string()])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("queue/rotational"), "{}", error);
+    }
+
+    #[test]
+    fn test_report_rejection_reasons() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").partition(1 << 20).partition(1 << 30))
+            .disk(FakeDisk::new("sdb").partition(1 << 30))
+            .disk(FakeDisk::new("sdc").rotational(false))
+            .disk(FakeDisk::new("sdd"))
+            .mount("sda2", "/", "ext4")
+            .mount("sdb1", "/data", "ext4")
+            .symlink("dev/disk/by-uuid/3A1F-22C4", "../../sda1")
+            .file("etc/fstab", "UUID=3A1F-22C4 /boot/efi vfat noauto 0 1\n")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let devices = ["sda", "sdb", "sdc", "sdd"]
+            .iter()
+            .map(|name| BlockDevice::from_name_in(root, name).unwrap())
+            .collect();
+
+        let report = report_unmounted_hdd_block_devices_in(root, devices).unwrap();
+        assert_eq!(report.accepted.len(), 1);
+        assert_eq!(report.accepted[0].name, "sdd");
+
+        let reasons: Vec<String> = report
+            .rejected
+            .iter()
+            .map(|rejected| format!("{}: {}", rejected.device.name, rejected.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                "sda: system disk: backs /, backs fstab entry /boot/efi",
+                "sdb: in use: sdb1 is mounted at /data",
+                "sdc: not a rotational disk",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_device_filter_predicates() {
+        let fixture = FixtureBuilder::new()
+            .disk(
+                FakeDisk::new("sda")
+                    .size_bytes(8 << 40)
+                    .model("WDC WD80EFZX")
+                    .serial("WD-1"),
+            )
+            .disk(
+                FakeDisk::new("sdb")
+                    .size_bytes(4 << 40)
+                    .model("ST4000VN008")
+                    .serial("ZDH-2"),
+            )
+            .disk(
+                FakeDisk::new("sdc")
+                    .size_bytes(1 << 40)
+                    .model("WDC WD10EZEX"),
+            )
+            .disk(FakeDisk::new("sdd").rotational(false).removable(true))
+            .disk(
+                FakeDisk::new("nvme0n1")
+                    .rotational(false)
+                    .size_bytes(2 << 40),
+            )
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let devices: Vec<BlockDevice> = ["sda", "sdb", "sdc", "sdd", "nvme0n1"]
+            .iter()
+            .map(|name| BlockDevice::from_name_in(root, name).unwrap())
+            .collect();
+        let accepted = |filter: DeviceFilter| -> Vec<String> {
+            let report = filter.apply_in(root, devices.clone()).unwrap();
+            assert_eq!(report.accepted.len() + report.rejected.len(), devices.len());
+            report
+                .accepted
+                .into_iter()
+                .map(|device| device.name)
+                .collect()
+        };
+
+        // SSD cache nodes
+        let ssd = DeviceFilter::new()
+            .rotational(false)
+            .removable(false)
+            .transport(Transport::Nvme);
+        assert_eq!(accepted(ssd), vec!["nvme0n1"]);
+
+        let large_wd = DeviceFilter::unmounted_hdd()
+            .model("^WDC")
+            .unwrap()
+            .min_size_bytes(2 << 40);
+        assert_eq!(accepted(large_wd), vec!["sda"]);
+
+        assert_eq!(
+            accepted(DeviceFilter::new().allow_serial("ZDH-2")),
+            vec!["sdb"]
+        );
+        assert_eq!(
+            accepted(
+                DeviceFilter::unmounted_hdd()
+                    .deny_serial("WD-1")
+                    .max_size_bytes(4 << 40)
+            ),
+            vec!["sdb", "sdc"]
+        );
+        assert!(DeviceFilter::new().vendor("(").is_err());
+    }
+
+    #[test]
+    fn test_device_filter_reasons() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").size_bytes(1 << 30).read_only(true))
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let device = BlockDevice::from_name_in(root, "sda").unwrap();
+
+        let report = DeviceFilter::new()
+            .transport(Transport::Sata)
+            .read_only(false)
+            .apply_in(root, vec![device.clone()])
+            .unwrap();
+        assert_eq!(report.rejected[0].reason, RejectReason::ReadOnly(true));
+
+        let report = DeviceFilter::new()
+            .model("^ST")
+            .unwrap()
+            .apply_in(root, vec![device.clone()])
+            .unwrap();
+        assert_eq!(
+            report.rejected[0].reason.to_string(),
+            "model (unknown) not accepted"
+        );
+
+        let report = DeviceFilter::new()
+            .allow_wwn("naa.5000c500a1b2c3d4")
+            .apply_in(root, vec![device])
+            .unwrap();
+        assert_eq!(report.rejected[0].reason, RejectReason::Wwn(None));
+    }
+
+    #[test]
+    fn test_wwn_lists_ignore_spelling() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").wwid("naa.5000c500a1b2c3d4"))
+            .disk(FakeDisk::new("sdc").wwid("naa.5000c500deadbeef"))
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let devices: Vec<BlockDevice> = ["sdb", "sdc"]
+            .iter()
+            .map(|name| BlockDevice::from_name_in(root, name).unwrap())
+            .collect();
+        let names = |report: FilterReport| -> Vec<String> {
+            report
+                .accepted
+                .into_iter()
+                .map(|device| device.name)
+                .collect()
+        };
+
+        for spelling in [
+            "0x5000c500a1b2c3d4",
+            "wwn-0x5000c500a1b2c3d4",
+            "naa.5000c500a1b2c3d4",
+            "NAA.5000C500A1B2C3D4",
+        ] {
+            let allowed = DeviceFilter::new()
+                .allow_wwn(spelling)
+                .apply_in(root, devices.clone())
+                .unwrap();
+            assert_eq!(names(allowed), vec!["sdb"], "allow {}", spelling);
+
+            let denied = DeviceFilter::new()
+                .deny_wwn(spelling)
+                .apply_in(root, devices.clone())
+                .unwrap();
+            assert_eq!(names(denied), vec!["sdc"], "deny {}", spelling);
+        }
+    }
+
     #[test]
     fn test_sysfs_path_construction() {
         let device_name = "sda";
@@ -136,12 +691,33 @@ mod tests {
 
     #[test]
     fn test_empty_device_list() {
-        let devices = vec![];
-        let result = filter_unmounted_hdd_devices(devices);
+        let fixture = FixtureBuilder::new().build().unwrap();
+        let filtered = filter_unmounted_hdd_block_devices_in(fixture.root(), vec![]).unwrap();
+        assert!(filtered.is_empty());
+    }
 
-        match result {
-            Ok(filtered) => assert!(filtered.is_empty()),
-            Err(_) => {} // System info creation might fail in test environment
-        }
+    #[test]
+    fn test_filter_unmounted_hdds_in_fixture() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").partition(1 << 30))
+            .disk(FakeDisk::new("sdb"))
+            .disk(FakeDisk::new("sdc").rotational(false))
+            .disk(FakeDisk::new("sdd").partition(1 << 30))
+            .disk(FakeDisk::new("sde"))
+            .dm("dm-0", "crypt-home", &["sdd1"])
+            .mount("sda1", "/", "ext4")
+            .mount("tmpfs", "/run", "tmpfs")
+            .swap("sde")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let devices = ["sda", "sdb", "sdc", "sdd", "sde"]
+            .iter()
+            .map(|name| BlockDevice::from_name_in(root, name).unwrap())
+            .collect();
+
+        let filtered = filter_unmounted_hdd_block_devices_in(root, devices).unwrap();
+        let names: Vec<&str> = filtered.iter().map(|device| device.name.as_str()).collect();
+        assert_eq!(names, vec!["sdb"]);
     }
 }
@@ -0,0 +1,644 @@
+//! Hotplug monitoring module for auto_mount
+//!
+//! This module listens on the kernel uevent netlink socket and turns raw uevents
+//! into debounced add/remove/change events for whole-disk block devices
+
+use crate::device_discovery::classify_disk_name;
+use crate::DeviceNumber;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+/// Errors that can occur while monitoring devices
+#[derive(Debug, thiserror::Error)]
+pub enum DeviceMonitorError {
+    #[error("IO error: {0}")]
+    IoError(io::Error),
+    #[error("Netlink socket setup failed: {0}")]
+    SocketFailed(io::Error),
+}
+
+impl From<io::Error> for DeviceMonitorError {
+    fn from(error: io::Error) -> Self {
+        DeviceMonitorError::IoError(error)
+    }
+}
+
+/// Uevent action reported by the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UeventAction {
+    Add,
+    Remove,
+    Change,
+    Other(String),
+}
+
+impl From<&str> for UeventAction {
+    fn from(action: &str) -> Self {
+        match action {
+            "add" => UeventAction::Add,
+            "remove" => UeventAction::Remove,
+            "change" => UeventAction::Change,
+            other => UeventAction::Other(other.to_string()),
+        }
+    }
+}
+
+/// A single kernel uevent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uevent {
+    pub action: UeventAction,
+    pub devpath: String,
+    pub subsystem: String,
+    /// Kernel name (`DEVNAME`), e.g. `sda1`
+    pub devname: Option<String>,
+    /// `disk` or `partition` for block devices
+    pub devtype: Option<String>,
+    pub device_number: Option<DeviceNumber>,
+    pub seqnum: Option<u64>,
+    pub properties: HashMap<String, String>,
+}
+
+impl Uevent {
+    /// Kernel name of the whole disk this event belongs to
+    fn disk_name(&self) -> Option<String> {
+        let mut components = self.devpath.rsplit('/');
+        let own = components.next()?;
+        if self.devtype.as_deref() == Some("partition") {
+            components.next().map(str::to_string)
+        } else {
+            Some(own.to_string())
+        }
+    }
+
+    /// Kernel name of the device itself
+    fn name(&self) -> Option<String> {
+        self.devname
+            .as_deref()
+            .map(|name| name.trim_start_matches("/dev/").to_string())
+            .or_else(|| self.devpath.rsplit('/').next().map(str::to_string))
+    }
+}
+
+/// Parse a raw kernel uevent payload (`action@devpath\0KEY=value\0...`)
+pub fn parse_uevent(payload: &[u8]) -> Option<Uevent> {
+    let mut fields = payload
+        .split(|byte| *byte == 0)
+        .filter(|field| !field.is_empty())
+        .map(String::from_utf8_lossy);
+
+    // Messages re-broadcast by udev start with "libudev" and are not handled here
+    let header = fields.next()?;
+    let (action, devpath) = header.split_once('@')?;
+
+    let properties: HashMap<String, String> = fields
+        .filter_map(|field| {
+            field
+                .split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+        })
+        .collect();
+
+    let device_number = match (properties.get("MAJOR"), properties.get("MINOR")) {
+        (Some(major), Some(minor)) => format!("{}:{}", major, minor).parse().ok(),
+        _ => None,
+    };
+
+    Some(Uevent {
+        action: properties
+            .get("ACTION")
+            .map(String::as_str)
+            .unwrap_or(action)
+            .into(),
+        devpath: properties
+            .get("DEVPATH")
+            .cloned()
+            .unwrap_or_else(|| devpath.to_string()),
+        subsystem: properties.get("SUBSYSTEM").cloned().unwrap_or_default(),
+        devname: properties.get("DEVNAME").cloned(),
+        devtype: properties.get("DEVTYPE").cloned(),
+        device_number,
+        seqnum: properties
+            .get("SEQNUM")
+            .and_then(|seqnum| seqnum.parse().ok()),
+        properties,
+    })
+}
+
+/// Whole disk described by a device event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskEvent {
+    /// Kernel name, e.g. `sdb`
+    pub name: String,
+    /// Device node path, e.g. `/dev/sdb`
+    pub path: String,
+    pub device_number: Option<DeviceNumber>,
+    /// Kernel names of the partitions present once the disk settled
+    pub partitions: Vec<String>,
+}
+
+/// Debounced block device event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Added(DiskEvent),
+    Removed(DiskEvent),
+    Changed(DiskEvent),
+}
+
+impl DeviceEvent {
+    /// The disk this event is about
+    pub fn disk(&self) -> &DiskEvent {
+        match self {
+            DeviceEvent::Added(disk) | DeviceEvent::Removed(disk) | DeviceEvent::Changed(disk) => {
+                disk
+            }
+        }
+    }
+}
+
+/// Outcome of waiting for a uevent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Received {
+    Payload(Vec<u8>),
+    Timeout,
+    /// The source is exhausted and will not produce more payloads
+    Closed,
+}
+
+/// Source of raw uevent payloads
+pub trait UeventSource {
+    /// Wait up to `timeout` (forever if `None`) for the next payload
+    fn receive(&mut self, timeout: Option<Duration>) -> io::Result<Received>;
+}
+
+/// Kernel uevent netlink socket
+#[derive(Debug)]
+pub struct NetlinkSocket {
+    fd: OwnedFd,
+    buffer: Vec<u8>,
+}
+
+impl NetlinkSocket {
+    /// Open a socket subscribed to kernel uevents
+    pub fn open() -> Result<NetlinkSocket, DeviceMonitorError> {
+        // SAFETY: plain socket(2) call, the descriptor is owned right after
+        let raw = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if raw < 0 {
+            return Err(DeviceMonitorError::SocketFailed(io::Error::last_os_error()));
+        }
+        // SAFETY: `raw` is a freshly created, valid descriptor
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        // SAFETY: sockaddr_nl is plain data, all-zero is a valid value
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = 1; // kernel uevent multicast group
+
+        // SAFETY: address points to a properly sized sockaddr_nl
+        let result = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(DeviceMonitorError::SocketFailed(io::Error::last_os_error()));
+        }
+
+        Ok(NetlinkSocket {
+            fd,
+            buffer: vec![0; 16 * 1024],
+        })
+    }
+}
+
+impl UeventSource for NetlinkSocket {
+    fn receive(&mut self, timeout: Option<Duration>) -> io::Result<Received> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout
+            .map(|timeout| timeout.as_millis().min(i32::MAX as u128) as libc::c_int)
+            .unwrap_or(-1);
+
+        // SAFETY: poll_fd is a single valid pollfd
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+        if ready < 0 {
+            let error = io::Error::last_os_error();
+            return if error.kind() == io::ErrorKind::Interrupted {
+                Ok(Received::Timeout)
+            } else {
+                Err(error)
+            };
+        }
+        if ready == 0 {
+            return Ok(Received::Timeout);
+        }
+
+        // SAFETY: buffer is valid for writes of its full length
+        let length = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                self.buffer.as_mut_ptr() as *mut libc::c_void,
+                self.buffer.len(),
+                0,
+            )
+        };
+        if length < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Received::Payload(self.buffer[..length as usize].to_vec()))
+    }
+}
+
+/// Recorded uevent payloads replayed in order, for tests and offline analysis
+#[derive(Debug, Clone, Default)]
+pub struct RecordedUevents {
+    payloads: VecDeque<Vec<u8>>,
+}
+
+impl RecordedUevents {
+    pub fn new<I, P>(payloads: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<Vec<u8>>,
+    {
+        RecordedUevents {
+            payloads: payloads.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl UeventSource for RecordedUevents {
+    fn receive(&mut self, _timeout: Option<Duration>) -> io::Result<Received> {
+        Ok(self
+            .payloads
+            .pop_front()
+            .map(Received::Payload)
+            .unwrap_or(Received::Closed))
+    }
+}
+
+/// Default quiet period before a disk is considered settled
+pub const DEFAULT_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PendingKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Clone)]
+struct Pending {
+    kind: PendingKind,
+    disk: DiskEvent,
+    last_seen: Instant,
+}
+
+/// Collects uevents per disk until no more arrive for the settle time
+#[derive(Debug)]
+struct Debouncer {
+    settle_time: Duration,
+    pending: BTreeMap<String, Pending>,
+}
+
+impl Debouncer {
+    fn new(settle_time: Duration) -> Self {
+        Debouncer {
+            settle_time,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn push(&mut self, event: &Uevent, now: Instant) {
+        if event.subsystem != "block" {
+            return;
+        }
+        let (disk_name, name) = match (event.disk_name(), event.name()) {
+            (Some(disk_name), Some(name)) => (disk_name, name),
+            _ => return,
+        };
+        if classify_disk_name(&disk_name).is_none() {
+            return;
+        }
+
+        let is_partition = event.devtype.as_deref() == Some("partition");
+        let pending = self
+            .pending
+            .entry(disk_name.clone())
+            .or_insert_with(|| Pending {
+                kind: PendingKind::Changed,
+                disk: DiskEvent {
+                    path: format!("/dev/{}", disk_name),
+                    name: disk_name,
+                    device_number: None,
+                    partitions: Vec::new(),
+                },
+                last_seen: now,
+            });
+        pending.last_seen = now;
+
+        if is_partition {
+            let partitions = &mut pending.disk.partitions;
+            match event.action {
+                UeventAction::Add if !partitions.contains(&name) => partitions.push(name),
+                UeventAction::Remove => partitions.retain(|partition| *partition != name),
+                _ => {}
+            }
+            partitions.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
+            return;
+        }
+
+        if event.device_number.is_some() {
+            pending.disk.device_number = event.device_number;
+        }
+        match event.action {
+            UeventAction::Add => pending.kind = PendingKind::Added,
+            UeventAction::Remove => {
+                pending.kind = PendingKind::Removed;
+                pending.disk.partitions.clear();
+            }
+            // A change after an add still reports the disk as added
+            UeventAction::Change if pending.kind != PendingKind::Added => {
+                pending.kind = PendingKind::Changed
+            }
+            _ => {}
+        }
+    }
+
+    /// When the next pending disk settles
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .map(|pending| pending.last_seen + self.settle_time)
+            .min()
+    }
+
+    /// Take the first disk that has been quiet for the settle time
+    fn pop_settled(&mut self, now: Instant) -> Option<DeviceEvent> {
+        let settle_time = self.settle_time;
+        let name = self
+            .pending
+            .iter()
+            .find(|(_, pending)| now >= pending.last_seen + settle_time)
+            .map(|(name, _)| name.clone())?;
+        self.pending.remove(&name).map(Pending::into_event)
+    }
+
+    /// Take any pending disk regardless of settle time
+    fn pop_any(&mut self) -> Option<DeviceEvent> {
+        let name = self.pending.keys().next().cloned()?;
+        self.pending.remove(&name).map(Pending::into_event)
+    }
+}
+
+impl Pending {
+    fn into_event(self) -> DeviceEvent {
+        match self.kind {
+            PendingKind::Added => DeviceEvent::Added(self.disk),
+            PendingKind::Removed => DeviceEvent::Removed(self.disk),
+            PendingKind::Changed => DeviceEvent::Changed(self.disk),
+        }
+    }
+}
+
+/// Watches for block devices being inserted, removed or changed
+#[derive(Debug)]
+pub struct DeviceMonitor<S = NetlinkSocket> {
+    source: S,
+    debouncer: Debouncer,
+    closed: bool,
+}
+
+impl DeviceMonitor<NetlinkSocket> {
+    /// Monitor the kernel uevent netlink socket
+    pub fn new() -> Result<Self, DeviceMonitorError> {
+        Ok(DeviceMonitor::with_source(NetlinkSocket::open()?))
+    }
+}
+
+impl<S: UeventSource> DeviceMonitor<S> {
+    /// Monitor uevents coming from any source, e.g. [`RecordedUevents`]
+    pub fn with_source(source: S) -> Self {
+        DeviceMonitor {
+            source,
+            debouncer: Debouncer::new(DEFAULT_SETTLE_TIME),
+            closed: false,
+        }
+    }
+
+    /// Set how long a disk must stay quiet before its event is emitted
+    pub fn settle_time(mut self, settle_time: Duration) -> Self {
+        self.debouncer.settle_time = settle_time;
+        self
+    }
+
+    /// Block until the next settled device event; `None` once the source is closed
+    pub fn next_event(&mut self) -> Result<Option<DeviceEvent>, DeviceMonitorError> {
+        loop {
+            if self.closed {
+                return Ok(self.debouncer.pop_any());
+            }
+
+            let now = Instant::now();
+            if let Some(event) = self.debouncer.pop_settled(now) {
+                return Ok(Some(event));
+            }
+
+            let timeout = self
+                .debouncer
+                .next_deadline()
+                .map(|deadline| deadline.saturating_duration_since(now));
+
+            match self.source.receive(timeout)? {
+                Received::Payload(payload) => {
+                    if let Some(event) = parse_uevent(&payload) {
+                        self.debouncer.push(&event, Instant::now());
+                    }
+                }
+                Received::Timeout => {}
+                Received::Closed => self.closed = true,
+            }
+        }
+    }
+}
+
+impl<S: UeventSource> Iterator for DeviceMonitor<S> {
+    type Item = Result<DeviceEvent, DeviceMonitorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(header: &str, properties: &[&str]) -> Vec<u8> {
+        let mut payload = header.as_bytes().to_vec();
+        for property in properties {
+            payload.push(0);
+            payload.extend_from_slice(property.as_bytes());
+        }
+        payload.push(0);
+        payload
+    }
+
+    fn disk_add(name: &str, minor: u32) -> Vec<u8> {
+        let devpath = format!(
+            "/devices/pci0000:00/0000:00:1f.2/ata3/host2/target2:0:0/2:0:0:0/block/{}",
+            name
+        );
+        payload(
+            &format!("add@{}", devpath),
+            &[
+                "ACTION=add",
+                &format!("DEVPATH={}", devpath),
+                "SUBSYSTEM=block",
+                "MAJOR=8",
+                &format!("MINOR={}", minor),
+                &format!("DEVNAME={}", name),
+                "DEVTYPE=disk",
+                "SEQNUM=4211",
+            ],
+        )
+    }
+
+    fn partition_event(action: &str, disk: &str, partition: &str) -> Vec<u8> {
+        let devpath = format!(
+            "/devices/pci0000:00/0000:00:1f.2/ata3/host2/target2:0:0/2:0:0:0/block/{}/{}",
+            disk, partition
+        );
+        payload(
+            &format!("{}@{}", action, devpath),
+            &[
+                &format!("ACTION={}", action),
+                &format!("DEVPATH={}", devpath),
+                "SUBSYSTEM=block",
+                &format!("DEVNAME={}", partition),
+                "DEVTYPE=partition",
+                "PARTN=1",
+            ],
+        )
+    }
+
+    #[test]
+    fn test_parse_uevent() {
+        let event = parse_uevent(&disk_add("sdb", 16)).unwrap();
+        assert_eq!(event.action, UeventAction::Add);
+        assert_eq!(event.subsystem, "block");
+        assert_eq!(event.devname.as_deref(), Some("sdb"));
+        assert_eq!(event.devtype.as_deref(), Some("disk"));
+        assert_eq!(
+            event.device_number,
+            Some(DeviceNumber {
+                major: 8,
+                minor: 16
+            })
+        );
+        assert_eq!(event.seqnum, Some(4211));
+        assert_eq!(event.disk_name().as_deref(), Some("sdb"));
+
+        let partition = parse_uevent(&partition_event("add", "sdb", "sdb1")).unwrap();
+        assert_eq!(partition.disk_name().as_deref(), Some("sdb"));
+        assert_eq!(partition.name().as_deref(), Some("sdb1"));
+    }
+
+    #[test]
+    fn test_parse_uevent_rejects_garbage() {
+        assert!(parse_uevent(b"").is_none());
+        assert!(parse_uevent(b"libudev\0\xfe\xed").is_none());
+        assert_eq!(
+            parse_uevent(b"bind@/devices/x\0ACTION=bind\0")
+                .unwrap()
+                .action,
+            UeventAction::Other("bind".to_string())
+        );
+    }
+
+    #[test]
+    fn test_debouncer_waits_for_partitions_to_settle() {
+        let settle = Duration::from_millis(500);
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(settle);
+
+        debouncer.push(&parse_uevent(&disk_add("sdb", 16)).unwrap(), start);
+        assert!(debouncer.pop_settled(start + settle / 2).is_none());
+
+        let later = start + settle / 2;
+        let partition = parse_uevent(&partition_event("add", "sdb", "sdb1")).unwrap();
+        debouncer.push(&partition, later);
+        assert!(debouncer.pop_settled(start + settle).is_none());
+        assert_eq!(debouncer.next_deadline(), Some(later + settle));
+
+        match debouncer.pop_settled(later + settle) {
+            Some(DeviceEvent::Added(disk)) => {
+                assert_eq!(disk.name, "sdb");
+                assert_eq!(disk.path, "/dev/sdb");
+                assert_eq!(disk.partitions, vec!["sdb1"]);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(debouncer.next_deadline().is_none());
+    }
+
+    #[test]
+    fn test_monitor_replays_recorded_uevents() {
+        let loop_change = payload(
+            "change@/devices/virtual/block/loop0",
+            &[
+                "ACTION=change",
+                "DEVPATH=/devices/virtual/block/loop0",
+                "SUBSYSTEM=block",
+                "DEVNAME=loop0",
+                "DEVTYPE=disk",
+            ],
+        );
+        let usb_bind = payload(
+            "bind@/devices/pci0000:00/usb1/1-1",
+            &["ACTION=bind", "SUBSYSTEM=usb"],
+        );
+        let source = RecordedUevents::new(vec![
+            usb_bind,
+            disk_add("sdaa", 160),
+            partition_event("add", "sdaa", "sdaa1"),
+            loop_change,
+            partition_event("remove", "sdc", "sdc1"),
+            payload(
+                "remove@/devices/pci0000:00/ata4/host3/target3:0:0/3:0:0:0/block/sdc",
+                &[
+                    "ACTION=remove",
+                    "SUBSYSTEM=block",
+                    "DEVNAME=sdc",
+                    "DEVTYPE=disk",
+                ],
+            ),
+        ]);
+
+        let events: Vec<DeviceEvent> = DeviceMonitor::with_source(source)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert!(
+            matches!(&events[0], DeviceEvent::Added(disk) if disk.name == "sdaa" && disk.partitions == ["sdaa1"])
+        );
+        assert!(
+            matches!(&events[1], DeviceEvent::Removed(disk) if disk.name == "sdc" && disk.partitions.is_empty())
+        );
+    }
+}
@@ -0,0 +1,378 @@
+//! Device usage module for auto_mount
+//!
+//! This module decides whether a block device is in use by looking at mounts,
+//! active swap areas and the device-mapper or md devices stacked on the disk
+//! and its partitions
+
+use crate::device_discovery::{multipath_node, partition_names, MultipathNode};
+use crate::{BlockDevice, DeviceNumber, SystemRoot};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+
+/// Errors that can occur while checking device usage
+#[derive(Debug, thiserror::Error)]
+pub enum DeviceUsageError {
+    #[error("IO error: {0}")]
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for DeviceUsageError {
+    fn from(error: std::io::Error) -> Self {
+        DeviceUsageError::IoError(error)
+    }
+}
+
+/// Why a block device is considered busy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusyReason {
+    /// `device` (the disk, one of its partitions or a device stacked on them) is mounted
+    Mounted { device: String, mount_point: String },
+    /// `device` is an active swap area
+    Swap { device: String },
+    /// `device` is held by a device-mapper target such as LVM or dm-crypt
+    DmHolder { device: String, holder: String },
+    /// `device` is a member of an md RAID array
+    MdMember { device: String, array: String },
+}
+
+impl fmt::Display for BusyReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BusyReason::Mounted {
+                device,
+                mount_point,
+            } => write!(f, "{} is mounted at {}", device, mount_point),
+            BusyReason::Swap { device } => write!(f, "{} is an active swap area", device),
+            BusyReason::DmHolder { device, holder } => {
+                write!(f, "{} is held by device-mapper target {}", device, holder)
+            }
+            BusyReason::MdMember { device, array } => {
+                write!(f, "{} is a member of md array {}", device, array)
+            }
+        }
+    }
+}
+
+/// Reasons a device is busy, empty when it is free to use
+pub fn busy_reasons(device: &BlockDevice) -> Result<Vec<BusyReason>, DeviceUsageError> {
+    busy_reasons_in(&SystemRoot::default(), device)
+}
+
+/// Reasons a device below the given system root is busy, empty when it is free to use
+pub fn busy_reasons_in(
+    root: &SystemRoot,
+    device: &BlockDevice,
+) -> Result<Vec<BusyReason>, DeviceUsageError> {
+    Ok(UsageTable::read(root)?.busy_reasons(root, &device.name))
+}
+
+/// One line of `/proc/self/mountinfo`
+#[derive(Debug, Clone)]
+pub(crate) struct MountRecord {
+    pub(crate) device_number: Option<DeviceNumber>,
+    pub(crate) source: String,
+    pub(crate) mount_point: String,
+}
+
+/// Parse `/proc/self/mountinfo`
+pub(crate) fn read_mounts(root: &SystemRoot) -> io::Result<Vec<MountRecord>> {
+    let mountinfo = fs::read_to_string(root.mountinfo())?;
+    Ok(mountinfo.lines().filter_map(parse_mountinfo_line).collect())
+}
+
+/// Paths of the active swap areas in `/proc/swaps`
+pub(crate) fn read_swaps(root: &SystemRoot) -> io::Result<Vec<String>> {
+    // Kernels built without swap support have no /proc/swaps at all
+    let swaps = match fs::read_to_string(root.swaps()) {
+        Ok(swaps) => swaps,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+
+    Ok(swaps
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .map(unescape)
+        .collect())
+}
+
+/// Snapshot of mounts and swap areas, read once and checked against many devices
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UsageTable {
+    mounts: Vec<MountRecord>,
+    swaps: Vec<String>,
+}
+
+impl UsageTable {
+    pub(crate) fn read(root: &SystemRoot) -> Result<Self, DeviceUsageError> {
+        Ok(UsageTable {
+            mounts: read_mounts(root)?,
+            swaps: read_swaps(root)?,
+        })
+    }
+
+    /// Walk the device, its partitions and everything stacked on them
+    pub(crate) fn busy_reasons(&self, root: &SystemRoot, name: &str) -> Vec<BusyReason> {
+        let mut reasons = Vec::new();
+        self.visit(root, name, &mut reasons, &mut BTreeSet::new());
+        reasons
+    }
+
+    fn visit(
+        &self,
+        root: &SystemRoot,
+        name: &str,
+        reasons: &mut Vec<BusyReason>,
+        visited: &mut BTreeSet<String>,
+    ) {
+        if !visited.insert(name.to_string()) {
+            return;
+        }
+
+        let dir = root.sys_class_block().join(name);
+        let device_number = read_trimmed(&dir.join("dev")).and_then(|dev| dev.parse().ok());
+        let mut aliases = vec![format!("/dev/{}", name)];
+        if let Some(dm_name) = read_trimmed(&dir.join("dm/name")) {
+            aliases.push(format!("/dev/mapper/{}", dm_name));
+        }
+
+        // btrfs reports an anonymous device number, so fall back to the source path
+        for mount in &self.mounts {
+            if (device_number.is_some() && mount.device_number == device_number)
+                || aliases.contains(&mount.source)
+            {
+                reasons.push(BusyReason::Mounted {
+                    device: name.to_string(),
+                    mount_point: mount.mount_point.clone(),
+                });
+            }
+        }
+        if self.swaps.iter().any(|swap| aliases.contains(swap)) {
+            reasons.push(BusyReason::Swap {
+                device: name.to_string(),
+            });
+        }
+
+        for partition in partition_names(root, name) {
+            self.visit(root, &partition, reasons, visited);
+        }
+
+        let mut holders: Vec<String> = fs::read_dir(dir.join("holders"))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        holders.sort();
+        for holder in holders {
+            let holder_dir = root.sys_class_block().join(&holder);
+            if multipath_node(root, &holder) == Some(MultipathNode::Partition(name.to_string())) {
+                // kpartx partitions of a multipath map count as its partitions
+            } else if let Some(dm_name) = read_trimmed(&holder_dir.join("dm/name")) {
+                reasons.push(BusyReason::DmHolder {
+                    device: name.to_string(),
+                    holder: dm_name,
+                });
+            } else if holder_dir.join("md").exists() {
+                reasons.push(BusyReason::MdMember {
+                    device: name.to_string(),
+                    array: holder.clone(),
+                });
+            }
+            self.visit(root, &holder, reasons, visited);
+        }
+    }
+}
+
+/// Parse `id parent major:minor root mount_point options [optional...] - fstype source super_options`
+fn parse_mountinfo_line(line: &str) -> Option<MountRecord> {
+    let (head, tail) = line.split_once(" - ")?;
+    let mut head = head.split_whitespace();
+    let device_number = head.nth(2)?.parse().ok();
+    let mount_point = unescape(head.nth(1)?);
+    let source = unescape(tail.split_whitespace().nth(1)?);
+
+    Some(MountRecord {
+        device_number,
+        source,
+        mount_point,
+    })
+}
+
+/// Undo the octal escaping procfs and fstab apply to spaces, tabs, newlines and backslashes
+pub(crate) fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let octal = bytes
+            .get(index + 1..index + 4)
+            .filter(|_| bytes[index] == b'\\')
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match octal {
+            Some(byte) => {
+                unescaped.push(byte);
+                index += 4;
+            }
+            None => {
+                unescaped.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).to_string()
+}
+
+pub(crate) fn read_trimmed(path: &std::path::Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
+
+    fn reasons(root: &SystemRoot, name: &str) -> Vec<BusyReason> {
+        UsageTable::read(root).unwrap().busy_reasons(root, name)
+    }
+
+    #[test]
+    fn test_parse_mountinfo_line() {
+        let record = parse_mountinfo_line(
+            "36 35 8:17 / /mnt/my\\040disk rw,relatime shared:1 master:2 - ext4 /dev/sdb1 rw",
+        )
+        .unwrap();
+        assert_eq!(
+            record.device_number,
+            Some(DeviceNumber {
+                major: 8,
+                minor: 17
+            })
+        );
+        assert_eq!(record.mount_point, "/mnt/my disk");
+        assert_eq!(record.source, "/dev/sdb1");
+        assert!(parse_mountinfo_line("garbage").is_none());
+    }
+
+    #[test]
+    fn test_prefix_names_are_not_confused() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda"))
+            .disk(FakeDisk::new("sdaa").partition(1 << 30))
+            .mount("sdaa1", "/data", "ext4")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        assert!(reasons(root, "sda").is_empty());
+        assert_eq!(
+            reasons(root, "sdaa"),
+            vec![BusyReason::Mounted {
+                device: "sdaa1".to_string(),
+                mount_point: "/data".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_swap_and_mounted_partitions() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").partition(1 << 30).partition(1 << 30))
+            .mount("sda1", "/", "ext4")
+            .swap("sda2")
+            .swap("/swapfile")
+            .build()
+            .unwrap();
+
+        let found = reasons(fixture.root(), "sda");
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].to_string(), "sda1 is mounted at /");
+        assert_eq!(found[1].to_string(), "sda2 is an active swap area");
+    }
+
+    #[test]
+    fn test_dm_and_md_holders() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").partition(1 << 30))
+            .disk(FakeDisk::new("sdc"))
+            .disk(FakeDisk::new("sdd"))
+            .dm("dm-0", "vg0-data", &["sdb1"])
+            .md("md0", &["sdc", "sdd"])
+            .mount("dm-0", "/srv", "xfs")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        assert_eq!(
+            reasons(root, "sdb"),
+            vec![
+                BusyReason::DmHolder {
+                    device: "sdb1".to_string(),
+                    holder: "vg0-data".to_string(),
+                },
+                BusyReason::Mounted {
+                    device: "dm-0".to_string(),
+                    mount_point: "/srv".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            reasons(root, "sdd"),
+            vec![BusyReason::MdMember {
+                device: "sdd".to_string(),
+                array: "md0".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_multipath_partitions() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb"))
+            .disk(FakeDisk::new("sdc"))
+            .multipath("dm-0", "mpatha", &["sdb", "sdc"])
+            .dm("dm-1", "mpatha-part1", &["dm-0"])
+            .file(
+                "sys/devices/virtual/block/dm-1/dm/uuid",
+                "part1-mpath-mpatha\n",
+            )
+            .mount("dm-1", "/srv", "xfs")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        assert_eq!(
+            reasons(root, "dm-0"),
+            vec![BusyReason::Mounted {
+                device: "dm-1".to_string(),
+                mount_point: "/srv".to_string(),
+            }]
+        );
+        assert_eq!(
+            reasons(root, "sdb")[0].to_string(),
+            "sdb is held by device-mapper target mpatha"
+        );
+    }
+
+    #[test]
+    fn test_mount_matched_by_source_path() {
+        // btrfs mounts carry an anonymous 0:N device number
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb"))
+            .file(
+                "proc/self/mountinfo",
+                "40 1 0:52 / /pool rw,relatime shared:5 - btrfs /dev/sdb rw\n",
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(reasons(fixture.root(), "sdb").len(), 1);
+    }
+}
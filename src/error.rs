@@ -1,13 +1,39 @@
-use crate::{DeviceDiscoveryError, DeviceFilterError, FilesystemError, PartitionError};
+use crate::{
+    BlockDeviceError, DetachError, DeviceDiscoveryError, DeviceFilterError, DeviceUsageError,
+    FilesystemError, GptError, HealthError, MbrError, PartitionError, PartitionTableError,
+    ScsiRescanError, SignatureError, SystemDiskError, TableBackupError,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("Block device error: {0}")]
+    BlockDeviceError(#[from] BlockDeviceError),
+    #[error("Detach error: {0}")]
+    DetachError(#[from] DetachError),
     #[error("Device discovery error: {0}")]
     DeviceDiscoveryError(#[from] DeviceDiscoveryError),
     #[error("Device filter error: {0}")]
     DeviceFilterError(#[from] DeviceFilterError),
+    #[error("Device usage error: {0}")]
+    DeviceUsageError(#[from] DeviceUsageError),
+    #[error("GPT error: {0}")]
+    GptError(#[from] GptError),
+    #[error("Health check error: {0}")]
+    HealthError(#[from] HealthError),
+    #[error("MBR error: {0}")]
+    MbrError(#[from] MbrError),
     #[error("Partition error: {0}")]
     PartitionError(#[from] PartitionError),
+    #[error("Partition table error: {0}")]
+    PartitionTableError(#[from] PartitionTableError),
+    #[error("SCSI rescan error: {0}")]
+    ScsiRescanError(#[from] ScsiRescanError),
+    #[error("Signature error: {0}")]
+    SignatureError(#[from] SignatureError),
+    #[error("System disk error: {0}")]
+    SystemDiskError(#[from] SystemDiskError),
+    #[error("Partition table backup error: {0}")]
+    TableBackupError(#[from] TableBackupError),
     #[error("Filesystem error: {0}")]
     FilesystemError(#[from] FilesystemError),
 }
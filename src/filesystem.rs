@@ -2,6 +2,13 @@
 //!
 //! This module handles filesystem creation with support for multiple filesystem types
 
+use crate::block_device::resolve_device_paths_in;
+use crate::device_discovery::{node_kind, partition_names, NodeKind};
+use crate::signature::{is_blank, probe_device_signatures_in};
+use crate::system_disk::{describe_roles, find_system_disks_in, system_roles};
+use crate::{
+    BlockDevice, BlockDeviceError, SignatureError, SystemDiskError, SystemRoot, ZonedModel,
+};
 use std::process::Command;
 use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
 
@@ -18,6 +25,21 @@ pub enum FilesystemError {
     InvalidDevice(String),
     #[error("Filesystem formatting failed for device: {0}")]
     FormatFailed(String),
+    #[error("System disk detection failed: {0}")]
+    SystemDiskDetection(#[from] SystemDiskError),
+    #[error("Refusing to format {device} on a system disk: {roles}")]
+    SystemDisk { device: String, roles: String },
+    #[error("Signature probing failed: {0}")]
+    Signature(#[from] SignatureError),
+    #[error("Refusing to overwrite {device}: found {signatures}")]
+    NotBlank { device: String, signatures: String },
+    #[error("{filesystem} can't be created on zoned disk {device}")]
+    ZonedUnsupported {
+        device: String,
+        filesystem: FilesystemType,
+    },
+    #[error("Block device error: {0}")]
+    BlockDevice(#[from] BlockDeviceError),
 }
 
 impl From<std::io::Error> for FilesystemError {
@@ -38,6 +60,7 @@ pub enum FilesystemType {
     Ntfs,
     #[strum(serialize = "fat32")]
     Fat32,
+    F2fs,
 }
 
 impl FilesystemType {
@@ -51,9 +74,25 @@ impl FilesystemType {
             FilesystemType::Btrfs => ("mkfs.btrfs", vec!["-f"]),
             FilesystemType::Ntfs => ("mkfs.ntfs", vec!["-f", "-Q"]),
             FilesystemType::Fat32 => ("mkfs.fat", vec!["-F", "32"]),
+            FilesystemType::F2fs => ("mkfs.f2fs", vec!["-f"]),
         }
     }
 
+    /// Extra arguments to lay the filesystem out in zones, `None` if the
+    /// filesystem can't live on a host-managed zoned disk
+    fn zoned_args(&self) -> Option<Vec<&'static str>> {
+        match self {
+            FilesystemType::Btrfs => Some(vec!["-O", "zoned"]),
+            FilesystemType::F2fs => Some(vec!["-m"]),
+            _ => None,
+        }
+    }
+
+    /// Whether the filesystem can be created on a zoned disk
+    pub fn supports_zoned(&self) -> bool {
+        self.zoned_args().is_some()
+    }
+
     /// Get all supported filesystem types
     pub fn supported_types() -> Vec<FilesystemType> {
         FilesystemType::iter().collect()
@@ -70,6 +109,40 @@ impl FilesystemType {
     }
 }
 
+/// Formatting configuration
+#[derive(Debug, Clone)]
+pub struct FormatConfig {
+    pub filesystem: FilesystemType,
+    /// Devices (`/dev/sdb1` or `sdb1`) that may be formatted even though existing
+    /// signatures were found on them; listing a disk covers its partitions
+    pub allow_non_blank: Vec<String>,
+    /// Where sysfs, /dev and /etc are read from
+    pub root: SystemRoot,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        Self {
+            filesystem: FilesystemType::Ext4,
+            allow_non_blank: Vec::new(),
+            root: SystemRoot::default(),
+        }
+    }
+}
+
+impl FormatConfig {
+    fn allows_non_blank(&self, device: &BlockDevice) -> bool {
+        let mut names = vec![device.path.clone(), device.name.clone()];
+        if let Some(parent) = &device.parent {
+            names.push(format!("/dev/{}", parent));
+            names.push(parent.clone());
+        }
+        self.allow_non_blank
+            .iter()
+            .any(|allowed| names.contains(allowed))
+    }
+}
+
 /// Format result for a single device
 #[derive(Debug, Clone)]
 pub struct FormatResult {
@@ -84,27 +157,139 @@ pub fn format_devices(devices: &[String]) -> Result<(), FilesystemError> {
     format_devices_with_type(devices, FilesystemType::Ext4)
 }
 
-/// Format devices with specified filesystem type
+/// Format devices with specified filesystem type; devices may be given by any
+/// identifier `resolve_device_path` accepts
 pub fn format_devices_with_type(
     devices: &[String],
     filesystem: FilesystemType,
 ) -> Result<(), FilesystemError> {
+    format_devices_with_type_in(&SystemRoot::default(), devices, filesystem)
+}
+
+/// Format devices with specified filesystem type, resolving them and checking
+/// for system disks below the given system root
+pub fn format_devices_with_type_in(
+    root: &SystemRoot,
+    devices: &[String],
+    filesystem: FilesystemType,
+) -> Result<(), FilesystemError> {
+    let config = FormatConfig {
+        filesystem,
+        root: root.clone(),
+        ..Default::default()
+    };
+    format_devices_with_config(devices, &config)
+}
+
+/// Format devices with custom configuration; every device is checked for
+/// system disks and existing signatures before the first one is formatted
+pub fn format_devices_with_config(
+    devices: &[String],
+    config: &FormatConfig,
+) -> Result<(), FilesystemError> {
+    let root = &config.root;
+    let devices = resolve_device_paths_in(root, devices)?;
+    let devices = devices
+        .iter()
+        .map(|device| BlockDevice::from_path_in(root, device))
+        .collect::<Result<Vec<_>, _>>()?;
+    format_block_devices_with_config(&devices, config)
+}
+
+/// Format block devices (usually partitions) with ext4 filesystem
+pub fn format_block_devices(devices: &[BlockDevice]) -> Result<(), FilesystemError> {
+    format_block_devices_with_type(devices, FilesystemType::Ext4)
+}
+
+/// Format block devices with specified filesystem type
+pub fn format_block_devices_with_type(
+    devices: &[BlockDevice],
+    filesystem: FilesystemType,
+) -> Result<(), FilesystemError> {
+    format_block_devices_with_type_in(&SystemRoot::default(), devices, filesystem)
+}
+
+/// Format block devices with specified filesystem type, checking for system
+/// disks below the given system root
+pub fn format_block_devices_with_type_in(
+    root: &SystemRoot,
+    devices: &[BlockDevice],
+    filesystem: FilesystemType,
+) -> Result<(), FilesystemError> {
+    let config = FormatConfig {
+        filesystem,
+        root: root.clone(),
+        ..Default::default()
+    };
+    format_block_devices_with_config(devices, &config)
+}
+
+/// Format block devices with custom configuration, refusing system disks and
+/// devices that already carry a filesystem, RAID, LVM or LUKS signature
+pub fn format_block_devices_with_config(
+    devices: &[BlockDevice],
+    config: &FormatConfig,
+) -> Result<(), FilesystemError> {
+    let paths: Vec<String> = devices.iter().map(|device| device.path.clone()).collect();
+    ensure_not_system_devices(&config.root, &paths)?;
+    ensure_blank(devices, config)?;
+    for device in devices {
+        format_single_device(&config.root, device, &config.filesystem)?;
+    }
+    Ok(())
+}
+
+/// Refuse every device with existing signatures, unless allowed, before formatting any
+fn ensure_blank(devices: &[BlockDevice], config: &FormatConfig) -> Result<(), FilesystemError> {
     for device in devices {
-        format_single_device(device, &filesystem)?;
+        if config.allows_non_blank(device) {
+            continue;
+        }
+
+        let signatures = probe_device_signatures_in(&config.root, device)?;
+        if !is_blank(&signatures) {
+            let signatures: Vec<String> = signatures.iter().map(ToString::to_string).collect();
+            return Err(FilesystemError::NotBlank {
+                device: device.path.clone(),
+                signatures: signatures.join(", "),
+            });
+        }
     }
     Ok(())
 }
 
-/// Format a single device with specified filesystem
-fn format_single_device(device: &str, filesystem: &FilesystemType) -> Result<(), FilesystemError> {
-    validate_device_path(device)?;
+/// Refuse every device backed by a system disk before formatting any
+fn ensure_not_system_devices(root: &SystemRoot, devices: &[String]) -> Result<(), FilesystemError> {
+    let system_disks = find_system_disks_in(root)?;
+    for device in devices {
+        let name = device.strip_prefix("/dev/").unwrap_or(device);
+        let roles = system_roles(root, &system_disks, name);
+        if !roles.is_empty() {
+            return Err(FilesystemError::SystemDisk {
+                device: device.clone(),
+                roles: describe_roles(&roles),
+            });
+        }
+    }
+    Ok(())
+}
 
-    let (command_name, mut args) = filesystem.format_command();
-    args.push(device);
+/// Format a single device with specified filesystem; zoned disks get a zoned
+/// layout, or are refused when the filesystem can't write sequentially
+fn format_single_device(
+    root: &SystemRoot,
+    device: &BlockDevice,
+    filesystem: &FilesystemType,
+) -> Result<(), FilesystemError> {
+    let args = format_args(device, filesystem)?;
+    let command_name = filesystem.format_command().0;
+    let device = device.path.as_str();
+    validate_device_path_in(root, device)?;
 
     let output = Command::new("sudo")
         .arg(command_name)
         .args(&args)
+        .arg(device)
         .output()?;
 
     if !output.status.success() {
@@ -118,17 +303,66 @@ fn format_single_device(device: &str, filesystem: &FilesystemType) -> Result<(),
     Ok(())
 }
 
-/// Validate device path
-fn validate_device_path(device: &str) -> Result<(), FilesystemError> {
-    if !device.starts_with("/dev/") {
-        return Err(FilesystemError::InvalidDevice(device.to_string()));
+/// mkfs arguments for a device, without the device path
+fn format_args(
+    device: &BlockDevice,
+    filesystem: &FilesystemType,
+) -> Result<Vec<&'static str>, FilesystemError> {
+    let mut args = filesystem.format_command().1;
+    match (device.zoned, filesystem.zoned_args()) {
+        (ZonedModel::None, _) => {}
+        (_, Some(zoned_args)) => args.extend(zoned_args),
+        // Host-aware disks still take random writes, just slowly
+        (ZonedModel::HostAware, None) => {}
+        (ZonedModel::HostManaged, None) => {
+            return Err(FilesystemError::ZonedUnsupported {
+                device: device.path.clone(),
+                filesystem: filesystem.clone(),
+            })
+        }
     }
+    Ok(args)
+}
+
+/// Validate device path, refusing whole disks that still carry partitions
+fn validate_device_path_in(root: &SystemRoot, device: &str) -> Result<(), FilesystemError> {
+    let name = device
+        .strip_prefix("/dev/")
+        .ok_or_else(|| FilesystemError::InvalidDevice(device.to_string()))?;
+
+    if node_kind(root, name) == Some(NodeKind::WholeDisk) && !partition_names(root, name).is_empty()
+    {
+        return Err(FilesystemError::InvalidDevice(format!(
+            "{} is a whole disk with partitions",
+            device
+        )));
+    }
+
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
+
+    #[test]
+    fn test_refuses_system_partitions() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").partition(1 << 20).partition(1 << 30))
+            .disk(FakeDisk::new("sdb").partition(1 << 30))
+            .mount("sda2", "/", "ext4")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        let error = ensure_not_system_devices(root, &["/dev/sda1".to_string()]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Refusing to format /dev/sda1 on a system disk: backs /"
+        );
+        assert!(ensure_not_system_devices(root, &["/dev/sdb1".to_string()]).is_ok());
+    }
 
     #[test]
     fn test_filesystem_type_from_str() {
@@ -192,6 +426,36 @@ mod tests {
         assert_eq!(args, vec!["-f"]);
     }
 
+    #[test]
+    fn test_zoned_format_args() {
+        let mut device = BlockDevice::fake("sdb", 20 << 40);
+        assert_eq!(
+            format_args(&device, &FilesystemType::Btrfs).unwrap(),
+            vec!["-f"]
+        );
+
+        device.zoned = ZonedModel::HostManaged;
+        assert_eq!(
+            format_args(&device, &FilesystemType::Btrfs).unwrap(),
+            vec!["-f", "-O", "zoned"]
+        );
+        assert_eq!(
+            format_args(&device, &FilesystemType::F2fs).unwrap(),
+            vec!["-f", "-m"]
+        );
+        let error = format_args(&device, &FilesystemType::Ext4).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "ext4 can't be created on zoned disk /dev/sdb"
+        );
+
+        device.zoned = ZonedModel::HostAware;
+        assert_eq!(
+            format_args(&device, &FilesystemType::Ext4).unwrap(),
+            vec!["-F"]
+        );
+    }
+
     #[test]
     fn test_filesystem_display() {
         assert_eq!(FilesystemType::Ext4.to_string(), "ext4");
@@ -206,7 +470,7 @@ mod tests {
         assert!(supported.contains(&FilesystemType::Ext4));
         assert!(supported.contains(&FilesystemType::Xfs));
         assert!(supported.contains(&FilesystemType::Fat32));
-        assert_eq!(supported.len(), 7); // All 7 filesystem types
+        assert_eq!(supported.len(), 8); // All 8 filesystem types
     }
 
     #[test]
@@ -215,7 +479,8 @@ mod tests {
         assert!(names.contains(&"ext4".to_string()));
         assert!(names.contains(&"xfs".to_string()));
         assert!(names.contains(&"fat32".to_string()));
-        assert_eq!(names.len(), 7);
+        assert!(names.contains(&"f2fs".to_string()));
+        assert_eq!(names.len(), 8);
     }
 
     #[test]
@@ -242,9 +507,81 @@ mod tests {
 
     #[test]
     fn test_validate_device_path() {
-        assert!(validate_device_path("/dev/sda1").is_ok());
-        assert!(validate_device_path("sda1").is_err());
-        assert!(validate_device_path("/home/user").is_err());
+        let fixture = FixtureBuilder::new().build().unwrap();
+        let root = fixture.root();
+
+        assert!(validate_device_path_in(root, "/dev/sda1").is_ok());
+        assert!(validate_device_path_in(root, "sda1").is_err());
+        assert!(validate_device_path_in(root, "/home/user").is_err());
+    }
+
+    #[test]
+    fn test_refuses_system_disks_below_root() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").partition(1 << 30))
+            .file("etc/fstab", "/dev/sdb1 /data ext4 defaults 0 2\n")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let partition = BlockDevice::from_name_in(root, "sdb1").unwrap();
+
+        let error =
+            format_block_devices_with_type_in(root, &[partition], FilesystemType::Xfs).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Refusing to format /dev/sdb1 on a system disk: backs fstab entry /data"
+        );
+        let error =
+            format_devices_with_type_in(root, &["/dev/sdb1".to_string()], FilesystemType::Xfs)
+                .unwrap_err();
+        assert!(matches!(error, FilesystemError::SystemDisk { .. }));
+    }
+
+    #[test]
+    fn test_refuses_non_blank_partitions() {
+        let mut lvm = vec![0; 1 << 20];
+        lvm[512..520].copy_from_slice(b"LABELONE");
+        lvm[536..544].copy_from_slice(b"LVM2 001");
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").partition(1 << 30).partition(1 << 30))
+            .file("dev/sdb1", lvm)
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let partitions: Vec<BlockDevice> = ["sdb1", "sdb2"]
+            .iter()
+            .map(|name| BlockDevice::from_name_in(root, name).unwrap())
+            .collect();
+        let mut config = FormatConfig {
+            root: root.clone(),
+            ..Default::default()
+        };
+
+        assert!(ensure_blank(&partitions[1..], &config).is_ok());
+        let error = format_block_devices_with_config(&partitions, &config).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Refusing to overwrite /dev/sdb1: found LVM2_member"
+        );
+
+        config.allow_non_blank = vec!["sdb1".to_string()];
+        assert!(ensure_blank(&partitions, &config).is_ok());
+        config.allow_non_blank = vec!["/dev/sdb".to_string()];
+        assert!(ensure_blank(&partitions, &config).is_ok());
+    }
+
+    #[test]
+    fn test_validate_device_path_whole_disk_with_partitions() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdaa").partition(1 << 30))
+            .disk(FakeDisk::new("sdzz"))
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        assert!(validate_device_path_in(root, "/dev/sdaa1").is_ok());
+        assert!(validate_device_path_in(root, "/dev/sdzz").is_ok());
+        assert!(validate_device_path_in(root, "/dev/sdaa").is_err());
     }
 
     #[test]
@@ -0,0 +1,604 @@
+//! Test fixture module for auto_mount
+//!
+//! This module lays out a fake sysfs/procfs/dev/etc tree in a temporary directory,
+//! so code reading through a [`SystemRoot`] can be tested without real disks
+
+use crate::SystemRoot;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+/// Fake whole disk to lay out in a fixture
+#[derive(Debug, Clone)]
+pub struct FakeDisk {
+    name: String,
+    device_path: Option<String>,
+    size_bytes: u64,
+    attributes: BTreeMap<String, String>,
+    device_attributes: BTreeMap<String, String>,
+    removed: Vec<String>,
+    partitions: Vec<FakePartition>,
+}
+
+#[derive(Debug, Clone)]
+struct FakePartition {
+    size_bytes: u64,
+    attributes: BTreeMap<String, String>,
+}
+
+impl FakeDisk {
+    /// A rotational disk with 512-byte sectors, attached the way its name suggests
+    pub fn new(name: &str) -> Self {
+        let mut disk = FakeDisk {
+            name: name.to_string(),
+            device_path: None,
+            size_bytes: 0,
+            attributes: BTreeMap::new(),
+            device_attributes: BTreeMap::new(),
+            removed: Vec::new(),
+            partitions: Vec::new(),
+        };
+        disk.attributes.insert("ro".to_string(), "0".to_string());
+        disk.attributes
+            .insert("removable".to_string(), "0".to_string());
+        disk.attributes
+            .insert("queue/rotational".to_string(), "1".to_string());
+        disk.attributes
+            .insert("queue/logical_block_size".to_string(), "512".to_string());
+        disk.attributes
+            .insert("queue/physical_block_size".to_string(), "512".to_string());
+        disk
+    }
+
+    pub fn size_bytes(mut self, size_bytes: u64) -> Self {
+        self.size_bytes = size_bytes;
+        self
+    }
+
+    /// Set any attribute relative to the disk's sysfs directory (`queue/zoned`, ...)
+    pub fn attribute(mut self, attribute: &str, value: &str) -> Self {
+        self.attributes
+            .insert(attribute.to_string(), value.to_string());
+        self
+    }
+
+    /// Remove an attribute so the disk lacks it entirely
+    pub fn without_attribute(mut self, attribute: &str) -> Self {
+        self.attributes.remove(attribute);
+        self.device_attributes
+            .remove(attribute.trim_start_matches("device/"));
+        self.removed.push(attribute.to_string());
+        self
+    }
+
+    /// Set an attribute of the underlying device (`device/<attribute>`)
+    pub fn device_attribute(mut self, attribute: &str, value: &str) -> Self {
+        self.device_attributes
+            .insert(attribute.to_string(), value.to_string());
+        self
+    }
+
+    /// Path below `/sys/devices` of the device the disk hangs off
+    pub fn device_path(mut self, device_path: &str) -> Self {
+        self.device_path = Some(device_path.trim_matches('/').to_string());
+        self
+    }
+
+    pub fn rotational(self, rotational: bool) -> Self {
+        self.attribute("queue/rotational", flag(rotational))
+    }
+
+    pub fn removable(self, removable: bool) -> Self {
+        self.attribute("removable", flag(removable))
+    }
+
+    pub fn read_only(self, read_only: bool) -> Self {
+        self.attribute("ro", flag(read_only))
+    }
+
+    pub fn sector_sizes(self, logical: u32, physical: u32) -> Self {
+        self.attribute("queue/logical_block_size", &logical.to_string())
+            .attribute("queue/physical_block_size", &physical.to_string())
+    }
+
+    pub fn model(self, model: &str) -> Self {
+        self.device_attribute("model", model)
+    }
+
+    pub fn vendor(self, vendor: &str) -> Self {
+        self.device_attribute("vendor", vendor)
+    }
+
+    pub fn serial(self, serial: &str) -> Self {
+        self.device_attribute("serial", serial)
+    }
+
+    pub fn wwid(self, wwid: &str) -> Self {
+        self.device_attribute("wwid", wwid)
+    }
+
+    /// Append a partition; partitions are named and numbered in order
+    pub fn partition(mut self, size_bytes: u64) -> Self {
+        self.partitions.push(FakePartition {
+            size_bytes,
+            attributes: BTreeMap::new(),
+        });
+        self
+    }
+
+    /// Set an attribute of the most recently added partition
+    pub fn partition_attribute(mut self, attribute: &str, value: &str) -> Self {
+        if let Some(partition) = self.partitions.last_mut() {
+            partition
+                .attributes
+                .insert(attribute.to_string(), value.to_string());
+        }
+        self
+    }
+
+    /// Kernel name of the n-th (1-based) partition
+    pub fn partition_name(&self, number: usize) -> String {
+        partition_name(&self.name, number)
+    }
+}
+
+/// Builder for a fake system tree
+#[derive(Debug, Clone, Default)]
+pub struct FixtureBuilder {
+    disks: Vec<FakeDisk>,
+    holders: Vec<FakeHolder>,
+    mounts: Vec<(String, String, String)>,
+    swaps: Vec<String>,
+    files: Vec<(PathBuf, Vec<u8>)>,
+    links: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Virtual block device stacked on fixture disks or partitions
+#[derive(Debug, Clone)]
+struct FakeHolder {
+    name: String,
+    dm_name: Option<String>,
+    dm_uuid: Option<String>,
+    slaves: Vec<String>,
+}
+
+impl FixtureBuilder {
+    pub fn new() -> Self {
+        FixtureBuilder::default()
+    }
+
+    pub fn disk(mut self, disk: FakeDisk) -> Self {
+        self.disks.push(disk);
+        self
+    }
+
+    /// Stack a device-mapper target (`dm-0`, opened as `/dev/mapper/<dm_name>`) on `slaves`
+    pub fn dm(mut self, name: &str, dm_name: &str, slaves: &[&str]) -> Self {
+        self.holders.push(FakeHolder {
+            name: name.to_string(),
+            dm_name: Some(dm_name.to_string()),
+            dm_uuid: None,
+            slaves: slaves.iter().map(|slave| slave.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Create a dm-multipath map (`dm-0` named `mpatha`) over several paths
+    /// (`sdb`, `sdc`) to the same LUN; the map takes the size of its first path
+    pub fn multipath(mut self, name: &str, dm_name: &str, paths: &[&str]) -> Self {
+        self.holders.push(FakeHolder {
+            name: name.to_string(),
+            dm_name: Some(dm_name.to_string()),
+            dm_uuid: Some(format!("mpath-{}", dm_name)),
+            slaves: paths.iter().map(|path| path.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Assemble an md array (`md0`) from `slaves`
+    pub fn md(mut self, name: &str, slaves: &[&str]) -> Self {
+        self.holders.push(FakeHolder {
+            name: name.to_string(),
+            dm_name: None,
+            dm_uuid: None,
+            slaves: slaves.iter().map(|slave| slave.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Record an active swap area in `/proc/swaps`; `source` is a kernel name
+    /// from the fixture (`sda2`) or a swap file path
+    pub fn swap(mut self, source: &str) -> Self {
+        self.swaps.push(source.to_string());
+        self
+    }
+
+    /// Record a mount in `/proc/self/mountinfo`; `source` is a kernel name
+    /// from the fixture (`sda1`) or any other mount source (`tmpfs`)
+    pub fn mount(mut self, source: &str, mount_point: &str, filesystem: &str) -> Self {
+        self.mounts.push((
+            source.to_string(),
+            mount_point.to_string(),
+            filesystem.to_string(),
+        ));
+        self
+    }
+
+    /// Write an arbitrary file relative to the fixture root (`etc/fstab`, ...)
+    pub fn file<P: AsRef<Path>, C: AsRef<[u8]>>(mut self, path: P, contents: C) -> Self {
+        self.files
+            .push((path.as_ref().to_path_buf(), contents.as_ref().to_vec()));
+        self
+    }
+
+    /// Create a symlink relative to the fixture root (`dev/disk/by-uuid/...`);
+    /// relative targets keep it inside the fixture
+    pub fn symlink<P: AsRef<Path>, T: AsRef<Path>>(mut self, path: P, target: T) -> Self {
+        self.links
+            .push((path.as_ref().to_path_buf(), target.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Lay out the tree in a fresh temporary directory
+    pub fn build(self) -> io::Result<SystemFixture> {
+        let dir = unique_temp_dir();
+        fs::create_dir_all(&dir)?;
+        let fixture = SystemFixture {
+            root: SystemRoot::new(&dir),
+            dir,
+        };
+
+        for sub_dir in ["sys/block", "sys/class/block", "proc/self", "dev", "etc"] {
+            fs::create_dir_all(fixture.dir.join(sub_dir))?;
+        }
+
+        let mut nodes = BTreeMap::new();
+        for (index, disk) in self.disks.iter().enumerate() {
+            write_disk(&fixture.dir, disk, index as u32, &mut nodes)?;
+        }
+        for (index, holder) in self.holders.iter().enumerate() {
+            write_holder(&fixture.dir, holder, index as u32, &mut nodes)?;
+        }
+
+        let mut mountinfo = String::new();
+        for (index, (source, mount_point, filesystem)) in self.mounts.iter().enumerate() {
+            let (device_number, source) = match nodes.get(source) {
+                Some(node) => node.clone(),
+                None => (format!("0:{}", 100 + index), source.clone()),
+            };
+            mountinfo.push_str(&format!(
+                "{} 1 {} / {} rw,relatime shared:{} - {} {} rw\n",
+                index + 20,
+                device_number,
+                mount_point,
+                index + 1,
+                filesystem,
+                source
+            ));
+        }
+        fs::write(fixture.root.mountinfo(), mountinfo)?;
+
+        let mut swaps = String::from("Filename\tType\tSize\tUsed\tPriority\n");
+        for (index, source) in self.swaps.iter().enumerate() {
+            let (kind, source) = match nodes.get(source) {
+                Some((_, source)) => ("partition", source.clone()),
+                None => ("file", source.clone()),
+            };
+            swaps.push_str(&format!(
+                "{}\t{}\t1048572\t0\t-{}\n",
+                source,
+                kind,
+                index + 2
+            ));
+        }
+        fs::write(fixture.root.swaps(), swaps)?;
+
+        for (path, contents) in &self.files {
+            let path = fixture.dir.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)?;
+        }
+        for (path, target) in &self.links {
+            let path = fixture.dir.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            symlink(target, path)?;
+        }
+
+        Ok(fixture)
+    }
+}
+
+/// Fake system tree in a temporary directory, removed on drop
+#[derive(Debug)]
+pub struct SystemFixture {
+    dir: PathBuf,
+    root: SystemRoot,
+}
+
+impl SystemFixture {
+    /// Root to pass to the `*_in` functions
+    pub fn root(&self) -> &SystemRoot {
+        &self.root
+    }
+
+    /// Temporary directory holding the tree
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for SystemFixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Device number and `/dev` path of every node written so far, by kernel name
+type Nodes = BTreeMap<String, (String, String)>;
+
+fn write_disk(root: &Path, disk: &FakeDisk, index: u32, nodes: &mut Nodes) -> io::Result<()> {
+    let (default_path, major) = default_device_path(&disk.name, index);
+    let device_path = disk.device_path.clone().unwrap_or(default_path);
+    let device_dir = root.join("sys/devices").join(&device_path);
+    let relative_disk_dir = format!("devices/{}/block/{}", device_path, disk.name);
+    let disk_dir = root.join("sys").join(&relative_disk_dir);
+    fs::create_dir_all(&disk_dir)?;
+    fs::create_dir_all(disk_dir.join("holders"))?;
+    fs::create_dir_all(disk_dir.join("slaves"))?;
+    symlink("../..", disk_dir.join("device"))?;
+    symlink(
+        format!("../{}", relative_disk_dir),
+        root.join("sys/block").join(&disk.name),
+    )?;
+    symlink(
+        format!("../../{}", relative_disk_dir),
+        root.join("sys/class/block").join(&disk.name),
+    )?;
+    fs::write(root.join("dev").join(&disk.name), b"")?;
+
+    let minor = index * 16;
+    let device_number = format!("{}:{}", major, minor);
+    let size_sectors = (disk.size_bytes / 512).to_string();
+    for (attribute, value) in [("dev", device_number.as_str()), ("size", &size_sectors)] {
+        if !disk.removed.iter().any(|removed| removed == attribute) {
+            write_attribute(&disk_dir, attribute, value)?;
+        }
+    }
+    for (attribute, value) in &disk.attributes {
+        write_attribute(&disk_dir, attribute, value)?;
+    }
+    for (attribute, value) in &disk.device_attributes {
+        write_attribute(&device_dir, attribute, value)?;
+    }
+    nodes.insert(
+        disk.name.clone(),
+        (device_number, format!("/dev/{}", disk.name)),
+    );
+
+    let mut start_sector = 2048;
+    for (offset, partition) in disk.partitions.iter().enumerate() {
+        let number = offset + 1;
+        let name = partition_name(&disk.name, number);
+        let partition_dir = disk_dir.join(&name);
+        let device_number = format!("{}:{}", major, minor + number as u32);
+        let size_sectors = partition.size_bytes / 512;
+
+        fs::create_dir_all(partition_dir.join("holders"))?;
+        write_attribute(&partition_dir, "partition", &number.to_string())?;
+        write_attribute(&partition_dir, "dev", &device_number)?;
+        write_attribute(&partition_dir, "size", &size_sectors.to_string())?;
+        write_attribute(&partition_dir, "start", &start_sector.to_string())?;
+        write_attribute(&partition_dir, "ro", "0")?;
+        for (attribute, value) in &partition.attributes {
+            write_attribute(&partition_dir, attribute, value)?;
+        }
+        symlink(
+            format!("../../{}/{}", relative_disk_dir, name),
+            root.join("sys/class/block").join(&name),
+        )?;
+        fs::write(root.join("dev").join(&name), b"")?;
+
+        let source = format!("/dev/{}", name);
+        nodes.insert(name, (device_number, source));
+        start_sector += size_sectors;
+    }
+
+    Ok(())
+}
+
+fn write_holder(root: &Path, holder: &FakeHolder, index: u32, nodes: &mut Nodes) -> io::Result<()> {
+    let relative_dir = format!("devices/virtual/block/{}", holder.name);
+    let dir = root.join("sys").join(&relative_dir);
+    fs::create_dir_all(dir.join("holders"))?;
+    fs::create_dir_all(dir.join("slaves"))?;
+    symlink(
+        format!("../{}", relative_dir),
+        root.join("sys/block").join(&holder.name),
+    )?;
+    symlink(
+        format!("../../{}", relative_dir),
+        root.join("sys/class/block").join(&holder.name),
+    )?;
+    fs::write(root.join("dev").join(&holder.name), b"")?;
+
+    let (device_number, source) = match &holder.dm_name {
+        Some(dm_name) => {
+            write_attribute(&dir, "dm/name", dm_name)?;
+            fs::create_dir_all(root.join("dev/mapper"))?;
+            symlink(
+                format!("../{}", holder.name),
+                root.join("dev/mapper").join(dm_name),
+            )?;
+            (format!("253:{}", index), format!("/dev/mapper/{}", dm_name))
+        }
+        None => {
+            write_attribute(&dir, "md/level", "raid1")?;
+            (format!("9:{}", index), format!("/dev/{}", holder.name))
+        }
+    };
+    if let Some(dm_uuid) = &holder.dm_uuid {
+        write_attribute(&dir, "dm/uuid", dm_uuid)?;
+    }
+    let size = match (&holder.dm_uuid, holder.slaves.first()) {
+        (Some(_), Some(path)) => {
+            fs::read_to_string(root.join("sys/class/block").join(path).join("size"))?
+        }
+        _ => "0".to_string(),
+    };
+    write_attribute(&dir, "dev", &device_number)?;
+    write_attribute(&dir, "size", size.trim())?;
+    write_attribute(&dir, "ro", "0")?;
+
+    for slave in &holder.slaves {
+        let slave_dir = root.join("sys/class/block").join(slave);
+        symlink(&slave_dir, dir.join("slaves").join(slave))?;
+        symlink(&dir, slave_dir.join("holders").join(&holder.name))?;
+    }
+    nodes.insert(holder.name.clone(), (device_number, source));
+
+    Ok(())
+}
+
+fn write_attribute(dir: &Path, attribute: &str, value: &str) -> io::Result<()> {
+    let path = dir.join(attribute);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, format!("{}\n", value))
+}
+
+/// Typical sysfs device path and block major number for the n-th disk
+fn default_device_path(name: &str, index: u32) -> (String, u32) {
+    if name.starts_with("nvme") {
+        (
+            format!("pci0000:00/0000:00:1d.0/0000:3d:00.0/nvme/nvme{}", index),
+            259,
+        )
+    } else if name.starts_with("mmcblk") {
+        (
+            format!("platform/mmc{0}/mmc_host/mmc{0}/mmc{0}:0001", index),
+            179,
+        )
+    } else if name.starts_with("xvd") {
+        (format!("vbd-{}", 51712 + index * 16), 202)
+    } else if name.starts_with("vd") {
+        (format!("pci0000:00/0000:00:04.0/virtio{}", index), 252)
+    } else {
+        (
+            format!(
+                "pci0000:00/0000:00:1f.2/ata{}/host{1}/target{1}:0:0/{1}:0:0:0",
+                index + 1,
+                index
+            ),
+            8,
+        )
+    }
+}
+
+fn partition_name(disk: &str, number: usize) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", disk, number)
+    } else {
+        format!("{}{}", disk, number)
+    }
+}
+
+fn flag(value: bool) -> &'static str {
+    if value {
+        "1"
+    } else {
+        "0"
+    }
+}
+
+fn unique_temp_dir() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or_default();
+    std::env::temp_dir().join(format!(
+        "auto_mount_fixture_{}_{}_{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst),
+        nanos
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_layout() {
+        let fixture = FixtureBuilder::new()
+            .disk(
+                FakeDisk::new("sdb")
+                    .size_bytes(4 << 30)
+                    .model("WDC WD40EFRX")
+                    .partition(1 << 30),
+            )
+            .mount("sdb1", "/data", "ext4")
+            .file("etc/fstab", "# empty\n")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        assert!(root.sys_block().join("sdb/queue/rotational").exists());
+        assert_eq!(
+            fs::read_to_string(root.sys_block().join("sdb/device/model")).unwrap(),
+            "WDC WD40EFRX\n"
+        );
+        assert!(root.sys_class_block().join("sdb1/partition").exists());
+        assert!(root.dev_node("sdb1").exists());
+        assert!(root.fstab().exists());
+
+        let mountinfo = fs::read_to_string(root.mountinfo()).unwrap();
+        assert!(mountinfo.contains(" 8:1 / /data "));
+        assert!(mountinfo.contains(" - ext4 /dev/sdb1 rw"));
+
+        let path = fixture.path().to_path_buf();
+        drop(fixture);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_holder_layout() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").partition(1 << 30).partition(1 << 30))
+            .dm("dm-0", "vg0-data", &["sda1"])
+            .mount("dm-0", "/data", "xfs")
+            .swap("sda2")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        assert!(root.sys_class_block().join("sda1/holders/dm-0").exists());
+        assert!(root.sys_block().join("dm-0/slaves/sda1/partition").exists());
+        assert!(root.dev_path("/dev/mapper/vg0-data").exists());
+
+        let mountinfo = fs::read_to_string(root.mountinfo()).unwrap();
+        assert!(mountinfo.contains(" 253:0 / /data "));
+        assert!(mountinfo.contains(" - xfs /dev/mapper/vg0-data rw"));
+        let swaps = fs::read_to_string(root.swaps()).unwrap();
+        assert!(swaps
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("/dev/sda2\tpartition"));
+    }
+
+    #[test]
+    fn test_partition_naming() {
+        assert_eq!(FakeDisk::new("sdaa").partition_name(2), "sdaa2");
+        assert_eq!(FakeDisk::new("nvme0n1").partition_name(1), "nvme0n1p1");
+    }
+}
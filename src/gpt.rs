@@ -0,0 +1,560 @@
+//! GPT module for auto_mount
+//!
+//! This module writes GUID partition tables (protective MBR, primary and backup
+//! headers, partition entries) to any seekable target, without calling parted
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+const REVISION: u32 = 0x0001_0000;
+const HEADER_SIZE: u32 = 92;
+/// Entries every header reserves room for, whatever is in use
+const ENTRY_COUNT: u32 = 128;
+const ENTRY_SIZE: u32 = 128;
+/// UTF-16 code units in a partition name
+const NAME_UNITS: usize = 36;
+const PROTECTIVE_MBR_TYPE: u8 = 0xee;
+/// Bytes of boot code in front of the disk signature of an MBR
+pub(crate) const BOOT_CODE_BYTES: usize = 440;
+
+/// Errors that can occur while building or writing a GPT
+#[derive(Debug, thiserror::Error)]
+pub enum GptError {
+    #[error("Unsupported sector size: {0}")]
+    InvalidSectorSize(u64),
+    #[error("Disk of {0} sectors is too small for a GPT")]
+    DiskTooSmall(u64),
+    #[error(
+        "Partition {first}-{last} lies outside the usable sectors {first_usable}-{last_usable}"
+    )]
+    OutOfRange {
+        first: u64,
+        last: u64,
+        first_usable: u64,
+        last_usable: u64,
+    },
+    #[error("Partition {first}-{last} overlaps partition {other}")]
+    Overlap { first: u64, last: u64, other: u32 },
+    #[error("A GPT holds at most 128 partitions")]
+    TooManyPartitions,
+    #[error("Partition name longer than 36 UTF-16 units: {0}")]
+    NameTooLong(String),
+    #[error("Invalid GUID: {0}")]
+    InvalidGuid(String),
+    #[error("IO error: {0}")]
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for GptError {
+    fn from(error: std::io::Error) -> Self {
+        GptError::IoError(error)
+    }
+}
+
+/// GUID kept in its on-disk byte order (first three fields little-endian)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Guid([u8; 16]);
+
+impl Guid {
+    pub const ZERO: Guid = Guid([0; 16]);
+    pub const LINUX_FILESYSTEM: Guid = Guid::from_fields(
+        0x0fc6_3daf,
+        0x8483,
+        0x4772,
+        [0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d, 0xe4],
+    );
+    pub const LINUX_SWAP: Guid = Guid::from_fields(
+        0x0657_fd6d,
+        0xa4ab,
+        0x43c4,
+        [0x84, 0xe5, 0x09, 0x33, 0xc8, 0x4b, 0x4f, 0x4f],
+    );
+    pub const LINUX_LVM: Guid = Guid::from_fields(
+        0xe6d6_d379,
+        0xf507,
+        0x44c2,
+        [0xa2, 0x3c, 0x23, 0x8f, 0x2a, 0x3d, 0xf9, 0x28],
+    );
+    pub const LINUX_RAID: Guid = Guid::from_fields(
+        0xa19d_880f,
+        0x05fc,
+        0x4d3b,
+        [0xa0, 0x06, 0x74, 0x3f, 0x0f, 0x84, 0x91, 0x1e],
+    );
+    pub const EFI_SYSTEM: Guid = Guid::from_fields(
+        0xc12a_7328,
+        0xf81f,
+        0x11d2,
+        [0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b],
+    );
+    pub const MICROSOFT_BASIC_DATA: Guid = Guid::from_fields(
+        0xebd0_a0a2,
+        0xb9e5,
+        0x4433,
+        [0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99, 0xc7],
+    );
+
+    /// Build a GUID from the fields of its text form
+    pub const fn from_fields(time_low: u32, time_mid: u16, time_high: u16, rest: [u8; 8]) -> Guid {
+        let low = time_low.to_le_bytes();
+        let mid = time_mid.to_le_bytes();
+        let high = time_high.to_le_bytes();
+        Guid([
+            low[0], low[1], low[2], low[3], mid[0], mid[1], high[0], high[1], rest[0], rest[1],
+            rest[2], rest[3], rest[4], rest[5], rest[6], rest[7],
+        ])
+    }
+
+    /// GUID from its 16 on-disk bytes
+    pub fn from_bytes(bytes: [u8; 16]) -> Guid {
+        Guid(bytes)
+    }
+
+    /// The 16 on-disk bytes
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    /// Random (version 4) GUID read from the kernel's random pool
+    pub fn random() -> Result<Guid, GptError> {
+        let mut bytes = [0; 16];
+        File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+        bytes[7] = (bytes[7] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Ok(Guid(bytes))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Guid::ZERO
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+        b[10..]
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02X}", byte))
+    }
+}
+
+impl FromStr for Guid {
+    type Err = GptError;
+
+    fn from_str(text: &str) -> Result<Guid, GptError> {
+        let invalid = || GptError::InvalidGuid(text.to_string());
+        let fields: Vec<&str> = text.split('-').collect();
+        let lengths: Vec<usize> = fields.iter().map(|field| field.len()).collect();
+        if lengths != [8, 4, 4, 4, 12] {
+            return Err(invalid());
+        }
+
+        let hex: String = fields.concat();
+        let mut raw = [0u8; 16];
+        for (index, byte) in raw.iter_mut().enumerate() {
+            *byte = hex
+                .get(index * 2..index * 2 + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(invalid)?;
+        }
+
+        let mut rest = [0u8; 8];
+        rest.copy_from_slice(&raw[8..]);
+        Ok(Guid::from_fields(
+            u32::from_be_bytes([raw[0], raw[1], raw[2], raw[3]]),
+            u16::from_be_bytes([raw[4], raw[5]]),
+            u16::from_be_bytes([raw[6], raw[7]]),
+            rest,
+        ))
+    }
+}
+
+/// One partition entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptPartition {
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    /// Last sector of the partition, inclusive
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+impl GptPartition {
+    pub fn sectors(&self) -> u64 {
+        self.last_lba - self.first_lba + 1
+    }
+}
+
+/// A GUID partition table for a disk of a given geometry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptDisk {
+    pub disk_guid: Guid,
+    pub sector_size: u64,
+    pub total_sectors: u64,
+    /// Partitions in entry order; entry `n` is partition number `n + 1`
+    pub partitions: Vec<GptPartition>,
+    /// Boot code for the first 440 bytes of the protective MBR; zeros when empty
+    pub boot_code: Vec<u8>,
+}
+
+impl GptDisk {
+    /// Empty table with a random disk GUID
+    pub fn new(total_sectors: u64, sector_size: u64) -> Result<GptDisk, GptError> {
+        Self::with_guid(total_sectors, sector_size, Guid::random()?)
+    }
+
+    /// Empty table with the given disk GUID
+    pub fn with_guid(
+        total_sectors: u64,
+        sector_size: u64,
+        disk_guid: Guid,
+    ) -> Result<GptDisk, GptError> {
+        if sector_size < 512 || !sector_size.is_power_of_two() {
+            return Err(GptError::InvalidSectorSize(sector_size));
+        }
+        let disk = GptDisk {
+            disk_guid,
+            sector_size,
+            total_sectors,
+            partitions: Vec::new(),
+            boot_code: Vec::new(),
+        };
+        // Room for the MBR, both headers, both entry arrays and one usable sector
+        if total_sectors < 4 + 2 * disk.entry_sectors() {
+            return Err(GptError::DiskTooSmall(total_sectors));
+        }
+        Ok(disk)
+    }
+
+    /// Sectors taken by one copy of the partition entry array
+    pub fn entry_sectors(&self) -> u64 {
+        let bytes = u64::from(ENTRY_COUNT * ENTRY_SIZE);
+        (bytes + self.sector_size - 1) / self.sector_size
+    }
+
+    pub fn first_usable_lba(&self) -> u64 {
+        2 + self.entry_sectors()
+    }
+
+    pub fn last_usable_lba(&self) -> u64 {
+        self.total_sectors - 2 - self.entry_sectors()
+    }
+
+    /// Add a partition with a random unique GUID and return its number (1-based)
+    pub fn add_partition(
+        &mut self,
+        type_guid: Guid,
+        first_lba: u64,
+        last_lba: u64,
+        name: &str,
+    ) -> Result<u32, GptError> {
+        self.push(GptPartition {
+            type_guid,
+            unique_guid: Guid::random()?,
+            first_lba,
+            last_lba,
+            attributes: 0,
+            name: name.to_string(),
+        })
+    }
+
+    /// Add a fully described partition after checking it fits, and return its number
+    pub fn push(&mut self, partition: GptPartition) -> Result<u32, GptError> {
+        let (first, last) = (partition.first_lba, partition.last_lba);
+        if first > last || first < self.first_usable_lba() || last > self.last_usable_lba() {
+            return Err(GptError::OutOfRange {
+                first,
+                last,
+                first_usable: self.first_usable_lba(),
+                last_usable: self.last_usable_lba(),
+            });
+        }
+        if let Some(index) = self
+            .partitions
+            .iter()
+            .position(|other| first <= other.last_lba && other.first_lba <= last)
+        {
+            return Err(GptError::Overlap {
+                first,
+                last,
+                other: index as u32 + 1,
+            });
+        }
+        if self.partitions.len() >= ENTRY_COUNT as usize {
+            return Err(GptError::TooManyPartitions);
+        }
+        if partition.name.encode_utf16().count() > NAME_UNITS {
+            return Err(GptError::NameTooLong(partition.name));
+        }
+
+        self.partitions.push(partition);
+        Ok(self.partitions.len() as u32)
+    }
+
+    /// Write the protective MBR, both headers and both entry arrays
+    pub fn write<W: Write + Seek>(&self, target: &mut W) -> Result<(), GptError> {
+        let entries = self.entry_array();
+        let backup_lba = self.total_sectors - 1;
+        let backup_entries_lba = backup_lba - self.entry_sectors();
+
+        self.write_sector(target, 0, &self.protective_mbr())?;
+        self.write_sector(target, 1, &self.header(1, backup_lba, 2, &entries))?;
+        target.seek(SeekFrom::Start(2 * self.sector_size))?;
+        target.write_all(&entries)?;
+        target.seek(SeekFrom::Start(backup_entries_lba * self.sector_size))?;
+        target.write_all(&entries)?;
+        let backup = self.header(backup_lba, 1, backup_entries_lba, &entries);
+        self.write_sector(target, backup_lba, &backup)?;
+        target.flush()?;
+        Ok(())
+    }
+
+    fn write_sector<W: Write + Seek>(
+        &self,
+        target: &mut W,
+        lba: u64,
+        data: &[u8],
+    ) -> Result<(), GptError> {
+        let mut sector = vec![0; self.sector_size as usize];
+        sector[..data.len()].copy_from_slice(data);
+        target.seek(SeekFrom::Start(lba * self.sector_size))?;
+        target.write_all(&sector)?;
+        Ok(())
+    }
+
+    /// A single 0xEE partition covering the disk, so MBR-only tools keep off it
+    fn protective_mbr(&self) -> [u8; 512] {
+        let mut mbr = [0; 512];
+        let boot_code = &self.boot_code[..self.boot_code.len().min(BOOT_CODE_BYTES)];
+        mbr[..boot_code.len()].copy_from_slice(boot_code);
+        let sectors = (self.total_sectors - 1).min(u64::from(u32::MAX)) as u32;
+        let entry = &mut mbr[446..462];
+        entry[1..4].copy_from_slice(&[0x00, 0x02, 0x00]);
+        entry[4] = PROTECTIVE_MBR_TYPE;
+        entry[5..8].copy_from_slice(&[0xff, 0xff, 0xff]);
+        entry[8..12].copy_from_slice(&1u32.to_le_bytes());
+        entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+        mbr[510..512].copy_from_slice(&[0x55, 0xaa]);
+        mbr
+    }
+
+    fn header(&self, lba: u64, backup_lba: u64, entries_lba: u64, entries: &[u8]) -> Vec<u8> {
+        let mut header = vec![0; HEADER_SIZE as usize];
+        header[0..8].copy_from_slice(SIGNATURE);
+        header[8..12].copy_from_slice(&REVISION.to_le_bytes());
+        header[12..16].copy_from_slice(&HEADER_SIZE.to_le_bytes());
+        header[24..32].copy_from_slice(&lba.to_le_bytes());
+        header[32..40].copy_from_slice(&backup_lba.to_le_bytes());
+        header[40..48].copy_from_slice(&self.first_usable_lba().to_le_bytes());
+        header[48..56].copy_from_slice(&self.last_usable_lba().to_le_bytes());
+        header[56..72].copy_from_slice(self.disk_guid.as_bytes());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&ENTRY_COUNT.to_le_bytes());
+        header[84..88].copy_from_slice(&ENTRY_SIZE.to_le_bytes());
+        header[88..92].copy_from_slice(&crc32fast::hash(entries).to_le_bytes());
+        // The header CRC is taken with its own field zeroed
+        let crc = crc32fast::hash(&header);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        header
+    }
+
+    fn entry_array(&self) -> Vec<u8> {
+        let mut entries = vec![0; (ENTRY_COUNT * ENTRY_SIZE) as usize];
+        for (index, partition) in self.partitions.iter().enumerate() {
+            let offset = index * ENTRY_SIZE as usize;
+            let entry = &mut entries[offset..offset + ENTRY_SIZE as usize];
+            entry[0..16].copy_from_slice(partition.type_guid.as_bytes());
+            entry[16..32].copy_from_slice(partition.unique_guid.as_bytes());
+            entry[32..40].copy_from_slice(&partition.first_lba.to_le_bytes());
+            entry[40..48].copy_from_slice(&partition.last_lba.to_le_bytes());
+            entry[48..56].copy_from_slice(&partition.attributes.to_le_bytes());
+            for (unit, code) in partition.name.encode_utf16().enumerate() {
+                entry[56 + unit * 2..58 + unit * 2].copy_from_slice(&code.to_le_bytes());
+            }
+        }
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signature::probe_signatures;
+    use std::io::Cursor;
+
+    const MIB: u64 = 1 << 20;
+
+    fn image(bytes: u64) -> Cursor<Vec<u8>> {
+        Cursor::new(vec![0; bytes as usize])
+    }
+
+    fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn le_u64(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// Check a header's own CRC and return it with the CRC field zeroed
+    fn checked_header(sector: &[u8]) -> Vec<u8> {
+        let mut header = sector[..HEADER_SIZE as usize].to_vec();
+        let crc = le_u32(&header, 16);
+        header[16..20].copy_from_slice(&[0; 4]);
+        assert_eq!(crc32fast::hash(&header), crc);
+        header
+    }
+
+    #[test]
+    fn test_guid_text_form() {
+        assert_eq!(
+            Guid::LINUX_FILESYSTEM.to_string(),
+            "0FC63DAF-8483-4772-8E79-3D69D8477DE4"
+        );
+        assert_eq!(
+            &Guid::LINUX_FILESYSTEM.as_bytes()[..4],
+            &[0xaf, 0x3d, 0xc6, 0x0f]
+        );
+        let parsed: Guid = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b".parse().unwrap();
+        assert_eq!(parsed, Guid::EFI_SYSTEM);
+        assert!("c12a7328-f81f-11d2-ba4b".parse::<Guid>().is_err());
+        assert!("g12a7328-f81f-11d2-ba4b-00a0c93ec93b"
+            .parse::<Guid>()
+            .is_err());
+
+        let random = Guid::random().unwrap();
+        assert_ne!(random, Guid::random().unwrap());
+        assert_eq!(random.to_string().as_bytes()[14], b'4');
+        assert_eq!(random.to_string().parse::<Guid>().unwrap(), random);
+    }
+
+    #[test]
+    fn test_write_gpt_image() {
+        let sectors = 64 * MIB / 512;
+        let mut disk = GptDisk::new(sectors, 512).unwrap();
+        assert_eq!(disk.first_usable_lba(), 34);
+        assert_eq!(disk.last_usable_lba(), sectors - 34);
+        disk.add_partition(Guid::LINUX_FILESYSTEM, 2048, 34815, "data")
+            .unwrap();
+        disk.add_partition(Guid::LINUX_SWAP, 34816, sectors - 34, "")
+            .unwrap();
+        let mut target = image(64 * MIB);
+        disk.write(&mut target).unwrap();
+        let bytes = target.into_inner();
+
+        // Protective MBR
+        assert_eq!(&bytes[510..512], &[0x55, 0xaa]);
+        assert_eq!(bytes[446 + 4], 0xee);
+        assert_eq!(le_u32(&bytes, 446 + 8), 1);
+        assert_eq!(le_u32(&bytes, 446 + 12), (sectors - 1) as u32);
+
+        // Primary header and entries
+        let primary = checked_header(&bytes[512..1024]);
+        assert_eq!(&primary[0..8], SIGNATURE);
+        assert_eq!(le_u64(&primary, 24), 1);
+        assert_eq!(le_u64(&primary, 32), sectors - 1);
+        assert_eq!(le_u64(&primary, 72), 2);
+        assert_eq!(&primary[56..72], disk.disk_guid.as_bytes());
+        let entries = &bytes[1024..1024 + 16384];
+        assert_eq!(crc32fast::hash(entries), le_u32(&primary, 88));
+        assert_eq!(&entries[0..16], Guid::LINUX_FILESYSTEM.as_bytes());
+        assert_eq!(&entries[16..32], disk.partitions[0].unique_guid.as_bytes());
+        assert_eq!(le_u64(entries, 32), 2048);
+        assert_eq!(le_u64(entries, 40), 34815);
+        assert_eq!(&entries[56..64], b"d\0a\0t\0a\0");
+        assert_eq!(&entries[128..144], Guid::LINUX_SWAP.as_bytes());
+        assert_ne!(
+            disk.partitions[0].unique_guid,
+            disk.partitions[1].unique_guid
+        );
+
+        // Backup header points back at the primary and at its own entry copy
+        let backup_offset = ((sectors - 1) * 512) as usize;
+        let backup = checked_header(&bytes[backup_offset..]);
+        assert_eq!(le_u64(&backup, 24), sectors - 1);
+        assert_eq!(le_u64(&backup, 32), 1);
+        let backup_entries = le_u64(&backup, 72);
+        assert_eq!(backup_entries, sectors - 33);
+        let start = (backup_entries * 512) as usize;
+        assert_eq!(&bytes[start..start + 16384], entries);
+        assert_eq!(&backup[40..56], &primary[40..56]);
+    }
+
+    #[test]
+    fn test_write_gpt_4k_sectors() {
+        let dir = std::env::temp_dir().join(format!("auto_mount_gpt_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("disk.img");
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        file.set_len(16 * MIB).unwrap();
+
+        let disk = GptDisk::new(16 * MIB / 4096, 4096).unwrap();
+        assert_eq!(disk.entry_sectors(), 4);
+        assert_eq!(disk.first_usable_lba(), 6);
+        disk.write(&mut file).unwrap();
+        drop(file);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let primary = checked_header(&bytes[4096..]);
+        assert_eq!(le_u64(&primary, 72), 2);
+        let backup = checked_header(&bytes[bytes.len() - 4096..]);
+        assert_eq!(le_u64(&backup, 24), 16 * MIB / 4096 - 1);
+        assert_eq!(
+            probe_signatures(&path).unwrap(),
+            vec![crate::Signature::Gpt { partitions: 0 }]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_bad_partitions() {
+        let mut disk = GptDisk::with_guid(4096, 512, Guid::ZERO).unwrap();
+        disk.add_partition(Guid::LINUX_FILESYSTEM, 2048, 3000, "")
+            .unwrap();
+
+        assert!(matches!(
+            disk.add_partition(Guid::LINUX_FILESYSTEM, 10, 100, ""),
+            Err(GptError::OutOfRange { .. })
+        ));
+        assert!(matches!(
+            disk.add_partition(Guid::LINUX_FILESYSTEM, 3001, 4063, ""),
+            Err(GptError::OutOfRange { .. })
+        ));
+        assert!(matches!(
+            disk.add_partition(Guid::LINUX_FILESYSTEM, 1000, 2048, ""),
+            Err(GptError::Overlap { other: 1, .. })
+        ));
+        assert!(matches!(
+            disk.add_partition(Guid::LINUX_FILESYSTEM, 34, 100, &"x".repeat(37)),
+            Err(GptError::NameTooLong(_))
+        ));
+        assert!(matches!(
+            GptDisk::new(60, 512),
+            Err(GptError::DiskTooSmall(60))
+        ));
+        assert!(matches!(
+            GptDisk::new(4096, 520),
+            Err(GptError::InvalidSectorSize(520))
+        ));
+    }
+}
@@ -0,0 +1,359 @@
+//! Disk health module for auto_mount
+//!
+//! This module reads SMART data through `smartctl --json` and rejects disks
+//! that fail configurable health thresholds before they are provisioned
+
+use crate::{BlockDevice, FilterReport, RejectReason, RejectedDevice};
+use serde_json::Value;
+use std::fmt;
+use std::process::Command;
+
+/// smartctl exit status bits meaning no usable data was returned
+/// (command line did not parse, device open failed)
+const SMARTCTL_FATAL_BITS: i32 = 0b11;
+
+/// Errors that can occur while reading disk health
+#[derive(Debug, thiserror::Error)]
+pub enum HealthError {
+    #[error("smartctl failed for {device}: {message}")]
+    SmartctlFailed { device: String, message: String },
+    #[error("Invalid smartctl JSON: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+    #[error("IO error: {0}")]
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for HealthError {
+    fn from(error: std::io::Error) -> Self {
+        HealthError::IoError(error)
+    }
+}
+
+/// Health figures reported by `smartctl`; a figure the device doesn't report is `None`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskHealth {
+    /// Overall SMART self-assessment
+    pub passed: Option<bool>,
+    /// Reallocated sectors (ATA attribute 5) or grown defects (SCSI)
+    pub reallocated_sectors: Option<u64>,
+    /// Sectors waiting to be remapped (ATA attribute 197)
+    pub pending_sectors: Option<u64>,
+    pub power_on_hours: Option<u64>,
+}
+
+/// Limits a disk must stay within to be provisioned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthThresholds {
+    /// Reject disks whose SMART self-assessment failed
+    pub require_passed: bool,
+    pub max_reallocated_sectors: u64,
+    pub max_pending_sectors: u64,
+    /// Reject disks older than this; no limit when `None`
+    pub max_power_on_hours: Option<u64>,
+    /// Accept disks `smartctl` can't read (virtual disks, unknown USB bridges)
+    pub allow_unavailable: bool,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            require_passed: true,
+            max_reallocated_sectors: 0,
+            max_pending_sectors: 0,
+            max_power_on_hours: None,
+            allow_unavailable: false,
+        }
+    }
+}
+
+/// Why a disk failed the health check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthProblem {
+    /// The SMART self-assessment failed
+    Failed,
+    ReallocatedSectors {
+        count: u64,
+        max: u64,
+    },
+    PendingSectors {
+        count: u64,
+        max: u64,
+    },
+    PowerOnHours {
+        hours: u64,
+        max: u64,
+    },
+    /// smartctl couldn't read the disk
+    Unavailable(String),
+}
+
+impl fmt::Display for HealthProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthProblem::Failed => write!(f, "SMART overall health check failed"),
+            HealthProblem::ReallocatedSectors { count, max } => {
+                write!(f, "{} reallocated sectors (max {})", count, max)
+            }
+            HealthProblem::PendingSectors { count, max } => {
+                write!(f, "{} pending sectors (max {})", count, max)
+            }
+            HealthProblem::PowerOnHours { hours, max } => {
+                write!(f, "{} power-on hours (max {})", hours, max)
+            }
+            HealthProblem::Unavailable(message) => write!(f, "SMART data unavailable: {}", message),
+        }
+    }
+}
+
+impl DiskHealth {
+    /// Every threshold this disk exceeds; empty when it is healthy enough
+    pub fn problems(&self, thresholds: &HealthThresholds) -> Vec<HealthProblem> {
+        let mut problems = Vec::new();
+        if thresholds.require_passed && self.passed == Some(false) {
+            problems.push(HealthProblem::Failed);
+        }
+        if let Some(count) = self.reallocated_sectors {
+            if count > thresholds.max_reallocated_sectors {
+                problems.push(HealthProblem::ReallocatedSectors {
+                    count,
+                    max: thresholds.max_reallocated_sectors,
+                });
+            }
+        }
+        if let Some(count) = self.pending_sectors {
+            if count > thresholds.max_pending_sectors {
+                problems.push(HealthProblem::PendingSectors {
+                    count,
+                    max: thresholds.max_pending_sectors,
+                });
+            }
+        }
+        if let (Some(hours), Some(max)) = (self.power_on_hours, thresholds.max_power_on_hours) {
+            if hours > max {
+                problems.push(HealthProblem::PowerOnHours { hours, max });
+            }
+        }
+        problems
+    }
+}
+
+/// Read the health of a block device with `smartctl --json`
+pub fn read_disk_health(device: &BlockDevice) -> Result<DiskHealth, HealthError> {
+    // smartctl can't see through dm-multipath; ask one of the paths instead
+    let path = device
+        .multipath_paths
+        .first()
+        .map(|path| format!("/dev/{}", path))
+        .unwrap_or_else(|| device.path.clone());
+
+    let output = Command::new("sudo")
+        .args(["smartctl", "--json", "-H", "-A", "-i", &path])
+        .output()?;
+
+    // The exit status is a bit mask; only the low bits mean there is no data
+    let status = output.status.code().unwrap_or(SMARTCTL_FATAL_BITS);
+    if status & SMARTCTL_FATAL_BITS != 0 {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let message = smartctl_messages(&stdout)
+            .unwrap_or_else(|| String::from_utf8_lossy(&output.stderr).trim().to_string());
+        return Err(HealthError::SmartctlFailed {
+            device: path,
+            message,
+        });
+    }
+
+    parse_smartctl_json(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse the output of `smartctl --json -H -A` for ATA, NVMe and SCSI devices
+pub fn parse_smartctl_json(json: &str) -> Result<DiskHealth, HealthError> {
+    let value: Value = serde_json::from_str(json)?;
+
+    let ata_attribute = |id: u64| {
+        value["ata_smart_attributes"]["table"]
+            .as_array()?
+            .iter()
+            .find(|attribute| attribute["id"].as_u64() == Some(id))?["raw"]["value"]
+            .as_u64()
+    };
+
+    Ok(DiskHealth {
+        passed: value["smart_status"]["passed"].as_bool(),
+        reallocated_sectors: ata_attribute(5).or_else(|| value["scsi_grown_defect_list"].as_u64()),
+        pending_sectors: ata_attribute(197),
+        power_on_hours: value["power_on_time"]["hours"]
+            .as_u64()
+            .or_else(|| value["nvme_smart_health_information_log"]["power_on_hours"].as_u64()),
+    })
+}
+
+/// Move accepted disks that fail the thresholds to the rejected list
+pub fn check_health(report: FilterReport, thresholds: &HealthThresholds) -> FilterReport {
+    check_health_with(report, thresholds, read_disk_health)
+}
+
+/// [`check_health`] with the health reader supplied by the caller
+pub(crate) fn check_health_with<F>(
+    report: FilterReport,
+    thresholds: &HealthThresholds,
+    read_health: F,
+) -> FilterReport
+where
+    F: Fn(&BlockDevice) -> Result<DiskHealth, HealthError>,
+{
+    let mut checked = FilterReport {
+        accepted: Vec::new(),
+        rejected: report.rejected,
+    };
+
+    for device in report.accepted {
+        let problems = match read_health(&device) {
+            Ok(health) => health.problems(thresholds),
+            Err(_) if thresholds.allow_unavailable => Vec::new(),
+            Err(error) => vec![HealthProblem::Unavailable(error.to_string())],
+        };
+        if problems.is_empty() {
+            checked.accepted.push(device);
+        } else {
+            checked.rejected.push(RejectedDevice {
+                device,
+                reason: RejectReason::Unhealthy(problems),
+            });
+        }
+    }
+
+    checked
+}
+
+/// Error messages smartctl put in its JSON output, if any
+fn smartctl_messages(stdout: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(stdout).ok()?;
+    let messages: Vec<&str> = value["smartctl"]["messages"]
+        .as_array()?
+        .iter()
+        .filter_map(|message| message["string"].as_str())
+        .collect();
+    (!messages.is_empty()).then(|| messages.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ATA_HEALTHY: &str = include_str!("../testdata/smartctl/ata_healthy.json");
+    const ATA_FAILING: &str = include_str!("../testdata/smartctl/ata_failing.json");
+    const NVME_HEALTHY: &str = include_str!("../testdata/smartctl/nvme_healthy.json");
+    const SCSI_GROWN_DEFECTS: &str = include_str!("../testdata/smartctl/scsi_grown_defects.json");
+    const USB_UNSUPPORTED: &str = include_str!("../testdata/smartctl/usb_unsupported.json");
+
+    #[test]
+    fn test_parse_ata() {
+        assert_eq!(
+            parse_smartctl_json(ATA_HEALTHY).unwrap(),
+            DiskHealth {
+                passed: Some(true),
+                reallocated_sectors: Some(0),
+                pending_sectors: Some(0),
+                power_on_hours: Some(19466),
+            }
+        );
+        assert_eq!(
+            parse_smartctl_json(ATA_FAILING).unwrap(),
+            DiskHealth {
+                passed: Some(false),
+                reallocated_sectors: Some(2792),
+                pending_sectors: Some(17),
+                power_on_hours: Some(52143),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_nvme_and_scsi() {
+        assert_eq!(
+            parse_smartctl_json(NVME_HEALTHY).unwrap(),
+            DiskHealth {
+                passed: Some(true),
+                reallocated_sectors: None,
+                pending_sectors: None,
+                power_on_hours: Some(4312),
+            }
+        );
+        assert_eq!(
+            parse_smartctl_json(SCSI_GROWN_DEFECTS).unwrap(),
+            DiskHealth {
+                passed: Some(true),
+                reallocated_sectors: Some(24),
+                pending_sectors: None,
+                power_on_hours: Some(38211),
+            }
+        );
+        assert_eq!(
+            parse_smartctl_json(USB_UNSUPPORTED).unwrap(),
+            DiskHealth::default()
+        );
+        assert_eq!(
+            smartctl_messages(USB_UNSUPPORTED).as_deref(),
+            Some("/dev/sde: Unknown USB bridge [0x152d:0x0578 (0x0209)]")
+        );
+        assert!(parse_smartctl_json("not json").is_err());
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let failing = parse_smartctl_json(ATA_FAILING).unwrap();
+        let problems = failing.problems(&HealthThresholds::default());
+        assert_eq!(problems.len(), 3);
+        assert_eq!(problems[0], HealthProblem::Failed);
+        assert_eq!(problems[2].to_string(), "17 pending sectors (max 0)");
+
+        let scsi = parse_smartctl_json(SCSI_GROWN_DEFECTS).unwrap();
+        let lenient = HealthThresholds {
+            max_reallocated_sectors: 100,
+            max_power_on_hours: Some(30_000),
+            ..Default::default()
+        };
+        assert_eq!(
+            scsi.problems(&lenient),
+            vec![HealthProblem::PowerOnHours {
+                hours: 38211,
+                max: 30_000,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_check_health_moves_disks_to_rejected() {
+        let report = FilterReport {
+            accepted: vec![
+                BlockDevice::fake("sdb", 0),
+                BlockDevice::fake("sdc", 0),
+                BlockDevice::fake("sde", 0),
+            ],
+            rejected: Vec::new(),
+        };
+        let read_health = |device: &BlockDevice| match device.name.as_str() {
+            "sdb" => parse_smartctl_json(ATA_HEALTHY),
+            "sdc" => parse_smartctl_json(ATA_FAILING),
+            _ => Err(HealthError::SmartctlFailed {
+                device: device.path.clone(),
+                message: "Unknown USB bridge".to_string(),
+            }),
+        };
+
+        let checked = check_health_with(report, &HealthThresholds::default(), read_health);
+        let accepted: Vec<&str> = checked.accepted.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(accepted, vec!["sdb"]);
+        assert_eq!(checked.rejected.len(), 2);
+        assert!(checked.rejected[0]
+            .reason
+            .to_string()
+            .starts_with("unhealthy: SMART overall health check failed"));
+        assert_eq!(
+            checked.rejected[1].reason,
+            RejectReason::Unhealthy(vec![HealthProblem::Unavailable(
+                "smartctl failed for /dev/sde: Unknown USB bridge".to_string()
+            )])
+        );
+    }
+}
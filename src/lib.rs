@@ -20,47 +20,153 @@
 //!         force_gpt: false,
 //!         gpt_threshold_gb: 1000, // Use GPT for disks >= 1TB
 //!         skip_gpt: false,
+//!         // Disks carrying partitions, filesystems or volume metadata are refused
+//!         // unless listed here
+//!         allow_non_blank: vec!["/dev/sdc".to_string()],
+//!         ..Default::default()
 //!     };
 //!     smart_auto_mount_with_config(config)?;
 //! ```
 //!
+//! ## Hotplug Monitoring
+//! ```ignore
+//!     use auto_mount::*;
+//!
+//!     // Events are emitted once the disk and its partitions have settled
+//!     for event in DeviceMonitor::new()? {
+//!         if let DeviceEvent::Added(disk) = event? {
+//!             println!("{} inserted with partitions {:?}", disk.path, disk.partitions);
+//!         }
+//!     }
+//! ```
+//!
+//! ## Testing Against a Fake System
+//! ```ignore
+//!     use auto_mount::*;
+//!
+//!     // Every `*_in` function reads sysfs, procfs, /dev and /etc through a SystemRoot
+//!     let fixture = FixtureBuilder::new()
+//!         .disk(FakeDisk::new("sdb").size_bytes(4 << 40).partition(1 << 30))
+//!         .mount("sdb1", "/data", "ext4")
+//!         .build()?;
+//!     let devices = find_sata_devices_in(fixture.root())?;
+//! ```
+//!
 //! ## Fine-grained Control
 //! ```ignore
 //!     use auto_mount::*;
 //!
-//!     let devices = find_connected_satas()?;
-//!     let devices = filter_unmounted_hdd_devices(devices)?;
+//!     // Devices are read from sysfs once and carried through every stage
+//!     let devices = find_sata_devices()?;
+//!     let devices = filter_unmounted_hdd_block_devices(devices)?;
 //!     
 //!     // Optional: Convert to GPT (only if needed)
-//!     change_devices_to_gpt(&devices)?;
+//!     change_block_devices_to_gpt(&devices)?;
 //!     
+//!     let partitions = create_block_device_partitions(&devices)?;
+//!     format_block_devices(&partitions)?;
+//!     mount_block_devices(&partitions)?;
+//!
+//!     // String-based equivalents are kept for compatibility
+//!     let devices = find_connected_satas()?;
+//!     let devices = filter_unmounted_hdd_devices(devices)?;
 //!     let devices = create_partition(&devices)?;
 //!     format_devices(&devices)?;
 //!     mount_devices(&devices)?;
 //! ```
-pub use device_discovery::{find_connected_satas, DeviceDiscoveryError};
+pub use block_device::{
+    probe_attributes, probe_attributes_in, resolve_device_path, resolve_device_path_in,
+    BlockDevice, BlockDeviceError, DeviceAttributes, DeviceNumber, ZonedModel,
+};
+pub use device_detach::{
+    detach_device, detach_device_with_config, DetachConfig, DetachError, DetachResult, DetachStep,
+    DetachStepResult, FstabAction,
+};
+pub use device_discovery::{
+    find_block_devices, find_block_devices_in, find_connected_satas, find_sata_devices,
+    find_sata_devices_in, DeviceDiscoveryError, Transport,
+};
 pub use device_filter::{
-    collect_device_infos, filter_unmounted_hdd_devices, DeviceFilterError, DeviceInfo,
+    collect_device_infos, collect_device_infos_in, filter_unmounted_hdd_block_devices,
+    filter_unmounted_hdd_block_devices_in, filter_unmounted_hdd_devices,
+    report_unmounted_hdd_block_devices, report_unmounted_hdd_block_devices_in, DeviceFilter,
+    DeviceFilterError, DeviceInfo, FilterReport, RejectReason, RejectedDevice,
+};
+pub use device_monitor::{
+    parse_uevent, DeviceEvent, DeviceMonitor, DeviceMonitorError, DiskEvent, NetlinkSocket,
+    Received, RecordedUevents, Uevent, UeventAction, UeventSource, DEFAULT_SETTLE_TIME,
 };
+pub use device_usage::{busy_reasons, busy_reasons_in, BusyReason, DeviceUsageError};
 pub use error::Error;
 pub use filesystem::{
-    format_devices, format_devices_with_type, FilesystemError, FilesystemType, FormatResult,
+    format_block_devices, format_block_devices_with_config, format_block_devices_with_type,
+    format_block_devices_with_type_in, format_devices, format_devices_with_config,
+    format_devices_with_type, format_devices_with_type_in, FilesystemError, FilesystemType,
+    FormatConfig, FormatResult,
 };
+pub use fixture::{FakeDisk, FixtureBuilder, SystemFixture};
+pub use gpt::{GptDisk, GptError, GptPartition, Guid};
+pub use health::{
+    check_health, parse_smartctl_json, read_disk_health, DiskHealth, HealthError, HealthProblem,
+    HealthThresholds,
+};
+pub use mbr::{gpt_type, mbr_type, MbrDisk, MbrError, MbrPartition};
 pub use mount_manager::{
-    mount_devices, MountConfig as MountManagerConfig, MountEntry, MountError, MountResult,
+    mount_block_devices, mount_devices, MountConfig as MountManagerConfig, MountEntry, MountError,
+    MountResult,
 };
+pub use partition_layout::{Alignment, LayoutError, PartitionLayout, PartitionSize, PartitionSpec};
 pub use partition_manager::{
-    change_devices_to_gpt, create_partition, PartitionError, PartitionResult,
+    change_block_devices_to_gpt, change_block_devices_to_gpt_with_config, change_devices_to_gpt,
+    convert_mbr_to_gpt, convert_mbr_to_gpt_with_config, create_block_device_partitions,
+    create_block_device_partitions_in, create_block_device_partitions_with_config,
+    create_block_device_partitions_with_report, create_partition, create_partition_with_config,
+    restore_partition_table, restore_partition_table_with_config, PartitionConfig, PartitionError,
+    PartitionResult, PlacedPartition,
+};
+pub use partition_table::{
+    parse_partition_table, read_partition_table, read_partition_table_in, FreeSpace,
+    PartitionEntry, PartitionTable, PartitionTableError, PartitionType, TableKind,
+};
+pub use scsi_rescan::{
+    rescan_scsi_hosts, rescan_scsi_hosts_with_config, RescanConfig, RescanResult, ScsiRescanError,
+};
+pub use signature::{
+    is_blank, probe_device_signatures, probe_device_signatures_in, probe_signatures, Signature,
+    SignatureError,
 };
 pub use smart_mount::{
-    gpt_auto_mount, simple_auto_mount, smart_auto_mount, smart_auto_mount_with_config, MountConfig,
-    SmartMountError,
+    gpt_auto_mount, simple_auto_mount, smart_auto_mount, smart_auto_mount_with_config,
+    smart_auto_mount_with_report, DevicePlan, MountConfig, SmartMountError, SmartMountReport,
+    TableDecision, ZonedPolicy,
+};
+pub use system_disk::{
+    find_system_disks, find_system_disks_in, SystemDisk, SystemDiskError, SystemRole,
+};
+pub use system_root::SystemRoot;
+pub use table_backup::{
+    backup_partition_table, backup_partition_table_in, TableBackup, TableBackupError,
 };
 
+mod block_device;
+mod device_detach;
 mod device_discovery;
 mod device_filter;
+mod device_monitor;
+mod device_usage;
 mod error;
 mod filesystem;
+mod fixture;
+mod gpt;
+mod health;
+mod mbr;
 mod mount_manager;
+mod partition_layout;
 mod partition_manager;
+mod partition_table;
+mod scsi_rescan;
+mod signature;
 mod smart_mount;
+mod system_disk;
+mod system_root;
+mod table_backup;
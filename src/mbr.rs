@@ -0,0 +1,272 @@
+//! MBR module for auto_mount
+//!
+//! This module writes DOS (MBR) partition tables with up to four primary
+//! partitions to any seekable target, without calling parted or fdisk
+
+use crate::Guid;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// MBR entries hold 32-bit sector numbers
+const MAX_SECTORS: u64 = 1 << 32;
+const PRIMARY_PARTITIONS: usize = 4;
+/// CHS placeholder for partitions addressed by LBA only
+const CHS_LBA_ONLY: [u8; 3] = [0xfe, 0xff, 0xff];
+
+/// MBR system IDs and the GPT types they correspond to; the first ID listed
+/// for a GPT type is the one written for it
+const TYPE_MAP: [(u8, Guid); 10] = [
+    (0x83, Guid::LINUX_FILESYSTEM),
+    (0x82, Guid::LINUX_SWAP),
+    (0x8e, Guid::LINUX_LVM),
+    (0xfd, Guid::LINUX_RAID),
+    (0xef, Guid::EFI_SYSTEM),
+    (0x07, Guid::MICROSOFT_BASIC_DATA),
+    // FAT16 and FAT32, CHS and LBA variants
+    (0x06, Guid::MICROSOFT_BASIC_DATA),
+    (0x0b, Guid::MICROSOFT_BASIC_DATA),
+    (0x0c, Guid::MICROSOFT_BASIC_DATA),
+    (0x0e, Guid::MICROSOFT_BASIC_DATA),
+];
+
+/// Errors that can occur while building or writing an MBR
+#[derive(Debug, thiserror::Error)]
+pub enum MbrError {
+    #[error("Disk of {0} sectors is beyond the reach of an MBR")]
+    DiskTooLarge(u64),
+    #[error("Partition {first}-{last} lies outside the usable sectors 1-{last_usable}")]
+    OutOfRange {
+        first: u64,
+        last: u64,
+        last_usable: u64,
+    },
+    #[error("Partition {first}-{last} overlaps partition {other}")]
+    Overlap { first: u64, last: u64, other: u32 },
+    #[error("An MBR holds at most 4 primary partitions")]
+    TooManyPartitions,
+    #[error("No MBR type for partition type {0}")]
+    UnmappedType(Guid),
+    #[error("IO error: {0}")]
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for MbrError {
+    fn from(error: std::io::Error) -> Self {
+        MbrError::IoError(error)
+    }
+}
+
+/// MBR system ID for a GPT partition type, for the types both schemes know
+pub fn mbr_type(guid: Guid) -> Option<u8> {
+    TYPE_MAP
+        .iter()
+        .find(|(_, mapped)| *mapped == guid)
+        .map(|(id, _)| *id)
+}
+
+/// GPT partition type for an MBR system ID, for the types both schemes know
+pub fn gpt_type(id: u8) -> Option<Guid> {
+    TYPE_MAP
+        .iter()
+        .find(|(mapped, _)| *mapped == id)
+        .map(|(_, guid)| *guid)
+}
+
+/// One primary partition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MbrPartition {
+    pub type_id: u8,
+    pub bootable: bool,
+    pub first_lba: u64,
+    /// Last sector of the partition, inclusive
+    pub last_lba: u64,
+}
+
+impl MbrPartition {
+    pub fn sectors(&self) -> u64 {
+        self.last_lba - self.first_lba + 1
+    }
+}
+
+/// A DOS partition table for a disk of a given geometry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MbrDisk {
+    pub disk_signature: u32,
+    pub sector_size: u64,
+    pub total_sectors: u64,
+    /// Primary partitions; entry `n` is partition number `n + 1`
+    pub partitions: Vec<MbrPartition>,
+}
+
+impl MbrDisk {
+    /// Empty table with a random disk signature
+    pub fn new(total_sectors: u64, sector_size: u64) -> Result<MbrDisk, MbrError> {
+        let mut signature = [0; 4];
+        File::open("/dev/urandom")?.read_exact(&mut signature)?;
+        Self::with_signature(total_sectors, sector_size, u32::from_le_bytes(signature))
+    }
+
+    /// Empty table with the given disk signature
+    pub fn with_signature(
+        total_sectors: u64,
+        sector_size: u64,
+        disk_signature: u32,
+    ) -> Result<MbrDisk, MbrError> {
+        if total_sectors > MAX_SECTORS {
+            return Err(MbrError::DiskTooLarge(total_sectors));
+        }
+        Ok(MbrDisk {
+            disk_signature,
+            sector_size,
+            total_sectors,
+            partitions: Vec::new(),
+        })
+    }
+
+    pub fn first_usable_lba(&self) -> u64 {
+        1
+    }
+
+    pub fn last_usable_lba(&self) -> u64 {
+        self.total_sectors - 1
+    }
+
+    /// Add a primary partition and return its number (1-based)
+    pub fn add_partition(
+        &mut self,
+        type_id: u8,
+        first_lba: u64,
+        last_lba: u64,
+    ) -> Result<u32, MbrError> {
+        if first_lba > last_lba
+            || first_lba < self.first_usable_lba()
+            || last_lba > self.last_usable_lba()
+        {
+            return Err(MbrError::OutOfRange {
+                first: first_lba,
+                last: last_lba,
+                last_usable: self.last_usable_lba(),
+            });
+        }
+        if let Some(index) = self
+            .partitions
+            .iter()
+            .position(|other| first_lba <= other.last_lba && other.first_lba <= last_lba)
+        {
+            return Err(MbrError::Overlap {
+                first: first_lba,
+                last: last_lba,
+                other: index as u32 + 1,
+            });
+        }
+        if self.partitions.len() >= PRIMARY_PARTITIONS {
+            return Err(MbrError::TooManyPartitions);
+        }
+
+        self.partitions.push(MbrPartition {
+            type_id,
+            bootable: false,
+            first_lba,
+            last_lba,
+        });
+        Ok(self.partitions.len() as u32)
+    }
+
+    /// Write the table, clearing both GPT header locations so no stale GPT outranks it
+    pub fn write<W: Write + Seek>(&self, target: &mut W) -> Result<(), MbrError> {
+        let mut mbr = vec![0; self.sector_size as usize];
+        mbr[440..444].copy_from_slice(&self.disk_signature.to_le_bytes());
+        for (index, partition) in self.partitions.iter().enumerate() {
+            let entry = &mut mbr[446 + index * 16..462 + index * 16];
+            entry[0] = if partition.bootable { 0x80 } else { 0 };
+            entry[1..4].copy_from_slice(&CHS_LBA_ONLY);
+            entry[4] = partition.type_id;
+            entry[5..8].copy_from_slice(&CHS_LBA_ONLY);
+            entry[8..12].copy_from_slice(&(partition.first_lba as u32).to_le_bytes());
+            entry[12..16].copy_from_slice(&(partition.sectors() as u32).to_le_bytes());
+        }
+        mbr[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        let blank = vec![0; self.sector_size as usize];
+        target.seek(SeekFrom::Start(0))?;
+        target.write_all(&mbr)?;
+        target.write_all(&blank)?;
+        target.seek(SeekFrom::Start((self.total_sectors - 1) * self.sector_size))?;
+        target.write_all(&blank)?;
+        target.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_partition_table, GptDisk, PartitionType, TableKind};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write_mbr_over_gpt() {
+        let sectors = 16 << 11;
+        let mut image = Cursor::new(vec![0; (sectors * 512) as usize]);
+        GptDisk::new(sectors, 512)
+            .unwrap()
+            .write(&mut image)
+            .unwrap();
+
+        let mut disk = MbrDisk::with_signature(sectors, 512, 0x1234_5678).unwrap();
+        disk.add_partition(0x82, 2048, 4095).unwrap();
+        disk.add_partition(0x83, 4096, sectors - 1).unwrap();
+        disk.write(&mut image).unwrap();
+
+        let table = parse_partition_table(&mut image, 512).unwrap();
+        assert_eq!(table.kind, TableKind::Mbr);
+        assert_eq!(table.disk_signature, Some(0x1234_5678));
+        let ranges: Vec<(u32, u64, u64, PartitionType)> = table
+            .partitions
+            .iter()
+            .map(|p| (p.number, p.first_lba, p.last_lba, p.partition_type))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (1, 2048, 4095, PartitionType::Mbr(0x82)),
+                (2, 4096, sectors - 1, PartitionType::Mbr(0x83)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mbr_limits() {
+        assert!(matches!(
+            MbrDisk::new((1 << 32) + 1, 512),
+            Err(MbrError::DiskTooLarge(_))
+        ));
+        let mut disk = MbrDisk::with_signature(1 << 20, 512, 0).unwrap();
+        assert!(matches!(
+            disk.add_partition(0x83, 0, 100),
+            Err(MbrError::OutOfRange { .. })
+        ));
+        for start in 0..4 {
+            disk.add_partition(0x83, 2048 + start * 2048, 4095 + start * 2048)
+                .unwrap();
+        }
+        assert!(matches!(
+            disk.add_partition(0x83, 4096, 5000),
+            Err(MbrError::Overlap { other: 2, .. })
+        ));
+        assert!(matches!(
+            disk.add_partition(0x83, 20480, 30000),
+            Err(MbrError::TooManyPartitions)
+        ));
+    }
+
+    #[test]
+    fn test_type_mapping() {
+        assert_eq!(mbr_type(Guid::LINUX_SWAP), Some(0x82));
+        assert_eq!(gpt_type(0x8e), Some(Guid::LINUX_LVM));
+        assert_eq!(gpt_type(0x0c), Some(Guid::MICROSOFT_BASIC_DATA));
+        assert_eq!(mbr_type(Guid::MICROSOFT_BASIC_DATA), Some(0x07));
+        assert_eq!(gpt_type(0x42), None);
+        assert_eq!(mbr_type(Guid::ZERO), None);
+    }
+}
@@ -3,6 +3,9 @@
 //! This module handles mounting with proper safety measures including
 //! backup, validation, and atomic operations
 
+use crate::block_device::resolve_device_path_in;
+use crate::system_disk::resolve_spec;
+use crate::{BlockDevice, SystemRoot};
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
@@ -44,6 +47,9 @@ pub struct MountConfig {
     pub mount_base_path: String,
     pub backup_fstab: bool,
     pub validate_before_write: bool,
+    /// Where /etc/fstab and the mount points live and device nodes are found;
+    /// `mount -a` only runs against the live system
+    pub root: SystemRoot,
 }
 
 impl Default for MountConfig {
@@ -54,6 +60,7 @@ impl Default for MountConfig {
             mount_base_path: "/mnt".to_string(),
             backup_fstab: true,
             validate_before_write: true,
+            root: SystemRoot::default(),
         }
     }
 }
@@ -71,7 +78,10 @@ pub struct MountEntry {
 /// Result of mount operation
 #[derive(Debug, Clone)]
 pub struct MountResult {
+    /// Current kernel path of the device, e.g. `/dev/sdb1`
     pub device: String,
+    /// Identifier that survives reboots, see [`BlockDevice::stable_id`]
+    pub stable_id: String,
     pub mount_point: String,
     pub success: bool,
     pub error_message: Option<String>,
@@ -82,12 +92,20 @@ pub fn mount_devices(devices: &[String]) -> Result<Vec<MountResult>, MountError>
     mount_devices_with_config(devices, MountConfig::default())
 }
 
-/// Mount devices with custom configuration
+/// Safe mount block devices (usually partitions) with default configuration
+pub fn mount_block_devices(devices: &[BlockDevice]) -> Result<Vec<MountResult>, MountError> {
+    let paths: Vec<String> = devices.iter().map(|device| device.path.clone()).collect();
+    mount_devices_with_config(&paths, MountConfig::default())
+}
+
+/// Mount devices with custom configuration; devices may be given by any
+/// identifier `resolve_device_path` accepts
 pub fn mount_devices_with_config(
     devices: &[String],
     config: MountConfig,
 ) -> Result<Vec<MountResult>, MountError> {
-    let fstab_path = "/etc/fstab";
+    let fstab_path = config.root.fstab();
+    let fstab_path = fstab_path.as_path();
 
     // Step 1: Create backup if enabled
     let backup_path = if config.backup_fstab {
@@ -101,11 +119,18 @@ pub fn mount_devices_with_config(
     let mut results = Vec::new();
 
     for device in devices {
-        match prepare_mount_entry(device, &config) {
+        let device =
+            resolve_device_path_in(&config.root, device).unwrap_or_else(|_| device.clone());
+        let stable_id = BlockDevice::from_path_in(&config.root, &device)
+            .map(|block_device| block_device.stable_id().to_string())
+            .unwrap_or_else(|_| device.clone());
+
+        match prepare_mount_entry(&device, &config) {
             Ok(entry) => {
                 mount_entries.push(entry.clone());
                 results.push(MountResult {
-                    device: device.clone(),
+                    device,
+                    stable_id,
                     mount_point: entry.mount_point.clone(),
                     success: true,
                     error_message: None,
@@ -113,7 +138,8 @@ pub fn mount_devices_with_config(
             }
             Err(e) => {
                 results.push(MountResult {
-                    device: device.clone(),
+                    device,
+                    stable_id,
                     mount_point: String::new(),
                     success: false,
                     error_message: Some(e.to_string()),
@@ -130,7 +156,7 @@ pub fn mount_devices_with_config(
     match update_fstab_safe(fstab_path, &mount_entries, &config) {
         Ok(()) => {
             // Step 4: Apply mounts
-            if let Err(e) = apply_mounts() {
+            if let Err(e) = apply_mounts(&config.root) {
                 // If mount fails, try to restore backup
                 if let Some(backup) = backup_path {
                     let _ = restore_fstab_backup(fstab_path, &backup);
@@ -151,13 +177,13 @@ pub fn mount_devices_with_config(
 }
 
 /// Create a timestamped backup of fstab
-fn create_fstab_backup(fstab_path: &str) -> Result<PathBuf, MountError> {
+pub(crate) fn create_fstab_backup(fstab_path: &Path) -> Result<PathBuf, MountError> {
     let timestamp = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_secs();
 
-    let backup_path = format!("{}.backup.{}", fstab_path, timestamp);
+    let backup_path = format!("{}.backup.{}", fstab_path.display(), timestamp);
 
     fs::copy(fstab_path, &backup_path)?;
 
@@ -173,7 +199,7 @@ fn create_fstab_backup(fstab_path: &str) -> Result<PathBuf, MountError> {
 }
 
 /// Restore fstab from backup
-fn restore_fstab_backup(fstab_path: &str, backup_path: &Path) -> Result<(), MountError> {
+fn restore_fstab_backup(fstab_path: &Path, backup_path: &Path) -> Result<(), MountError> {
     fs::copy(backup_path, fstab_path)?;
     Ok(())
 }
@@ -186,7 +212,7 @@ fn prepare_mount_entry(device: &str, config: &MountConfig) -> Result<MountEntry,
     }
 
     // Get UUID
-    let uuid = device_uuid(device)?;
+    let uuid = device_uuid(&config.root, device)?;
 
     // Create mount point
     let device_name = device
@@ -195,7 +221,7 @@ fn prepare_mount_entry(device: &str, config: &MountConfig) -> Result<MountEntry,
         .ok_or_else(|| MountError::InvalidDevice(device.to_string()))?;
     let mount_point = format!("{}/{}", config.mount_base_path, device_name);
 
-    create_mount_point(&mount_point)?;
+    create_mount_point(&config.root.path(&mount_point))?;
 
     Ok(MountEntry {
         device: device.to_string(),
@@ -206,8 +232,21 @@ fn prepare_mount_entry(device: &str, config: &MountConfig) -> Result<MountEntry,
     })
 }
 
-/// Find UUID for a device
-fn device_uuid(device: &str) -> Result<String, MountError> {
+/// Find UUID for a device; below a fake root it is looked up among the
+/// `/dev/disk/by-uuid` links instead of probed with blkid
+fn device_uuid(root: &SystemRoot, device: &str) -> Result<String, MountError> {
+    if !root.is_live() {
+        let name = device.trim_start_matches("/dev/");
+        let links = fs::read_dir(root.dev_path("/dev/disk/by-uuid"));
+        return links
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| format!("UUID={}", entry.file_name().to_string_lossy()))
+            .find(|spec| resolve_spec(root, spec).as_deref() == Some(name))
+            .ok_or_else(|| MountError::UuidNotFound(device.to_string()));
+    }
+
     let output = Command::new("sudo")
         .args(["blkid", device, "-s", "UUID", "-o", "export"])
         .output()?;
@@ -231,28 +270,29 @@ fn device_uuid(device: &str) -> Result<String, MountError> {
 }
 
 /// Create mount point directory
-fn create_mount_point(mount_point: &str) -> Result<(), MountError> {
+fn create_mount_point(mount_point: &Path) -> Result<(), MountError> {
     match fs::create_dir_all(mount_point) {
         Ok(()) => Ok(()),
         Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
         Err(e) => Err(MountError::MountPointCreationFailed(format!(
             "Failed to create {}: {}",
-            mount_point, e
+            mount_point.display(),
+            e
         ))),
     }
 }
 
 /// Update fstab file safely with atomic write
 fn update_fstab_safe(
-    fstab_path: &str,
+    fstab_path: &Path,
     mount_entries: &[MountEntry],
     config: &MountConfig,
 ) -> Result<(), MountError> {
-    let temp_path = format!("{}.tmp", fstab_path);
+    let temp_path = PathBuf::from(format!("{}.tmp", fstab_path.display()));
 
     // Read current fstab
     let mut current_lines = Vec::new();
-    if Path::new(fstab_path).exists() {
+    if fstab_path.exists() {
         let file = File::open(fstab_path)?;
         let reader = BufReader::new(file);
         current_lines = reader.lines().collect::<Result<Vec<_>, _>>()?;
@@ -296,7 +336,7 @@ fn update_fstab_safe(
 }
 
 /// Validate fstab syntax
-fn validate_fstab(fstab_path: &str) -> Result<(), MountError> {
+fn validate_fstab(fstab_path: &Path) -> Result<(), MountError> {
     // Basic validation - check if each line has proper format
     let file = File::open(fstab_path)?;
     let reader = BufReader::new(file);
@@ -324,8 +364,13 @@ fn validate_fstab(fstab_path: &str) -> Result<(), MountError> {
     Ok(())
 }
 
-/// Apply mounts using mount command
-fn apply_mounts() -> Result<(), MountError> {
+/// Apply mounts using mount command; skipped below a fake root, where
+/// `mount -a` would read the host's fstab instead
+fn apply_mounts(root: &SystemRoot) -> Result<(), MountError> {
+    if !root.is_live() {
+        return Ok(());
+    }
+
     let output = Command::new("sudo").args(["mount", "-a"]).output()?;
 
     if !output.status.success() {
@@ -339,6 +384,7 @@ fn apply_mounts() -> Result<(), MountError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
 
     #[test]
     fn test_mount_config_default() {
@@ -371,6 +417,37 @@ mod tests {
         // In real implementation, we'd create a temp file with valid content
     }
 
+    #[test]
+    fn test_mount_stays_inside_root() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").partition(1 << 30))
+            .symlink("dev/disk/by-uuid/0b6c1a52", "../../sdb1")
+            .file("etc/fstab", "UUID=4f2e-root  /  ext4  defaults  0 1\n")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let base = "/srv/auto_mount-fixture-test";
+        let config = MountConfig {
+            mount_base_path: base.to_string(),
+            root: root.clone(),
+            ..Default::default()
+        };
+
+        let results = mount_devices_with_config(&["/dev/sdb1".to_string()], config).unwrap();
+        assert!(results[0].success, "{:?}", results[0].error_message);
+        assert_eq!(results[0].mount_point, format!("{}/sdb1", base));
+        assert!(root.path(&results[0].mount_point).is_dir());
+        assert!(!Path::new(base).exists());
+        assert_eq!(
+            fs::read_to_string(root.fstab()).unwrap(),
+            format!(
+                "UUID=4f2e-root  /  ext4  defaults  0 1\n\
+                 UUID=0b6c1a52  {}/sdb1  ext4    rw,acl    0   0\n",
+                base
+            )
+        );
+    }
+
     #[test]
     fn test_device_path_validation() {
         assert!("/dev/sda1".starts_with("/dev/"));
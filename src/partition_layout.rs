@@ -0,0 +1,465 @@
+//! Partition layout module for auto_mount
+//!
+//! This module describes how a disk is split into partitions (absolute sizes,
+//! percentages or the remaining space) and turns that into aligned sector ranges
+
+use crate::{BlockDevice, FilesystemType, Guid};
+use std::str::FromStr;
+
+/// Partitions start and end on 1 MiB boundaries at least, like parted's `0%` and `100%`
+pub(crate) const DEFAULT_ALIGNMENT: u64 = 1 << 20;
+/// I/O sizes that would push the alignment past this are ignored; some USB
+/// bridges report nonsense such as 33553920 bytes
+const MAX_ALIGNMENT: u64 = 64 << 20;
+
+/// Errors that can occur while laying out partitions
+#[derive(Debug, thiserror::Error)]
+pub enum LayoutError {
+    #[error("Partition layout is empty")]
+    Empty,
+    #[error("Invalid partition size: {0}")]
+    InvalidSize(String),
+    #[error("Percentages add up to {0}%")]
+    PercentOverflow(u32),
+    #[error("Partitions need {needed} bytes but only {available} bytes are usable")]
+    DoesNotFit { needed: u64, available: u64 },
+    #[error("Alignment of {alignment} bytes is not a multiple of the {sector_size}-byte sectors")]
+    InvalidAlignment { alignment: u64, sector_size: u64 },
+}
+
+/// Size of one partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionSize {
+    /// At least this many bytes, rounded up to the alignment
+    Bytes(u64),
+    /// Whole percent of the usable disk area
+    Percent(u32),
+    /// An equal share of whatever the other partitions leave
+    Remaining,
+}
+
+impl FromStr for PartitionSize {
+    type Err = LayoutError;
+
+    /// Parse `remaining`/`rest`, `25%`, or a size such as `32GiB`, `512M` or `4096`
+    fn from_str(text: &str) -> Result<PartitionSize, LayoutError> {
+        let invalid = || LayoutError::InvalidSize(text.to_string());
+        let trimmed = text.trim();
+        if trimmed.eq_ignore_ascii_case("remaining") || trimmed.eq_ignore_ascii_case("rest") {
+            return Ok(PartitionSize::Remaining);
+        }
+        if let Some(percent) = trimmed.strip_suffix('%') {
+            let percent: u32 = percent.trim().parse().map_err(|_| invalid())?;
+            if percent == 0 || percent > 100 {
+                return Err(invalid());
+            }
+            return Ok(PartitionSize::Percent(percent));
+        }
+
+        let split = trimmed
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(trimmed.len());
+        let (number, unit) = trimmed.split_at(split);
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+        let shift = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 0,
+            "k" | "kib" => 10,
+            "m" | "mib" => 20,
+            "g" | "gib" => 30,
+            "t" | "tib" => 40,
+            _ => return Err(invalid()),
+        };
+        match number.checked_mul(1 << shift) {
+            Some(bytes) if bytes > 0 => Ok(PartitionSize::Bytes(bytes)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// One partition of a layout
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionSpec {
+    pub size: PartitionSize,
+    /// GPT partition type
+    pub partition_type: Guid,
+    /// GPT partition name
+    pub name: String,
+    /// Filesystem the smart pipeline creates on it; `None` leaves it unformatted
+    pub filesystem: Option<FilesystemType>,
+}
+
+impl PartitionSpec {
+    /// Linux data partition of the given size, formatted as ext4
+    pub fn new(size: PartitionSize) -> Self {
+        PartitionSpec {
+            size,
+            partition_type: Guid::LINUX_FILESYSTEM,
+            name: String::new(),
+            filesystem: Some(FilesystemType::Ext4),
+        }
+    }
+
+    /// Linux swap partition of the given size, left for mkswap
+    pub fn swap(size: PartitionSize) -> Self {
+        PartitionSpec {
+            partition_type: Guid::LINUX_SWAP,
+            filesystem: None,
+            ..PartitionSpec::new(size)
+        }
+    }
+
+    pub fn partition_type(mut self, partition_type: Guid) -> Self {
+        self.partition_type = partition_type;
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn filesystem(mut self, filesystem: Option<FilesystemType>) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+}
+
+/// Boundaries partitions start and end on: every `grain_bytes`, shifted by
+/// `offset_bytes` from the start of the disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alignment {
+    pub grain_bytes: u64,
+    pub offset_bytes: u64,
+}
+
+impl Alignment {
+    /// Alignment suiting a disk's physical sectors and preferred I/O sizes, or the
+    /// given grain (1 MiB, 4 MiB, ...) instead; the disk's alignment offset applies to both
+    pub fn for_device(
+        device: &BlockDevice,
+        grain_override: Option<u64>,
+    ) -> Result<Alignment, LayoutError> {
+        let logical = u64::from(device.logical_sector_size.max(512));
+        let physical = u64::from(device.physical_sector_size).max(logical);
+
+        let grain_bytes = match grain_override {
+            Some(grain) if grain == 0 || grain % logical != 0 => {
+                return Err(LayoutError::InvalidAlignment {
+                    alignment: grain,
+                    sector_size: logical,
+                })
+            }
+            Some(grain) => grain,
+            None => {
+                let mut grain = lcm(DEFAULT_ALIGNMENT, physical);
+                for io_size in [device.minimum_io_size, device.optimal_io_size] {
+                    let io_size = u64::from(io_size);
+                    if io_size == 0 || io_size % physical != 0 {
+                        continue;
+                    }
+                    let candidate = lcm(grain, io_size);
+                    if candidate <= MAX_ALIGNMENT {
+                        grain = candidate;
+                    }
+                }
+                grain
+            }
+        };
+
+        Ok(Alignment {
+            grain_bytes,
+            offset_bytes: u64::from(device.alignment_offset) % grain_bytes,
+        })
+    }
+}
+
+impl Default for Alignment {
+    /// 1 MiB boundaries from the start of the disk
+    fn default() -> Self {
+        Alignment {
+            grain_bytes: DEFAULT_ALIGNMENT,
+            offset_bytes: 0,
+        }
+    }
+}
+
+/// Partitions to create on every disk, in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionLayout {
+    pub partitions: Vec<PartitionSpec>,
+}
+
+impl Default for PartitionLayout {
+    /// One ext4 partition covering the disk
+    fn default() -> Self {
+        PartitionLayout::new().partition(PartitionSpec::new(PartitionSize::Remaining))
+    }
+}
+
+impl PartitionLayout {
+    /// Layout without partitions, to be filled with [`PartitionLayout::partition`]
+    pub fn new() -> Self {
+        PartitionLayout {
+            partitions: Vec::new(),
+        }
+    }
+
+    /// `count` ext4 partitions of equal size
+    pub fn equal(count: usize) -> Self {
+        PartitionLayout {
+            partitions: vec![PartitionSpec::new(PartitionSize::Remaining); count],
+        }
+    }
+
+    /// Append a partition
+    pub fn partition(mut self, spec: PartitionSpec) -> Self {
+        self.partitions.push(spec);
+        self
+    }
+
+    /// Sector ranges (first, last inclusive) for every partition, each starting and
+    /// ending on an `alignment`-sector boundary inside the usable area
+    pub fn sector_ranges(
+        &self,
+        first_usable: u64,
+        last_usable: u64,
+        sector_size: u64,
+        alignment: u64,
+    ) -> Result<Vec<(u64, u64)>, LayoutError> {
+        if self.partitions.is_empty() {
+            return Err(LayoutError::Empty);
+        }
+        let alignment = alignment.max(1);
+        let start = round_up(first_usable, alignment);
+        let end = (last_usable + 1) / alignment * alignment;
+        let available = end.saturating_sub(start);
+
+        let percent: u32 = self
+            .partitions
+            .iter()
+            .map(|spec| match spec.size {
+                PartitionSize::Percent(percent) => percent,
+                _ => 0,
+            })
+            .sum();
+        if percent > 100 {
+            return Err(LayoutError::PercentOverflow(percent));
+        }
+
+        let fixed: Vec<Option<u64>> = self
+            .partitions
+            .iter()
+            .map(|spec| match spec.size {
+                PartitionSize::Bytes(bytes) => {
+                    Some(round_up((bytes + sector_size - 1) / sector_size, alignment))
+                }
+                PartitionSize::Percent(percent) => {
+                    Some(available * u64::from(percent) / 100 / alignment * alignment)
+                }
+                PartitionSize::Remaining => None,
+            })
+            .collect();
+        let fixed_total: u64 = fixed.iter().flatten().sum();
+        let remaining_count = fixed.iter().filter(|size| size.is_none()).count() as u64;
+        let share = match available.checked_sub(fixed_total) {
+            Some(left) if remaining_count > 0 => left / remaining_count / alignment * alignment,
+            _ => 0,
+        };
+
+        let too_small = fixed.contains(&Some(0))
+            || (remaining_count > 0 && share == 0)
+            || fixed_total > available;
+        if too_small {
+            let needed = fixed_total + remaining_count * alignment;
+            return Err(LayoutError::DoesNotFit {
+                needed: needed.max(alignment) * sector_size,
+                available: available * sector_size,
+            });
+        }
+
+        let last_remaining = fixed.iter().rposition(|size| size.is_none());
+        let mut ranges = Vec::new();
+        let mut next = start;
+        for (index, size) in fixed.iter().enumerate() {
+            let sectors = match size {
+                Some(sectors) => *sectors,
+                // The last share also takes what rounding left over
+                None if Some(index) == last_remaining => {
+                    let later: u64 = fixed[index + 1..].iter().flatten().sum();
+                    end - next - later
+                }
+                None => share,
+            };
+            ranges.push((next, next + sectors - 1));
+            next += sectors;
+        }
+        Ok(ranges)
+    }
+
+    /// Sector ranges (first, last inclusive) for every partition, starting and
+    /// ending on the boundaries of `alignment` inside the usable area
+    pub fn aligned_ranges(
+        &self,
+        first_usable: u64,
+        last_usable: u64,
+        sector_size: u64,
+        alignment: &Alignment,
+    ) -> Result<Vec<(u64, u64)>, LayoutError> {
+        let grain = (alignment.grain_bytes / sector_size).max(1);
+        let offset = alignment.offset_bytes / sector_size;
+        // Lay out on a disk shifted by the offset, then shift back; when the offset
+        // is past the first usable sector the first boundary is the offset itself
+        let ranges = self.sector_ranges(
+            first_usable.saturating_sub(offset),
+            last_usable - offset,
+            sector_size,
+            grain,
+        )?;
+        Ok(ranges
+            .into_iter()
+            .map(|(first, last)| (first + offset, last + offset))
+            .collect())
+    }
+}
+
+fn round_up(value: u64, multiple: u64) -> u64 {
+    (value + multiple - 1) / multiple * multiple
+}
+
+fn lcm(a: u64, b: u64) -> u64 {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        let rest = x % y;
+        x = y;
+        y = rest;
+    }
+    a / x * b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1 << 30;
+
+    #[test]
+    fn test_parse_sizes() {
+        let parse = |text: &str| text.parse::<PartitionSize>();
+        assert_eq!(parse("32GiB").unwrap(), PartitionSize::Bytes(32 * GIB));
+        assert_eq!(parse("512M").unwrap(), PartitionSize::Bytes(512 << 20));
+        assert_eq!(parse("4096").unwrap(), PartitionSize::Bytes(4096));
+        assert_eq!(parse(" 25% ").unwrap(), PartitionSize::Percent(25));
+        assert_eq!(parse("rest").unwrap(), PartitionSize::Remaining);
+        assert_eq!(parse("Remaining").unwrap(), PartitionSize::Remaining);
+        for invalid in ["", "0", "0%", "101%", "12PB", "GiB", "1.5G"] {
+            assert!(parse(invalid).is_err(), "{:?} parsed", invalid);
+        }
+    }
+
+    #[test]
+    fn test_swap_and_rest() {
+        // 100 GiB disk with 512-byte sectors and a 1 MiB alignment
+        let sectors = 100 * GIB / 512;
+        let layout = PartitionLayout::new()
+            .partition(PartitionSpec::swap(PartitionSize::Bytes(32 * GIB)).name("swap"))
+            .partition(PartitionSpec::new(PartitionSize::Remaining).name("data"));
+        let ranges = layout.sector_ranges(34, sectors - 34, 512, 2048).unwrap();
+
+        assert_eq!(ranges[0], (2048, 2048 + 32 * GIB / 512 - 1));
+        assert_eq!(ranges[1].0, ranges[0].1 + 1);
+        assert_eq!(ranges[1].1, sectors - 2048 - 1);
+        assert_eq!(layout.partitions[0].partition_type, Guid::LINUX_SWAP);
+        assert!(layout.partitions[0].filesystem.is_none());
+    }
+
+    #[test]
+    fn test_equal_and_percent() {
+        let ranges = PartitionLayout::equal(4)
+            .sector_ranges(34, 10 * 2048 + 100, 512, 2048)
+            .unwrap();
+        let sizes: Vec<u64> = ranges
+            .iter()
+            .map(|(first, last)| last - first + 1)
+            .collect();
+        // Rounding leftovers go to the last share
+        assert_eq!(sizes, vec![4096, 4096, 4096, 6144]);
+
+        let layout = PartitionLayout::new()
+            .partition(PartitionSpec::new(PartitionSize::Percent(50)))
+            .partition(PartitionSpec::new(PartitionSize::Percent(25)));
+        let ranges = layout.sector_ranges(2048, 9 * 2048 - 1, 512, 2048).unwrap();
+        assert_eq!(ranges, vec![(2048, 5 * 2048 - 1), (5 * 2048, 7 * 2048 - 1)]);
+    }
+
+    #[test]
+    fn test_alignment_from_device() {
+        let mut disk = BlockDevice::fake("sdb", 100 * GIB);
+        assert_eq!(
+            Alignment::for_device(&disk, None).unwrap(),
+            Alignment::default()
+        );
+
+        // 512e disk behind a RAID controller with a 768 KiB stripe and a 3584-byte offset
+        disk.physical_sector_size = 4096;
+        disk.minimum_io_size = 256 << 10;
+        disk.optimal_io_size = 768 << 10;
+        disk.alignment_offset = 3584;
+        let alignment = Alignment::for_device(&disk, None).unwrap();
+        assert_eq!(alignment.grain_bytes, 3 << 20);
+        assert_eq!(alignment.offset_bytes, 3584);
+
+        let ranges = PartitionLayout::equal(2)
+            .aligned_ranges(34, disk.size_bytes / 512 - 34, 512, &alignment)
+            .unwrap();
+        for (first, last) in &ranges {
+            assert_eq!((first * 512 - 3584) % (3 << 20), 0);
+            assert_eq!(((last + 1) * 512 - 3584) % (3 << 20), 0);
+        }
+        assert_eq!(ranges[0].0, 6144 + 7);
+
+        // Bogus optimal I/O sizes are ignored, overrides win
+        disk.optimal_io_size = 33553920;
+        assert_eq!(
+            Alignment::for_device(&disk, None).unwrap().grain_bytes,
+            1 << 20
+        );
+        assert_eq!(
+            Alignment::for_device(&disk, Some(4 << 20)).unwrap(),
+            Alignment {
+                grain_bytes: 4 << 20,
+                offset_bytes: 3584,
+            }
+        );
+        disk.logical_sector_size = 4096;
+        assert!(matches!(
+            Alignment::for_device(&disk, Some(1000)),
+            Err(LayoutError::InvalidAlignment { .. })
+        ));
+    }
+
+    #[test]
+    fn test_layout_errors() {
+        let too_big = PartitionLayout::new()
+            .partition(PartitionSpec::new(PartitionSize::Bytes(GIB)))
+            .partition(PartitionSpec::new(PartitionSize::Remaining));
+        assert!(matches!(
+            too_big.sector_ranges(34, 100_000, 512, 2048),
+            Err(LayoutError::DoesNotFit { .. })
+        ));
+        assert!(matches!(
+            PartitionLayout::equal(8).sector_ranges(34, 4 * 2048, 512, 2048),
+            Err(LayoutError::DoesNotFit { .. })
+        ));
+        let over = PartitionLayout::new()
+            .partition(PartitionSpec::new(PartitionSize::Percent(60)))
+            .partition(PartitionSpec::new(PartitionSize::Percent(50)));
+        assert!(matches!(
+            over.sector_ranges(34, 1 << 30, 512, 2048),
+            Err(LayoutError::PercentOverflow(110))
+        ));
+        assert!(matches!(
+            PartitionLayout::new().sector_ranges(34, 1 << 30, 512, 2048),
+            Err(LayoutError::Empty)
+        ));
+    }
+}
@@ -2,8 +2,65 @@
 //!
 //! This module handles partition creation with proper error handling and modern tools
 
-use std::io::Write;
+use crate::block_device::{resolve_device_path_in, resolve_device_paths_in};
+use crate::device_discovery::{
+    is_whole_disk, multipath_partition, partition_by_number, partition_names,
+};
+use crate::gpt::BOOT_CODE_BYTES;
+use crate::mbr::{gpt_type, mbr_type};
+use crate::signature::{is_blank, probe_device_signatures_in};
+use crate::system_disk::{describe_roles, find_system_disks_in, system_roles, SystemDisk};
+use crate::table_backup::backup_partition_table_in;
+use crate::{
+    busy_reasons_in, read_partition_table_in, Alignment, BlockDevice, BlockDeviceError,
+    DeviceUsageError, GptDisk, GptError, GptPartition, Guid, LayoutError, MbrDisk, MbrError,
+    PartitionLayout, PartitionTable, PartitionTableError, PartitionType, SignatureError,
+    SystemDiskError, SystemRoot, TableBackup, TableBackupError, TableKind,
+};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Largest disk an MBR is written to; beyond it the table can't address the
+/// whole disk and the rest would silently go unused
+pub(crate) const MBR_MAX_BYTES: u64 = 2 << 40;
+
+/// `BLKRRPART`: ask the kernel to re-read a disk's partition table
+const BLKRRPART: libc::c_ulong = 0x125f;
+/// `BLKPG`: add or remove single partitions while others are busy
+const BLKPG: libc::c_ulong = 0x1269;
+const BLKPG_ADD_PARTITION: libc::c_int = 1;
+const BLKPG_DEL_PARTITION: libc::c_int = 2;
+
+/// GPT attribute bit marking a partition bootable by legacy BIOS, the MBR active flag
+const LEGACY_BIOS_BOOTABLE: u64 = 1 << 2;
+
+/// How often sysfs and /dev are checked while waiting for partition nodes
+const NODE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `struct blkpg_partition` from `<linux/blkpg.h>`
+#[repr(C)]
+struct BlkpgPartition {
+    start: libc::c_longlong,
+    length: libc::c_longlong,
+    pno: libc::c_int,
+    devname: [libc::c_char; 64],
+    volname: [libc::c_char; 64],
+}
+
+/// `struct blkpg_ioctl_arg` from `<linux/blkpg.h>`
+#[repr(C)]
+struct BlkpgIoctlArg {
+    op: libc::c_int,
+    flags: libc::c_int,
+    datalen: libc::c_int,
+    data: *mut libc::c_void,
+}
 
 /// Errors that can occur during partition operations
 #[derive(Debug, thiserror::Error)]
@@ -18,6 +75,60 @@ pub enum PartitionError {
     InvalidDevicePath(String),
     #[error("Partition creation failed for device: {0}")]
     PartitionCreationFailed(String),
+    #[error("Block device error: {0}")]
+    BlockDevice(#[from] BlockDeviceError),
+    #[error("Signature probing failed: {0}")]
+    Signature(#[from] SignatureError),
+    #[error("Refusing to overwrite {device}: found {signatures}")]
+    NotBlank { device: String, signatures: String },
+    #[error("System disk detection failed: {0}")]
+    SystemDiskDetection(#[from] SystemDiskError),
+    #[error("GPT error: {0}")]
+    Gpt(#[from] GptError),
+    #[error("MBR error: {0}")]
+    Mbr(#[from] MbrError),
+    #[error("Refusing to write an MBR to {device}: {size_bytes} bytes is over 2 TiB")]
+    TooLargeForMbr { device: String, size_bytes: u64 },
+    #[error("Partition layout error: {0}")]
+    Layout(#[from] LayoutError),
+    #[error("Partition {partition} of {device} did not appear within {timeout:?}")]
+    PartitionNodeTimeout {
+        device: String,
+        partition: u32,
+        timeout: Duration,
+    },
+    #[error("Refusing to touch system disk {device}: {roles}")]
+    SystemDisk { device: String, roles: String },
+    #[error("Partition table backup failed: {0}")]
+    Backup(#[from] TableBackupError),
+    #[error("Device usage check failed: {0}")]
+    Usage(#[from] DeviceUsageError),
+    #[error("Refusing to change the partition table of {device}: in use by {reasons}")]
+    Busy { device: String, reasons: String },
+    #[error("Partition table error: {0}")]
+    PartitionTable(#[from] PartitionTableError),
+    #[error("{device} does not hold an MBR partition table")]
+    NotMbr { device: String },
+    #[error(
+        "No room for GPT on {device}: partition {partition} spans sectors {first_lba}-{last_lba}, \
+         but GPT leaves only {first_usable}-{last_usable} usable"
+    )]
+    NoRoomForGpt {
+        device: String,
+        partition: u32,
+        first_lba: u64,
+        last_lba: u64,
+        first_usable: u64,
+        last_usable: u64,
+    },
+    #[error(
+        "Partition {partition} of {device} has MBR type {type_id:#04x} with no GPT equivalent"
+    )]
+    UnmappedMbrType {
+        device: String,
+        partition: u32,
+        type_id: u8,
+    },
 }
 
 impl From<std::io::Error> for PartitionError {
@@ -26,6 +137,58 @@ impl From<std::io::Error> for PartitionError {
     }
 }
 
+/// Configuration for partition table changes
+#[derive(Debug, Clone)]
+pub struct PartitionConfig {
+    /// Disks (`/dev/sdb` or `sdb`) that may be overwritten even though a
+    /// partition, filesystem or volume signature was found on them
+    pub allow_non_blank: Vec<String>,
+    /// Partitions created on every disk; one partition covering the disk by default
+    pub layout: PartitionLayout,
+    /// Partition table written by partition creation
+    pub table: TableKind,
+    /// Partition alignment in bytes (1 MiB, 4 MiB, ...); derived from each disk's
+    /// physical sector and I/O sizes when `None`
+    pub alignment: Option<u64>,
+    /// Save the old table of every disk before writing a new one
+    pub backup_tables: bool,
+    /// Where table backups go; `/var/backups/auto_mount` below the root when `None`
+    pub backup_dir: Option<PathBuf>,
+    /// How long to wait for the kernel and udev to create the new partition nodes
+    pub node_timeout: Duration,
+    /// Where sysfs and /dev are read from
+    pub root: SystemRoot,
+}
+
+impl Default for PartitionConfig {
+    fn default() -> Self {
+        Self {
+            allow_non_blank: Vec::new(),
+            layout: PartitionLayout::default(),
+            table: TableKind::Gpt,
+            alignment: None,
+            backup_tables: true,
+            backup_dir: None,
+            node_timeout: Duration::from_secs(10),
+            root: SystemRoot::default(),
+        }
+    }
+}
+
+impl PartitionConfig {
+    fn backup_dir(&self) -> PathBuf {
+        self.backup_dir
+            .clone()
+            .unwrap_or_else(|| self.root.partition_backups())
+    }
+
+    fn allows_non_blank(&self, device: &BlockDevice) -> bool {
+        self.allow_non_blank
+            .iter()
+            .any(|allowed| *allowed == device.path || *allowed == device.name)
+    }
+}
+
 /// Partition creation result
 #[derive(Debug, Clone)]
 pub struct PartitionResult {
@@ -34,41 +197,459 @@ pub struct PartitionResult {
     pub success: bool,
 }
 
-/// Create single partition on each device using modern parted command
+/// A partition created from a layout, with the sectors chosen for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacedPartition {
+    /// Partition node, e.g. `/dev/sdb1`
+    pub path: String,
+    /// Disk the partition was created on
+    pub disk: String,
+    pub number: u32,
+    pub first_lba: u64,
+    /// Last sector of the partition, inclusive
+    pub last_lba: u64,
+    /// Logical sector size the LBAs count in
+    pub sector_size: u64,
+    pub alignment: Alignment,
+}
+
+/// Create single partition on each device in a new GPT; devices may be given
+/// by any identifier `resolve_device_path` accepts
 pub fn create_partition(devices: &[String]) -> Result<Vec<String>, PartitionError> {
+    create_partition_with_config(devices, &PartitionConfig::default())
+}
+
+/// Create the configured layout on each device in a new GPT; returns the
+/// partition paths disk by disk, in layout order
+pub fn create_partition_with_config(
+    devices: &[String],
+    config: &PartitionConfig,
+) -> Result<Vec<String>, PartitionError> {
+    let devices = resolve_device_paths_in(&config.root, devices)?;
+    let devices = ensure_paths_safe(&devices, config)?;
     let mut partition_paths = Vec::new();
 
-    for device in devices {
-        let partition_path = create_single_partition_parted(device)?;
-        partition_paths.push(partition_path);
+    for device in &devices {
+        let placed = create_partitions(device, config)?;
+        partition_paths.extend(placed.into_iter().map(|partition| partition.path));
     }
 
     Ok(partition_paths)
 }
 
-/// Create partition using parted (recommended approach)
-fn create_single_partition_parted(device: &str) -> Result<String, PartitionError> {
-    validate_device_path(device)?;
+/// Create a single partition on each block device and read the new partitions back from sysfs
+pub fn create_block_device_partitions(
+    devices: &[BlockDevice],
+) -> Result<Vec<BlockDevice>, PartitionError> {
+    create_block_device_partitions_in(&SystemRoot::default(), devices)
+}
 
-    // Create partition using parted (more reliable than fdisk)
-    let output = Command::new("sudo")
-        .args([
-            "parted", "-s", // script mode (non-interactive)
-            device, "mkpart",  // make partition
-            "primary", // partition type
-            "ext4",    // filesystem type hint
-            "0%",      // start at beginning
-            "100%",    // use entire disk
-        ])
-        .output()?;
+/// Create a single partition on each block device, reading sysfs below the given root
+pub fn create_block_device_partitions_in(
+    root: &SystemRoot,
+    devices: &[BlockDevice],
+) -> Result<Vec<BlockDevice>, PartitionError> {
+    let config = PartitionConfig {
+        root: root.clone(),
+        ..Default::default()
+    };
+    create_block_device_partitions_with_config(devices, &config)
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(PartitionError::CommandFailed(stderr.to_string()));
+/// Create the configured layout on each block device; every disk must be blank
+/// unless the configuration allows overwriting it. Partitions are returned disk
+/// by disk, in layout order
+pub fn create_block_device_partitions_with_config(
+    devices: &[BlockDevice],
+    config: &PartitionConfig,
+) -> Result<Vec<BlockDevice>, PartitionError> {
+    create_block_device_partitions_with_report(devices, config)?
+        .iter()
+        .map(|partition| Ok(BlockDevice::from_path_in(&config.root, &partition.path)?))
+        .collect()
+}
+
+/// Create the configured layout on each block device like
+/// [`create_block_device_partitions_with_config`], reporting the start and end
+/// sector chosen for every partition
+pub fn create_block_device_partitions_with_report(
+    devices: &[BlockDevice],
+    config: &PartitionConfig,
+) -> Result<Vec<PlacedPartition>, PartitionError> {
+    ensure_safe(devices, config)?;
+    let mut partitions = Vec::new();
+
+    for device in devices {
+        partitions.extend(create_partitions(device, config)?);
+    }
+
+    Ok(partitions)
+}
+
+/// Check every disk before touching any, so one refusal leaves all of them untouched;
+/// system disks and busy disks are refused even when listed in `allow_non_blank`
+pub(crate) fn ensure_safe(
+    devices: &[BlockDevice],
+    config: &PartitionConfig,
+) -> Result<(), PartitionError> {
+    let system_disks = find_system_disks_in(&config.root)?;
+    for device in devices {
+        refuse_in_use(device, &system_disks, config)?;
+    }
+
+    ensure_blank(devices, config)
+}
+
+fn ensure_blank(devices: &[BlockDevice], config: &PartitionConfig) -> Result<(), PartitionError> {
+    for device in devices {
+        if config.allows_non_blank(device) {
+            continue;
+        }
+
+        let signatures = probe_device_signatures_in(&config.root, device)?;
+        if !is_blank(&signatures) {
+            let signatures: Vec<String> = signatures.iter().map(ToString::to_string).collect();
+            return Err(PartitionError::NotBlank {
+                device: device.path.clone(),
+                signatures: signatures.join(", "),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Refuse system disks and disks whose partitions are in use, for changes that
+/// keep the data on the disk
+fn ensure_not_in_use(device: &BlockDevice, config: &PartitionConfig) -> Result<(), PartitionError> {
+    let system_disks = find_system_disks_in(&config.root)?;
+    refuse_in_use(device, &system_disks, config)
+}
+
+/// Refuse a disk the system depends on or whose partitions are mounted, swapped
+/// on or held by another block device
+fn refuse_in_use(
+    device: &BlockDevice,
+    system_disks: &[SystemDisk],
+    config: &PartitionConfig,
+) -> Result<(), PartitionError> {
+    let roles = system_roles(&config.root, system_disks, &device.name);
+    if !roles.is_empty() {
+        return Err(PartitionError::SystemDisk {
+            device: device.path.clone(),
+            roles: describe_roles(&roles),
+        });
+    }
+    let busy = busy_reasons_in(&config.root, device)?;
+    if !busy.is_empty() {
+        let reasons: Vec<String> = busy.iter().map(ToString::to_string).collect();
+        return Err(PartitionError::Busy {
+            device: device.path.clone(),
+            reasons: reasons.join(", "),
+        });
+    }
+    Ok(())
+}
+
+fn ensure_paths_safe(
+    devices: &[String],
+    config: &PartitionConfig,
+) -> Result<Vec<BlockDevice>, PartitionError> {
+    let devices = devices
+        .iter()
+        .map(|device| {
+            validate_device_path_in(&config.root, device)?;
+            Ok(BlockDevice::from_path_in(&config.root, device)?)
+        })
+        .collect::<Result<Vec<_>, PartitionError>>()?;
+
+    ensure_safe(&devices, config)?;
+    Ok(devices)
+}
+
+/// Write a new partition table holding the configured layout and return the
+/// partitions in order, once the kernel and udev have created their nodes
+fn create_partitions(
+    device: &BlockDevice,
+    config: &PartitionConfig,
+) -> Result<Vec<PlacedPartition>, PartitionError> {
+    let layout = &config.layout;
+    validate_device_path_in(&config.root, &device.path)?;
+    let alignment = Alignment::for_device(device, config.alignment)?;
+
+    let ranges = match config.table {
+        TableKind::Gpt => write_gpt(config, device, |disk| {
+            let ranges = layout.aligned_ranges(
+                disk.first_usable_lba(),
+                disk.last_usable_lba(),
+                disk.sector_size,
+                &alignment,
+            )?;
+            for (spec, (first, last)) in layout.partitions.iter().zip(ranges) {
+                disk.add_partition(spec.partition_type, first, last, &spec.name)?;
+            }
+            Ok(())
+        })?,
+        TableKind::Mbr => write_mbr(config, device, |disk| {
+            let ranges = layout.aligned_ranges(
+                disk.first_usable_lba(),
+                disk.last_usable_lba(),
+                disk.sector_size,
+                &alignment,
+            )?;
+            for (spec, (first, last)) in layout.partitions.iter().zip(ranges) {
+                let type_id = mbr_type(spec.partition_type)
+                    .ok_or(MbrError::UnmappedType(spec.partition_type))?;
+                disk.add_partition(type_id, first, last)?;
+            }
+            Ok(())
+        })?,
+    };
+
+    let paths = wait_for_partition_nodes(
+        &config.root,
+        device,
+        ranges.len() as u32,
+        config.node_timeout,
+    )?;
+    Ok(paths
+        .into_iter()
+        .zip(ranges)
+        .enumerate()
+        .map(|(index, (path, (first_lba, last_lba)))| PlacedPartition {
+            path,
+            disk: device.path.clone(),
+            number: index as u32 + 1,
+            first_lba,
+            last_lba,
+            sector_size: sector_size(device),
+            alignment,
+        })
+        .collect())
+}
+
+/// Wait until partitions `1..=count` of a disk are known to sysfs and have a
+/// node in /dev, and return their paths in order
+fn wait_for_partition_nodes(
+    root: &SystemRoot,
+    device: &BlockDevice,
+    count: u32,
+    timeout: Duration,
+) -> Result<Vec<String>, PartitionError> {
+    let start = Instant::now();
+    loop {
+        let mut paths = Vec::new();
+        for number in 1..=count {
+            match partition_node(root, device, number) {
+                Some(path) if root.dev_path(&path).exists() => paths.push(path),
+                _ if start.elapsed() >= timeout => {
+                    return Err(PartitionError::PartitionNodeTimeout {
+                        device: device.path.clone(),
+                        partition: number,
+                        timeout,
+                    })
+                }
+                _ => break,
+            }
+        }
+        if paths.len() == count as usize {
+            return Ok(paths);
+        }
+        thread::sleep(NODE_POLL_INTERVAL);
     }
+}
 
-    // Return the first partition path
-    Ok(format!("{}1", device))
+/// Path of a partition by number, looked up in sysfs rather than derived from
+/// the disk name (`sdb1`, `nvme0n1p1`, `mmcblk0p1`, multipath `/dev/mapper/...`)
+fn partition_node(root: &SystemRoot, device: &BlockDevice, number: u32) -> Option<String> {
+    if device.multipath_paths.is_empty() {
+        return partition_by_number(root, &device.name, number)
+            .map(|name| format!("/dev/{}", name));
+    }
+
+    // kpartx names the partition after the map, with a distro-specific separator
+    let name = multipath_partition(root, &device.name, number)?;
+    BlockDevice::from_name_in(root, &name)
+        .ok()
+        .map(|partition| partition.path)
+}
+
+/// Write a fresh GPT laid out by `layout` to a disk and have the kernel pick it up;
+/// returns the sector range of every partition
+fn write_gpt<F>(
+    config: &PartitionConfig,
+    device: &BlockDevice,
+    layout: F,
+) -> Result<Vec<(u64, u64)>, PartitionError>
+where
+    F: FnOnce(&mut GptDisk) -> Result<(), PartitionError>,
+{
+    let (mut file, sector_size, total_sectors) = open_disk(config, device)?;
+    let mut disk = GptDisk::new(total_sectors, sector_size)?;
+    layout(&mut disk)?;
+    disk.write(&mut file)?;
+    file.sync_all()?;
+
+    let ranges: Vec<(u64, u64)> = disk
+        .partitions
+        .iter()
+        .map(|partition| (partition.first_lba, partition.last_lba))
+        .collect();
+    reread_partition_table(&config.root, &file, device, sector_size, &numbered(&ranges))?;
+    Ok(ranges)
+}
+
+/// Write a fresh MBR laid out by `layout` to a disk and have the kernel pick it up;
+/// returns the sector range of every partition
+fn write_mbr<F>(
+    config: &PartitionConfig,
+    device: &BlockDevice,
+    layout: F,
+) -> Result<Vec<(u64, u64)>, PartitionError>
+where
+    F: FnOnce(&mut MbrDisk) -> Result<(), PartitionError>,
+{
+    if device.size_bytes > MBR_MAX_BYTES {
+        return Err(PartitionError::TooLargeForMbr {
+            device: device.path.clone(),
+            size_bytes: device.size_bytes,
+        });
+    }
+
+    let (mut file, sector_size, total_sectors) = open_disk(config, device)?;
+    let mut disk = MbrDisk::new(total_sectors, sector_size)?;
+    layout(&mut disk)?;
+    disk.write(&mut file)?;
+    file.sync_all()?;
+
+    let ranges: Vec<(u64, u64)> = disk
+        .partitions
+        .iter()
+        .map(|partition| (partition.first_lba, partition.last_lba))
+        .collect();
+    reread_partition_table(&config.root, &file, device, sector_size, &numbered(&ranges))?;
+    Ok(ranges)
+}
+
+/// Open a disk node for writing, with its logical sector size and length in
+/// sectors, saving its current table first when backups are enabled
+fn open_disk(
+    config: &PartitionConfig,
+    device: &BlockDevice,
+) -> Result<(File, u64, u64), PartitionError> {
+    if config.backup_tables {
+        backup_partition_table_in(&config.root, device, &config.backup_dir())?;
+    }
+    let (sector_size, total_sectors) = disk_geometry(config, device)?;
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(config.root.dev_path(&device.path))?;
+    Ok((file, sector_size, total_sectors))
+}
+
+/// Logical sector size and length in sectors of a disk node, opened read-only
+fn disk_geometry(
+    config: &PartitionConfig,
+    device: &BlockDevice,
+) -> Result<(u64, u64), PartitionError> {
+    let mut file = File::open(config.root.dev_path(&device.path))?;
+    let sector_size = sector_size(device);
+    let total_sectors = file.seek(SeekFrom::End(0))? / sector_size;
+    Ok((sector_size, total_sectors))
+}
+
+/// Number partition ranges from 1, in order
+fn numbered(ranges: &[(u64, u64)]) -> Vec<(u32, u64, u64)> {
+    ranges
+        .iter()
+        .enumerate()
+        .map(|(index, (first, last))| (index as u32 + 1, *first, *last))
+        .collect()
+}
+
+fn sector_size(device: &BlockDevice) -> u64 {
+    u64::from(device.logical_sector_size.max(512))
+}
+
+/// Tell the kernel about the new table; image files have nothing to re-read
+fn reread_partition_table(
+    root: &SystemRoot,
+    file: &File,
+    device: &BlockDevice,
+    sector_size: u64,
+    partitions: &[(u32, u64, u64)],
+) -> Result<(), PartitionError> {
+    if !file.metadata()?.file_type().is_block_device() {
+        return Ok(());
+    }
+
+    if !device.multipath_paths.is_empty() {
+        // Partitions of a multipath map are device-mapper targets created by kpartx
+        let output = Command::new("sudo")
+            .args(["kpartx", "-u", &device.path])
+            .output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(PartitionError::CommandFailed(stderr.to_string()));
+        }
+        return Ok(());
+    }
+
+    // SAFETY: BLKRRPART takes no argument and the descriptor is open for the whole call
+    if unsafe { libc::ioctl(file.as_raw_fd(), BLKRRPART as _) } == 0 {
+        return Ok(());
+    }
+    let error = std::io::Error::last_os_error();
+    if error.raw_os_error() != Some(libc::EBUSY) {
+        return Err(error.into());
+    }
+
+    // Something still holds the disk open (often a udev probe); update the
+    // kernel's view partition by partition instead
+    for name in partition_names(root, &device.name) {
+        let number = fs::read_to_string(root.sys_class_block().join(&name).join("partition"))
+            .ok()
+            .and_then(|number| number.trim().parse().ok());
+        if let Some(number) = number {
+            blkpg(file, BLKPG_DEL_PARTITION, number, 0, 0)?;
+        }
+    }
+    for (number, first, last) in partitions {
+        let start = first * sector_size;
+        let length = (last - first + 1) * sector_size;
+        blkpg(file, BLKPG_ADD_PARTITION, *number as i32, start, length)?;
+    }
+    Ok(())
+}
+
+/// Add or delete one partition in the kernel's view of a disk
+fn blkpg(
+    file: &File,
+    op: libc::c_int,
+    number: libc::c_int,
+    start: u64,
+    length: u64,
+) -> Result<(), PartitionError> {
+    let mut partition = BlkpgPartition {
+        start: start as libc::c_longlong,
+        length: length as libc::c_longlong,
+        pno: number,
+        devname: [0; 64],
+        volname: [0; 64],
+    };
+    let mut arg = BlkpgIoctlArg {
+        op,
+        flags: 0,
+        datalen: std::mem::size_of::<BlkpgPartition>() as libc::c_int,
+        data: &mut partition as *mut BlkpgPartition as *mut libc::c_void,
+    };
+
+    // SAFETY: both structs match <linux/blkpg.h> and outlive the call
+    if unsafe { libc::ioctl(file.as_raw_fd(), BLKPG as _, &mut arg) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
 }
 
 /// Create partition using fdisk (fallback method)
@@ -99,27 +680,234 @@ fn create_single_partition_fdisk(device: &str) -> Result<String, PartitionError>
         return Err(PartitionError::CommandFailed(stderr.to_string()));
     }
 
-    Ok(format!("{}1", device))
+    Ok(partition_path(device, 1))
 }
 
 /// Validate device path format
 fn validate_device_path(device: &str) -> Result<(), PartitionError> {
-    if !device.starts_with("/dev/") {
-        return Err(PartitionError::InvalidDevicePath(device.to_string()));
-    }
+    validate_device_path_in(&SystemRoot::default(), device)
+}
 
-    // Additional validation for SATA devices
-    if device.starts_with("/dev/sd") && device.len() == 8 {
-        // Valid SATA device format like /dev/sda
+/// Validate that a device path names a whole disk, judged by sysfs structure;
+/// `/dev/mapper` paths are judged by the `dm-N` node they point at
+fn validate_device_path_in(root: &SystemRoot, device: &str) -> Result<(), PartitionError> {
+    let resolved = if device.starts_with("/dev/mapper/") {
+        resolve_device_path_in(root, device)
+            .map_err(|_| PartitionError::InvalidDevicePath(device.to_string()))?
+    } else {
+        device.to_string()
+    };
+    let name = resolved
+        .strip_prefix("/dev/")
+        .ok_or_else(|| PartitionError::InvalidDevicePath(device.to_string()))?;
+
+    if is_whole_disk(root, name) {
         Ok(())
     } else {
         Err(PartitionError::InvalidDevicePath(device.to_string()))
     }
 }
 
+/// Build the node path of a partition; disks whose names end in a digit
+/// (nvme0n1, mmcblk0) use a `p` separator
+pub(crate) fn partition_path(device: &str, number: u32) -> String {
+    if device.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", device, number)
+    } else {
+        format!("{}{}", device, number)
+    }
+}
+
+/// Convert devices to GPT partition table (supports devices larger than 4TB);
+/// devices may be given by any identifier `resolve_device_path` accepts. The new
+/// GPT is empty; [`convert_mbr_to_gpt`] keeps existing MBR partitions instead
+pub fn change_devices_to_gpt(devices: &[String]) -> Result<(), PartitionError> {
+    let config = PartitionConfig::default();
+    let devices = resolve_device_paths_in(&config.root, devices)?;
+    for device in &ensure_paths_safe(&devices, &config)? {
+        change_single_device_to_gpt(&config, device)?;
+    }
+    Ok(())
+}
+
+/// Convert block devices to GPT partition table
+pub fn change_block_devices_to_gpt(devices: &[BlockDevice]) -> Result<(), PartitionError> {
+    change_block_devices_to_gpt_with_config(devices, &PartitionConfig::default())
+}
+
+/// Convert block devices to GPT partition table; every disk must be blank
+/// unless the configuration allows overwriting it
+pub fn change_block_devices_to_gpt_with_config(
+    devices: &[BlockDevice],
+    config: &PartitionConfig,
+) -> Result<(), PartitionError> {
+    ensure_safe(devices, config)?;
+    for device in devices {
+        change_single_device_to_gpt(config, device)?;
+    }
+    Ok(())
+}
+
+/// Write an empty GPT to a single device
+fn change_single_device_to_gpt(
+    config: &PartitionConfig,
+    device: &BlockDevice,
+) -> Result<(), PartitionError> {
+    validate_device_path_in(&config.root, &device.path)?;
+    write_gpt(config, device, |_| Ok(()))?;
+    Ok(())
+}
+
+/// Convert the MBR of a disk to GPT in place, keeping its partitions; see
+/// [`convert_mbr_to_gpt_with_config`]
+pub fn convert_mbr_to_gpt(device: &BlockDevice) -> Result<PartitionTable, PartitionError> {
+    convert_mbr_to_gpt_with_config(device, &PartitionConfig::default())
+}
+
+/// Replace the MBR of a disk with a GPT holding the same partitions, sector for
+/// sector, and return the new table. MBR types become their GPT type GUIDs and
+/// the active flag the legacy BIOS bootable attribute; logical partitions are
+/// renumbered to follow the primary ones. Disks whose partitions leave no room
+/// for the primary or backup GPT, or whose types have no GPT equivalent, are
+/// refused before anything is written
+pub fn convert_mbr_to_gpt_with_config(
+    device: &BlockDevice,
+    config: &PartitionConfig,
+) -> Result<PartitionTable, PartitionError> {
+    validate_device_path_in(&config.root, &device.path)?;
+    ensure_not_in_use(device, config)?;
+
+    let table = read_partition_table_in(&config.root, device)?;
+    if table.kind != TableKind::Mbr {
+        return Err(PartitionError::NotMbr {
+            device: device.path.clone(),
+        });
+    }
+    // The bootable attribute is only useful if the BIOS still finds the boot code
+    let mut boot_code = vec![0; BOOT_CODE_BYTES];
+    File::open(config.root.dev_path(&device.path))?.read_exact(&mut boot_code)?;
+
+    // Usable area the new GPT leaves for the disk's geometry
+    let limits = GptDisk::with_guid(table.total_sectors, table.sector_size, Guid::ZERO)?;
+    let (first_usable, last_usable) = (limits.first_usable_lba(), limits.last_usable_lba());
+    let mut partitions = Vec::new();
+    for partition in &table.partitions {
+        let type_id = match partition.partition_type {
+            PartitionType::Mbr(type_id) => type_id,
+            PartitionType::Gpt(_) => {
+                return Err(PartitionError::NotMbr {
+                    device: device.path.clone(),
+                })
+            }
+        };
+        if partition.partition_type.is_extended() {
+            continue;
+        }
+        if partition.first_lba < first_usable || partition.last_lba > last_usable {
+            return Err(PartitionError::NoRoomForGpt {
+                device: device.path.clone(),
+                partition: partition.number,
+                first_lba: partition.first_lba,
+                last_lba: partition.last_lba,
+                first_usable,
+                last_usable,
+            });
+        }
+        let type_guid = gpt_type(type_id).ok_or_else(|| PartitionError::UnmappedMbrType {
+            device: device.path.clone(),
+            partition: partition.number,
+            type_id,
+        })?;
+        partitions.push(GptPartition {
+            type_guid,
+            unique_guid: Guid::random()?,
+            first_lba: partition.first_lba,
+            last_lba: partition.last_lba,
+            attributes: if partition.bootable {
+                LEGACY_BIOS_BOOTABLE
+            } else {
+                0
+            },
+            name: String::new(),
+        });
+    }
+
+    let count = partitions.len() as u32;
+    write_gpt(config, device, |disk| {
+        disk.boot_code = boot_code;
+        for partition in partitions {
+            disk.push(partition)?;
+        }
+        Ok(())
+    })?;
+    wait_for_partition_nodes(&config.root, device, count, config.node_timeout)?;
+    Ok(read_partition_table_in(&config.root, device)?)
+}
+
+/// Write a saved partition table back to its disk; see
+/// [`restore_partition_table_with_config`]
+pub fn restore_partition_table(
+    backup_dir: &Path,
+    device: &BlockDevice,
+) -> Result<TableBackup, PartitionError> {
+    restore_partition_table_with_config(backup_dir, device, &PartitionConfig::default())
+}
+
+/// Write the table saved in `backup_dir` back to a disk, once the backup has
+/// passed its checksums and matches the disk's size and sector size. System
+/// disks and disks in use are refused, and the table being replaced is backed
+/// up in turn when backups are enabled
+pub fn restore_partition_table_with_config(
+    backup_dir: &Path,
+    device: &BlockDevice,
+    config: &PartitionConfig,
+) -> Result<TableBackup, PartitionError> {
+    let backup = TableBackup::load(backup_dir)?;
+    validate_device_path_in(&config.root, &device.path)?;
+
+    ensure_not_in_use(device, config)?;
+
+    // A backup of another disk is refused before anything is saved or opened for writing
+    let (sector_size, total_sectors) = disk_geometry(config, device)?;
+    backup.check_target(total_sectors * sector_size, sector_size)?;
+    let (mut file, _, _) = open_disk(config, device)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&backup.head)?;
+    file.seek(SeekFrom::Start(
+        backup.size_bytes - backup.tail.len() as u64,
+    ))?;
+    file.write_all(&backup.tail)?;
+    for (lba, sector) in &backup.ebrs {
+        file.seek(SeekFrom::Start(lba * sector_size))?;
+        file.write_all(sector)?;
+    }
+    file.sync_all()?;
+
+    let partitions: Vec<(u32, u64, u64)> = backup
+        .table()?
+        .map(|table| table.partitions)
+        .unwrap_or_default()
+        .iter()
+        .filter(|partition| !partition.partition_type.is_extended())
+        .map(|partition| (partition.number, partition.first_lba, partition.last_lba))
+        .collect();
+    reread_partition_table(&config.root, &file, device, sector_size, &partitions)?;
+    Ok(backup)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
+    use crate::{Guid, PartitionSize, PartitionSpec};
+
+    fn config_in(root: &SystemRoot) -> PartitionConfig {
+        PartitionConfig {
+            node_timeout: Duration::from_millis(500),
+            root: root.clone(),
+            ..Default::default()
+        }
+    }
     /// Result of GPT conversion operation
     #[derive(Debug, Clone)]
     pub struct GptConversionResult {
@@ -139,10 +927,502 @@ mod tests {
     fn test_validate_device_path_invalid() {
         assert!(validate_device_path("sda").is_err());
         assert!(validate_device_path("/dev/").is_err());
-        assert!(validate_device_path("/dev/nvme0n1").is_err());
+        assert!(validate_device_path("/dev/nvme0n1p1").is_err());
         assert!(validate_device_path("/dev/sda1").is_err());
     }
 
+    #[test]
+    fn test_validate_device_path_beyond_26_disks() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdaa").partition(1 << 30))
+            .disk(FakeDisk::new("sdzz"))
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        assert!(validate_device_path_in(root, "/dev/sdaa").is_ok());
+        assert!(validate_device_path_in(root, "/dev/sdzz").is_ok());
+        assert!(validate_device_path_in(root, "/dev/sdaa1").is_err());
+        // Unknown to sysfs: judged by kernel naming
+        assert!(validate_device_path_in(root, "/dev/sdab").is_ok());
+        assert!(validate_device_path_in(root, "/dev/sdab1").is_err());
+    }
+
+    #[test]
+    fn test_refuses_non_blank_disks() {
+        let mut lvm = vec![0; 1 << 20];
+        lvm[512..520].copy_from_slice(b"LABELONE");
+        lvm[536..544].copy_from_slice(b"LVM2 001");
+        let mut empty_mbr = vec![0; 1 << 20];
+        empty_mbr[510..512].copy_from_slice(&[0x55, 0xaa]);
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb"))
+            .disk(FakeDisk::new("sdc"))
+            .disk(FakeDisk::new("sdd"))
+            .file("dev/sdc", lvm)
+            .file("dev/sdd", empty_mbr)
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let devices: Vec<BlockDevice> = ["sdb", "sdc", "sdd"]
+            .iter()
+            .map(|name| BlockDevice::from_name_in(root, name).unwrap())
+            .collect();
+        let mut config = PartitionConfig {
+            root: root.clone(),
+            ..Default::default()
+        };
+
+        assert!(ensure_blank(&[devices[0].clone(), devices[2].clone()], &config).is_ok());
+        match ensure_blank(&devices, &config) {
+            Err(PartitionError::NotBlank { device, signatures }) => {
+                assert_eq!(device, "/dev/sdc");
+                assert_eq!(signatures, "LVM2_member");
+            }
+            other => panic!("expected NotBlank, got {:?}", other),
+        }
+
+        config.allow_non_blank.push("/dev/sdc".to_string());
+        assert!(ensure_blank(&devices, &config).is_ok());
+    }
+
+    #[test]
+    fn test_refuses_system_disks() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").partition(1 << 20))
+            .disk(FakeDisk::new("sdb"))
+            .file("etc/fstab", "/dev/sda1 /boot/efi vfat noauto 0 1\n")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let devices: Vec<BlockDevice> = ["sda", "sdb"]
+            .iter()
+            .map(|name| BlockDevice::from_name_in(root, name).unwrap())
+            .collect();
+        let config = PartitionConfig {
+            allow_non_blank: vec!["sda".to_string()],
+            root: root.clone(),
+            ..Default::default()
+        };
+
+        match ensure_safe(&devices, &config) {
+            Err(PartitionError::SystemDisk { device, roles }) => {
+                assert_eq!(device, "/dev/sda");
+                assert_eq!(roles, "backs fstab entry /boot/efi");
+            }
+            other => panic!("expected SystemDisk, got {:?}", other),
+        }
+        assert!(ensure_safe(&devices[1..], &config).is_ok());
+    }
+
+    #[test]
+    fn test_refuses_busy_disks() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").partition(1 << 20))
+            .disk(FakeDisk::new("sdc"))
+            .md("md0", &["sdb1"])
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let devices: Vec<BlockDevice> = ["sdb", "sdc"]
+            .iter()
+            .map(|name| BlockDevice::from_name_in(root, name).unwrap())
+            .collect();
+        let config = PartitionConfig {
+            allow_non_blank: vec!["sdb".to_string()],
+            root: root.clone(),
+            ..Default::default()
+        };
+
+        match ensure_safe(&devices, &config) {
+            Err(PartitionError::Busy { device, reasons }) => {
+                assert_eq!(device, "/dev/sdb");
+                assert_eq!(reasons, "sdb1 is a member of md array md0");
+            }
+            other => panic!("expected Busy, got {:?}", other),
+        }
+        assert!(ensure_safe(&devices[1..], &config).is_ok());
+    }
+
+    #[test]
+    fn test_writes_gpt_to_disk_image() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb"))
+            // The kernel has already picked up the new partition
+            .disk(FakeDisk::new("sdc").partition(6 << 20))
+            .file("dev/sdb", vec![0; 8 << 20])
+            .file("dev/sdc", vec![0; 8 << 20])
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let config = config_in(root);
+        let sdb = BlockDevice::from_name_in(root, "sdb").unwrap();
+        let sdc = BlockDevice::from_name_in(root, "sdc").unwrap();
+
+        change_single_device_to_gpt(&config, &sdb).unwrap();
+        let signatures = probe_device_signatures_in(root, &sdb).unwrap();
+        assert_eq!(signatures, vec![crate::Signature::Gpt { partitions: 0 }]);
+
+        let placed = create_partitions(&sdc, &config).unwrap();
+        assert_eq!(placed.len(), 1);
+        assert_eq!(placed[0].path, "/dev/sdc1");
+        let signatures = probe_device_signatures_in(root, &sdc).unwrap();
+        assert_eq!(signatures, vec![crate::Signature::Gpt { partitions: 1 }]);
+        let table = crate::read_partition_table_in(root, &sdc).unwrap();
+        let partition = &table.partitions[0];
+        assert_eq!(
+            partition.partition_type,
+            crate::PartitionType::Gpt(Guid::LINUX_FILESYSTEM)
+        );
+        // 1 MiB to the last whole MiB before the backup table
+        assert_eq!(
+            (partition.first_lba, partition.last_lba),
+            (2048, 7 * 2048 - 1)
+        );
+    }
+
+    #[test]
+    fn test_creates_layout_in_order() {
+        let fixture = FixtureBuilder::new()
+            .disk(
+                FakeDisk::new("nvme0n1")
+                    .partition(4 << 20)
+                    .partition(3 << 20)
+                    .partition(7 << 20),
+            )
+            .file("dev/nvme0n1", vec![0; 16 << 20])
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let disk = BlockDevice::from_name_in(root, "nvme0n1").unwrap();
+        let mut config = config_in(root);
+        config.layout = PartitionLayout::new()
+            .partition(PartitionSpec::swap("4MiB".parse().unwrap()).name("swap"))
+            .partition(PartitionSpec::new(PartitionSize::Percent(25)))
+            .partition(PartitionSpec::new(PartitionSize::Remaining).name("data"));
+
+        let placed: Vec<(String, u32, u64, u64)> = create_partitions(&disk, &config)
+            .unwrap()
+            .into_iter()
+            .map(|p| (p.path, p.number, p.first_lba, p.last_lba))
+            .collect();
+        assert_eq!(
+            placed,
+            vec![
+                ("/dev/nvme0n1p1".to_string(), 1, 2048, 5 * 2048 - 1),
+                ("/dev/nvme0n1p2".to_string(), 2, 5 * 2048, 8 * 2048 - 1),
+                ("/dev/nvme0n1p3".to_string(), 3, 8 * 2048, 15 * 2048 - 1),
+            ]
+        );
+
+        let table = crate::read_partition_table_in(root, &disk).unwrap();
+        let summary: Vec<(u64, u64, Option<&str>)> = table
+            .partitions
+            .iter()
+            .map(|p| (p.first_lba, p.last_lba, p.partition_type.name()))
+            .collect();
+        // 14 usable MiB between the 1 MiB boundaries: 4 MiB, 3 MiB (25%, rounded down), rest
+        assert_eq!(
+            summary,
+            vec![
+                (2048, 5 * 2048 - 1, Some("Linux swap")),
+                (5 * 2048, 8 * 2048 - 1, Some("Linux filesystem")),
+                (8 * 2048, 15 * 2048 - 1, Some("Linux filesystem")),
+            ]
+        );
+        assert_eq!(table.partitions[2].name.as_deref(), Some("data"));
+
+        config.layout =
+            PartitionLayout::new().partition(PartitionSpec::new(PartitionSize::Bytes(1 << 30)));
+        assert!(matches!(
+            create_partitions(&disk, &config),
+            Err(PartitionError::Layout(LayoutError::DoesNotFit { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_restores_backed_up_table() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").partition(3 << 20).partition(3 << 20))
+            .disk(FakeDisk::new("sdc"))
+            .file("dev/sdb", vec![0; 8 << 20])
+            .file("dev/sdc", vec![0; 4 << 20])
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let sdb = BlockDevice::from_name_in(root, "sdb").unwrap();
+        let sdc = BlockDevice::from_name_in(root, "sdc").unwrap();
+        let mut config = config_in(root);
+        config.table = TableKind::Mbr;
+        config.layout = PartitionLayout::equal(2);
+        create_partitions(&sdb, &config).unwrap();
+        let original = crate::read_partition_table_in(root, &sdb).unwrap();
+
+        // Replacing the MBR saves it first
+        config.table = TableKind::Gpt;
+        create_partitions(&sdb, &config).unwrap();
+        let mut backups: Vec<PathBuf> = fs::read_dir(root.partition_backups())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        backups.sort();
+        assert_eq!(backups.len(), 2);
+        let saved = TableBackup::load(&backups[1]).unwrap();
+        assert!(saved.dump.contains("label: dos\n"));
+
+        restore_partition_table_with_config(&backups[1], &sdb, &config).unwrap();
+        assert_eq!(
+            crate::read_partition_table_in(root, &sdb).unwrap(),
+            original
+        );
+
+        // A mismatched disk is neither backed up nor written
+        let backup_count = fs::read_dir(root.partition_backups()).unwrap().count();
+        assert!(matches!(
+            restore_partition_table_with_config(&backups[1], &sdc, &config),
+            Err(PartitionError::Backup(
+                TableBackupError::SizeMismatch { .. }
+            ))
+        ));
+        assert_eq!(
+            fs::read_dir(root.partition_backups()).unwrap().count(),
+            backup_count
+        );
+        let image = fs::read(fixture.path().join("dev/sdc")).unwrap();
+        assert!(image.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_converts_mbr_to_gpt_in_place() {
+        let sectors = 16 << 11;
+        let mut image = std::io::Cursor::new(vec![0; 16 << 20]);
+        let mut mbr = MbrDisk::with_signature(sectors, 512, 1).unwrap();
+        mbr.add_partition(0x83, 63, 4095).unwrap();
+        mbr.add_partition(0x82, 4096, 8191).unwrap();
+        mbr.add_partition(0x0c, 8192, sectors - 34).unwrap();
+        mbr.partitions[0].bootable = true;
+        mbr.write(&mut image).unwrap();
+        let boot_code: Vec<u8> = (0..440).map(|byte| byte as u8 | 1).collect();
+        image.get_mut()[..440].copy_from_slice(&boot_code);
+
+        let fixture = FixtureBuilder::new()
+            .disk(
+                FakeDisk::new("sdb")
+                    .partition(2 << 20)
+                    .partition(2 << 20)
+                    .partition(12 << 20),
+            )
+            .file("dev/sdb", image.into_inner())
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let sdb = BlockDevice::from_name_in(root, "sdb").unwrap();
+        let config = config_in(root);
+
+        let table = convert_mbr_to_gpt_with_config(&sdb, &config).unwrap();
+        assert_eq!(table.kind, TableKind::Gpt);
+        let summary: Vec<(u64, u64, PartitionType, u64)> = table
+            .partitions
+            .iter()
+            .map(|p| (p.first_lba, p.last_lba, p.partition_type, p.attributes))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (63, 4095, PartitionType::Gpt(Guid::LINUX_FILESYSTEM), 1 << 2),
+                (4096, 8191, PartitionType::Gpt(Guid::LINUX_SWAP), 0),
+                (
+                    8192,
+                    sectors - 34,
+                    PartitionType::Gpt(Guid::MICROSOFT_BASIC_DATA),
+                    0
+                ),
+            ]
+        );
+        // The MBR was saved before it was replaced, and its boot code kept
+        assert_eq!(fs::read_dir(root.partition_backups()).unwrap().count(), 1);
+        let converted = fs::read(root.dev_path("/dev/sdb")).unwrap();
+        assert_eq!(&converted[..440], &boot_code[..]);
+        assert_eq!(converted[446 + 4], 0xee);
+
+        assert!(matches!(
+            convert_mbr_to_gpt_with_config(&sdb, &config),
+            Err(PartitionError::NotMbr { .. })
+        ));
+    }
+
+    #[test]
+    fn test_refuses_mbr_conversion_without_room() {
+        let sectors = 8 << 11;
+        let image = |first, last, type_id| {
+            let mut image = std::io::Cursor::new(vec![0; 8 << 20]);
+            let mut mbr = MbrDisk::with_signature(sectors, 512, 1).unwrap();
+            mbr.add_partition(type_id, first, last).unwrap();
+            mbr.write(&mut image).unwrap();
+            image.into_inner()
+        };
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").partition(8 << 20))
+            .disk(FakeDisk::new("sdc").partition(8 << 20))
+            .disk(FakeDisk::new("sdd").partition(7 << 20))
+            .file("dev/sdb", image(2048, sectors - 1, 0x83))
+            .file("dev/sdc", image(1, 8191, 0x83))
+            .file("dev/sdd", image(2048, 8191, 0x42))
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let config = config_in(root);
+        let convert = |name| {
+            let disk = BlockDevice::from_name_in(root, name).unwrap();
+            convert_mbr_to_gpt_with_config(&disk, &config)
+        };
+
+        // No room for the backup GPT at the end, nor the primary one at the start
+        match convert("sdb") {
+            Err(PartitionError::NoRoomForGpt {
+                partition,
+                last_lba,
+                last_usable,
+                ..
+            }) => {
+                assert_eq!(partition, 1);
+                assert_eq!(last_lba, sectors - 1);
+                assert_eq!(last_usable, sectors - 34);
+            }
+            other => panic!("expected NoRoomForGpt, got {:?}", other),
+        }
+        assert!(matches!(
+            convert("sdc"),
+            Err(PartitionError::NoRoomForGpt {
+                first_usable: 34,
+                ..
+            })
+        ));
+        assert!(matches!(
+            convert("sdd"),
+            Err(PartitionError::UnmappedMbrType { type_id: 0x42, .. })
+        ));
+
+        // Refusals leave the disks and the backup directory untouched
+        let sdb = BlockDevice::from_name_in(root, "sdb").unwrap();
+        assert_eq!(
+            read_partition_table_in(root, &sdb).unwrap().kind,
+            TableKind::Mbr
+        );
+        assert!(!root.partition_backups().exists());
+    }
+
+    #[test]
+    fn test_alignment_override_on_4kn_disk() {
+        let fixture = FixtureBuilder::new()
+            .disk(
+                FakeDisk::new("sdd")
+                    .sector_sizes(4096, 4096)
+                    .partition(12 << 20)
+                    .partition(12 << 20),
+            )
+            .file("dev/sdd", vec![0; 32 << 20])
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let disk = BlockDevice::from_name_in(root, "sdd").unwrap();
+        let mut config = config_in(root);
+        config.layout = PartitionLayout::equal(2);
+        config.alignment = Some(4 << 20);
+
+        let placed = create_partitions(&disk, &config).unwrap();
+        let ranges: Vec<(u64, u64)> = placed.iter().map(|p| (p.first_lba, p.last_lba)).collect();
+        // 4 MiB is 1024 sectors of 4 KiB; the backup GPT keeps the last 4 MiB free
+        assert_eq!(ranges, vec![(1024, 4095), (4096, 7167)]);
+        assert_eq!(placed[1].sector_size, 4096);
+        assert_eq!(placed[1].alignment.grain_bytes, 4 << 20);
+
+        config.alignment = Some(3000);
+        assert!(matches!(
+            create_partitions(&disk, &config),
+            Err(PartitionError::Layout(LayoutError::InvalidAlignment { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_writes_mbr_when_asked() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").partition(4 << 20).partition(3 << 20))
+            .disk(FakeDisk::new("sdc").size_bytes(3 << 40))
+            .file("dev/sdb", vec![0; 8 << 20])
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let mut config = config_in(root);
+        config.table = TableKind::Mbr;
+        config.layout = PartitionLayout::new()
+            .partition(PartitionSpec::swap(PartitionSize::Percent(50)))
+            .partition(PartitionSpec::new(PartitionSize::Remaining));
+
+        let sdb = BlockDevice::from_name_in(root, "sdb").unwrap();
+        let paths: Vec<String> = create_partitions(&sdb, &config)
+            .unwrap()
+            .into_iter()
+            .map(|partition| partition.path)
+            .collect();
+        assert_eq!(paths, vec!["/dev/sdb1", "/dev/sdb2"]);
+        let table = crate::read_partition_table_in(root, &sdb).unwrap();
+        assert_eq!(table.kind, TableKind::Mbr);
+        let types: Vec<String> = table
+            .partitions
+            .iter()
+            .map(|p| p.partition_type.to_string())
+            .collect();
+        assert_eq!(types, vec!["0x82", "0x83"]);
+
+        // Over 2 TiB: refused before the disk is opened
+        let sdc = BlockDevice::from_name_in(root, "sdc").unwrap();
+        match create_partitions(&sdc, &config) {
+            Err(PartitionError::TooLargeForMbr { device, size_bytes }) => {
+                assert_eq!(device, "/dev/sdc");
+                assert_eq!(size_bytes, 3 << 40);
+            }
+            other => panic!("expected TooLargeForMbr, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_waits_for_partition_nodes() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("mmcblk0"))
+            .disk(FakeDisk::new("sdb"))
+            .build()
+            .unwrap();
+        let root = fixture.root().clone();
+        let mmc = BlockDevice::from_name_in(&root, "mmcblk0").unwrap();
+        let sdb = BlockDevice::from_name_in(&root, "sdb").unwrap();
+        let timeout = Duration::from_secs(5);
+
+        // udev creates the node a moment after sysfs knows the partition
+        let part_dir = root.sys_block().join("mmcblk0/mmcblk0p1");
+        let node = root.dev_path("/dev/mmcblk0p1");
+        let udev = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(150));
+            fs::create_dir_all(&part_dir).unwrap();
+            fs::write(part_dir.join("partition"), "1\n").unwrap();
+            thread::sleep(Duration::from_millis(150));
+            fs::write(node, b"").unwrap();
+        });
+        let paths = wait_for_partition_nodes(&root, &mmc, 1, timeout).unwrap();
+        udev.join().unwrap();
+        assert_eq!(paths, vec!["/dev/mmcblk0p1"]);
+
+        match wait_for_partition_nodes(&root, &sdb, 1, Duration::from_millis(200)) {
+            Err(PartitionError::PartitionNodeTimeout {
+                device, partition, ..
+            }) => {
+                assert_eq!(device, "/dev/sdb");
+                assert_eq!(partition, 1);
+            }
+            other => panic!("expected PartitionNodeTimeout, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_partition_result_creation() {
         let result = PartitionResult {
@@ -158,10 +1438,10 @@ mod tests {
 
     #[test]
     fn test_partition_path_generation() {
-        let device = "/dev/sda";
-        let expected = "/dev/sda1";
-        let actual = format!("{}1", device);
-        assert_eq!(actual, expected);
+        assert_eq!(partition_path("/dev/sda", 1), "/dev/sda1");
+        assert_eq!(partition_path("/dev/sdaa", 1), "/dev/sdaa1");
+        assert_eq!(partition_path("/dev/nvme0n1", 1), "/dev/nvme0n1p1");
+        assert_eq!(partition_path("/dev/mmcblk0", 2), "/dev/mmcblk0p2");
     }
 
     #[test]
@@ -177,27 +1457,3 @@ mod tests {
         assert!(result.error_message.is_none());
     }
 }
-
-/// Convert devices to GPT partition table (supports devices larger than 4TB)
-pub fn change_devices_to_gpt(devices: &[String]) -> Result<(), PartitionError> {
-    for device in devices {
-        change_single_device_to_gpt(device)?;
-    }
-    Ok(())
-}
-
-/// Convert a single device to GPT partition table
-fn change_single_device_to_gpt(device: &str) -> Result<(), PartitionError> {
-    validate_device_path(device)?;
-
-    let output = Command::new("sudo")
-        .args(["parted", "-s", device, "mklabel", "gpt"])
-        .output()?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(PartitionError::CommandFailed(stderr.to_string()));
-    }
-
-    Ok(())
-}
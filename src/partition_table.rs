@@ -0,0 +1,685 @@
+//! Partition table module for auto_mount
+//!
+//! This module reads MBR (with extended/logical partitions) and GPT partition
+//! tables, validating GPT headers and CRCs and falling back to the backup GPT
+
+use crate::{BlockDevice, Guid, SystemRoot};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use strum::Display;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const MIN_GPT_HEADER_SIZE: usize = 92;
+const MIN_GPT_ENTRY_SIZE: u32 = 128;
+/// Refuse entry arrays larger than this instead of reading garbage sizes
+const MAX_GPT_ENTRY_BYTES: u64 = 1 << 20;
+const PROTECTIVE_MBR_TYPE: u8 = 0xee;
+/// Longest chain of extended boot records followed before giving up
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+/// Errors that can occur while reading a partition table
+#[derive(Debug, thiserror::Error)]
+pub enum PartitionTableError {
+    #[error("No partition table found")]
+    NoPartitionTable,
+    #[error("Unsupported sector size: {0}")]
+    InvalidSectorSize(u64),
+    #[error("Both GPT headers are invalid: {primary}; backup: {backup}")]
+    InvalidGpt { primary: String, backup: String },
+    #[error("IO error: {0}")]
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for PartitionTableError {
+    fn from(error: std::io::Error) -> Self {
+        PartitionTableError::IoError(error)
+    }
+}
+
+/// Partition table format, displayed with the name blkid reports for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum TableKind {
+    #[strum(serialize = "dos")]
+    Mbr,
+    #[strum(serialize = "gpt")]
+    Gpt,
+}
+
+/// Type of a partition: an MBR system ID or a GPT type GUID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    Mbr(u8),
+    Gpt(Guid),
+}
+
+impl PartitionType {
+    /// Whether this MBR entry is a container for logical partitions
+    pub fn is_extended(&self) -> bool {
+        matches!(self, PartitionType::Mbr(0x05 | 0x0f | 0x85))
+    }
+
+    /// Human-readable name of well-known types
+    pub fn name(&self) -> Option<&'static str> {
+        let name = match *self {
+            PartitionType::Mbr(0x05 | 0x0f | 0x85) => "Extended",
+            PartitionType::Mbr(0x07) => "HPFS/NTFS/exFAT",
+            PartitionType::Mbr(0x0b | 0x0c) => "W95 FAT32",
+            PartitionType::Mbr(0x82) => "Linux swap",
+            PartitionType::Mbr(0x83) => "Linux",
+            PartitionType::Mbr(0x8e) => "Linux LVM",
+            PartitionType::Mbr(0xef) => "EFI System",
+            PartitionType::Mbr(0xfd) => "Linux raid autodetect",
+            PartitionType::Gpt(guid) if guid == Guid::LINUX_FILESYSTEM => "Linux filesystem",
+            PartitionType::Gpt(guid) if guid == Guid::LINUX_SWAP => "Linux swap",
+            PartitionType::Gpt(guid) if guid == Guid::LINUX_LVM => "Linux LVM",
+            PartitionType::Gpt(guid) if guid == Guid::LINUX_RAID => "Linux RAID",
+            PartitionType::Gpt(guid) if guid == Guid::EFI_SYSTEM => "EFI System",
+            PartitionType::Gpt(guid) if guid == Guid::MICROSOFT_BASIC_DATA => {
+                "Microsoft basic data"
+            }
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
+impl fmt::Display for PartitionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionType::Mbr(id) => write!(f, "{:#04x}", id),
+            PartitionType::Gpt(guid) => write!(f, "{}", guid),
+        }
+    }
+}
+
+/// One partition as found in the table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionEntry {
+    /// Partition number the kernel uses (logical MBR partitions start at 5)
+    pub number: u32,
+    pub first_lba: u64,
+    /// Last sector of the partition, inclusive
+    pub last_lba: u64,
+    pub partition_type: PartitionType,
+    /// GPT unique partition GUID
+    pub unique_guid: Option<Guid>,
+    /// GPT partition name
+    pub name: Option<String>,
+    /// GPT attribute bits
+    pub attributes: u64,
+    /// MBR active flag
+    pub bootable: bool,
+    /// MBR partition inside the extended partition
+    pub logical: bool,
+}
+
+impl PartitionEntry {
+    pub fn sectors(&self) -> u64 {
+        self.last_lba - self.first_lba + 1
+    }
+}
+
+/// Unallocated sectors between partitions, inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeSpace {
+    pub first_lba: u64,
+    pub last_lba: u64,
+}
+
+impl FreeSpace {
+    pub fn sectors(&self) -> u64 {
+        self.last_lba - self.first_lba + 1
+    }
+}
+
+/// A partition table read from a disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionTable {
+    pub kind: TableKind,
+    pub sector_size: u64,
+    pub total_sectors: u64,
+    /// GPT disk GUID
+    pub disk_guid: Option<Guid>,
+    /// MBR disk signature
+    pub disk_signature: Option<u32>,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    /// Partitions by number; the MBR extended container is listed too
+    pub partitions: Vec<PartitionEntry>,
+    /// Gaps in the usable area not covered by any data partition
+    pub free: Vec<FreeSpace>,
+    /// The primary GPT was damaged and the backup was read instead
+    pub used_backup_gpt: bool,
+    /// Sectors holding the MBR's extended boot records, in chain order
+    pub ebr_lbas: Vec<u64>,
+}
+
+/// Read the partition table of a block device
+pub fn read_partition_table(device: &BlockDevice) -> Result<PartitionTable, PartitionTableError> {
+    read_partition_table_in(&SystemRoot::default(), device)
+}
+
+/// Read the partition table of a block device, opening its node below the given system root
+pub fn read_partition_table_in(
+    root: &SystemRoot,
+    device: &BlockDevice,
+) -> Result<PartitionTable, PartitionTableError> {
+    let mut file = File::open(root.dev_path(&device.path))?;
+    parse_partition_table(&mut file, u64::from(device.logical_sector_size.max(512)))
+}
+
+/// Parse the partition table of a device node or disk image with the given logical sector size
+pub fn parse_partition_table<R: Read + Seek>(
+    source: &mut R,
+    sector_size: u64,
+) -> Result<PartitionTable, PartitionTableError> {
+    if sector_size < 512 || !sector_size.is_power_of_two() {
+        return Err(PartitionTableError::InvalidSectorSize(sector_size));
+    }
+    let total_sectors = source.seek(SeekFrom::End(0))? / sector_size;
+    if total_sectors < 2 {
+        return Err(PartitionTableError::NoPartitionTable);
+    }
+    let mut reader = SectorReader {
+        source,
+        sector_size,
+        total_sectors,
+    };
+
+    let mbr = reader.read(0, 1)?;
+    let has_mbr = mbr[510..512] == [0x55, 0xaa];
+    let protective = has_mbr && mbr_entries(&mbr).any(|entry| entry.type_id == PROTECTIVE_MBR_TYPE);
+
+    // A wiped MBR with an intact GPT is still a GPT disk
+    if protective || !has_mbr {
+        match reader.read_gpt() {
+            Ok(table) => return Ok(table),
+            Err(PartitionTableError::InvalidGpt { .. }) if !protective => {
+                return Err(PartitionTableError::NoPartitionTable)
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    reader.read_mbr(&mbr)
+}
+
+struct SectorReader<'a, R> {
+    source: &'a mut R,
+    sector_size: u64,
+    total_sectors: u64,
+}
+
+/// A raw 16-byte MBR entry
+struct MbrEntry {
+    bootable: bool,
+    type_id: u8,
+    start: u64,
+    sectors: u64,
+}
+
+fn mbr_entries(sector: &[u8]) -> impl Iterator<Item = MbrEntry> + '_ {
+    (0..4).map(move |index| {
+        let entry = &sector[446 + index * 16..462 + index * 16];
+        MbrEntry {
+            bootable: entry[0] == 0x80,
+            type_id: entry[4],
+            start: u64::from(le_u32(entry, 8)),
+            sectors: u64::from(le_u32(entry, 12)),
+        }
+    })
+}
+
+/// A validated GPT header
+struct GptHeader {
+    first_usable: u64,
+    last_usable: u64,
+    disk_guid: Guid,
+    entry_count: u32,
+    entry_size: u32,
+    entries: Vec<u8>,
+}
+
+impl<'a, R: Read + Seek> SectorReader<'a, R> {
+    fn read(&mut self, lba: u64, count: u64) -> Result<Vec<u8>, PartitionTableError> {
+        let mut data = vec![0; (count * self.sector_size) as usize];
+        self.source.seek(SeekFrom::Start(lba * self.sector_size))?;
+        self.source.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_gpt(&mut self) -> Result<PartitionTable, PartitionTableError> {
+        let last_lba = self.total_sectors - 1;
+        let (header, used_backup) = match self.gpt_header(1) {
+            Ok(header) => (header, false),
+            Err(primary) => match self.gpt_header(last_lba) {
+                Ok(header) => (header, true),
+                Err(backup) => return Err(PartitionTableError::InvalidGpt { primary, backup }),
+            },
+        };
+
+        let mut partitions = Vec::new();
+        for index in 0..header.entry_count as usize {
+            let offset = index * header.entry_size as usize;
+            let entry = &header.entries[offset..offset + header.entry_size as usize];
+            let type_guid = guid_at(entry, 0);
+            if type_guid.is_zero() {
+                continue;
+            }
+            let name: Vec<u16> = entry[56..128]
+                .chunks(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .take_while(|&unit| unit != 0)
+                .collect();
+            partitions.push(PartitionEntry {
+                number: index as u32 + 1,
+                first_lba: le_u64(entry, 32),
+                last_lba: le_u64(entry, 40),
+                partition_type: PartitionType::Gpt(type_guid),
+                unique_guid: Some(guid_at(entry, 16)),
+                name: Some(String::from_utf16_lossy(&name)),
+                attributes: le_u64(entry, 48),
+                bootable: false,
+                logical: false,
+            });
+        }
+
+        Ok(self.table(
+            TableKind::Gpt,
+            (header.first_usable, header.last_usable),
+            partitions,
+            (Some(header.disk_guid), None),
+            used_backup,
+        ))
+    }
+
+    /// Read and validate the GPT header at `lba`; errors are descriptions for the report
+    fn gpt_header(&mut self, lba: u64) -> Result<GptHeader, String> {
+        let sector = self.read(lba, 1).map_err(|error| error.to_string())?;
+        if &sector[0..8] != GPT_SIGNATURE {
+            return Err(format!("no GPT signature at LBA {}", lba));
+        }
+        let header_size = le_u32(&sector, 12) as usize;
+        if header_size < MIN_GPT_HEADER_SIZE || header_size > sector.len() {
+            return Err(format!("bad header size {} at LBA {}", header_size, lba));
+        }
+        let mut header = sector[..header_size].to_vec();
+        let crc = le_u32(&header, 16);
+        header[16..20].copy_from_slice(&[0; 4]);
+        if crc32fast::hash(&header) != crc {
+            return Err(format!("header CRC mismatch at LBA {}", lba));
+        }
+        if le_u64(&header, 24) != lba {
+            return Err(format!("header at LBA {} claims another location", lba));
+        }
+
+        let first_usable = le_u64(&header, 40);
+        let last_usable = le_u64(&header, 48);
+        let entries_lba = le_u64(&header, 72);
+        let entry_count = le_u32(&header, 80);
+        let entry_size = le_u32(&header, 84);
+        let entry_bytes = u64::from(entry_count) * u64::from(entry_size);
+        if entry_size < MIN_GPT_ENTRY_SIZE
+            || entry_size % 8 != 0
+            || entry_bytes > MAX_GPT_ENTRY_BYTES
+            || first_usable > last_usable + 1
+            || last_usable >= self.total_sectors
+        {
+            return Err(format!("inconsistent header at LBA {}", lba));
+        }
+
+        let entry_sectors = (entry_bytes + self.sector_size - 1) / self.sector_size;
+        if entries_lba + entry_sectors > self.total_sectors {
+            return Err(format!("entry array beyond the disk for LBA {}", lba));
+        }
+        let mut entries = self
+            .read(entries_lba, entry_sectors)
+            .map_err(|error| error.to_string())?;
+        entries.truncate(entry_bytes as usize);
+        if crc32fast::hash(&entries) != le_u32(&header, 88) {
+            return Err(format!("entry array CRC mismatch for LBA {}", lba));
+        }
+
+        Ok(GptHeader {
+            first_usable,
+            last_usable,
+            disk_guid: guid_at(&header, 56),
+            entry_count,
+            entry_size,
+            entries,
+        })
+    }
+
+    fn read_mbr(&mut self, mbr: &[u8]) -> Result<PartitionTable, PartitionTableError> {
+        let mut partitions = Vec::new();
+        let mut extended = None;
+
+        for (index, entry) in mbr_entries(mbr).enumerate() {
+            if entry.type_id == 0 || entry.sectors == 0 {
+                continue;
+            }
+            let partition_type = PartitionType::Mbr(entry.type_id);
+            if partition_type.is_extended() && extended.is_none() {
+                extended = Some(entry.start);
+            }
+            partitions.push(PartitionEntry {
+                number: index as u32 + 1,
+                first_lba: entry.start,
+                last_lba: entry.start + entry.sectors - 1,
+                partition_type,
+                unique_guid: None,
+                name: None,
+                attributes: 0,
+                bootable: entry.bootable,
+                logical: false,
+            });
+        }
+
+        let mut ebr_lbas = Vec::new();
+        if let Some(extended_start) = extended {
+            partitions.extend(self.logical_partitions(extended_start, &mut ebr_lbas)?);
+        }
+
+        let last_usable = self.total_sectors.min(1 << 32) - 1;
+        let mut table = self.table(
+            TableKind::Mbr,
+            (1, last_usable),
+            partitions,
+            (None, Some(le_u32(mbr, 440))),
+            false,
+        );
+        table.ebr_lbas = ebr_lbas;
+        Ok(table)
+    }
+
+    /// Follow the chain of extended boot records; each holds one logical
+    /// partition (relative to itself) and a link to the next (relative to the container).
+    /// The sector of every valid EBR is added to `ebr_lbas`
+    fn logical_partitions(
+        &mut self,
+        extended_start: u64,
+        ebr_lbas: &mut Vec<u64>,
+    ) -> Result<Vec<PartitionEntry>, PartitionTableError> {
+        let mut logical = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut ebr_lba = extended_start;
+
+        while logical.len() < MAX_LOGICAL_PARTITIONS && ebr_lba < self.total_sectors {
+            if !visited.insert(ebr_lba) {
+                break;
+            }
+            let ebr = self.read(ebr_lba, 1)?;
+            if ebr[510..512] != [0x55, 0xaa] {
+                break;
+            }
+            ebr_lbas.push(ebr_lba);
+            let mut entries = mbr_entries(&ebr);
+            let (data, link) = match (entries.next(), entries.next()) {
+                (Some(data), Some(link)) => (data, link),
+                _ => break,
+            };
+
+            if data.type_id != 0 && data.sectors != 0 {
+                let first_lba = ebr_lba + data.start;
+                logical.push(PartitionEntry {
+                    number: 5 + logical.len() as u32,
+                    first_lba,
+                    last_lba: first_lba + data.sectors - 1,
+                    partition_type: PartitionType::Mbr(data.type_id),
+                    unique_guid: None,
+                    name: None,
+                    attributes: 0,
+                    bootable: data.bootable,
+                    logical: true,
+                });
+            }
+
+            if link.type_id == 0 || link.sectors == 0 {
+                break;
+            }
+            ebr_lba = extended_start + link.start;
+        }
+
+        Ok(logical)
+    }
+
+    fn table(
+        &self,
+        kind: TableKind,
+        (first_usable_lba, last_usable_lba): (u64, u64),
+        partitions: Vec<PartitionEntry>,
+        (disk_guid, disk_signature): (Option<Guid>, Option<u32>),
+        used_backup_gpt: bool,
+    ) -> PartitionTable {
+        let free = free_space(&partitions, first_usable_lba, last_usable_lba);
+        PartitionTable {
+            kind,
+            sector_size: self.sector_size,
+            total_sectors: self.total_sectors,
+            disk_guid,
+            disk_signature,
+            first_usable_lba,
+            last_usable_lba,
+            partitions,
+            free,
+            used_backup_gpt,
+            ebr_lbas: Vec::new(),
+        }
+    }
+}
+
+/// Gaps between data partitions; the extended container counts as free for this purpose
+fn free_space(partitions: &[PartitionEntry], first: u64, last: u64) -> Vec<FreeSpace> {
+    let mut used: Vec<(u64, u64)> = partitions
+        .iter()
+        .filter(|partition| !partition.partition_type.is_extended())
+        .map(|partition| (partition.first_lba, partition.last_lba))
+        .collect();
+    used.sort_unstable();
+
+    let mut free = Vec::new();
+    let mut next = first;
+    for (start, end) in used {
+        if start > next {
+            free.push(FreeSpace {
+                first_lba: next,
+                last_lba: (start - 1).min(last),
+            });
+        }
+        next = next.max(end.saturating_add(1));
+    }
+    if next <= last {
+        free.push(FreeSpace {
+            first_lba: next,
+            last_lba: last,
+        });
+    }
+    free.retain(|gap| gap.first_lba <= gap.last_lba);
+    free
+}
+
+fn guid_at(bytes: &[u8], offset: usize) -> Guid {
+    let mut guid = [0; 16];
+    guid.copy_from_slice(&bytes[offset..offset + 16]);
+    Guid::from_bytes(guid)
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn le_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GptDisk;
+    use std::io::Cursor;
+
+    const MIB: u64 = 1 << 20;
+
+    fn gpt_image() -> (GptDisk, Vec<u8>) {
+        let sectors = 32 * MIB / 512;
+        let mut disk = GptDisk::new(sectors, 512).unwrap();
+        disk.add_partition(Guid::EFI_SYSTEM, 2048, 4095, "EFI system")
+            .unwrap();
+        disk.add_partition(Guid::LINUX_FILESYSTEM, 8192, 40959, "data")
+            .unwrap();
+        let mut image = Cursor::new(vec![0; (32 * MIB) as usize]);
+        disk.write(&mut image).unwrap();
+        (disk, image.into_inner())
+    }
+
+    fn parse(image: Vec<u8>) -> Result<PartitionTable, PartitionTableError> {
+        parse_partition_table(&mut Cursor::new(image), 512)
+    }
+
+    fn mbr_entry(image: &mut [u8], sector: u64, slot: usize, type_id: u8, start: u32, len: u32) {
+        let offset = (sector * 512) as usize + 446 + slot * 16;
+        image[offset + 4] = type_id;
+        image[offset + 8..offset + 12].copy_from_slice(&start.to_le_bytes());
+        image[offset + 12..offset + 16].copy_from_slice(&len.to_le_bytes());
+        let signature = (sector * 512) as usize + 510;
+        image[signature..signature + 2].copy_from_slice(&[0x55, 0xaa]);
+    }
+
+    #[test]
+    fn test_read_gpt() {
+        let (disk, image) = gpt_image();
+        let table = parse(image).unwrap();
+
+        assert_eq!(table.kind, TableKind::Gpt);
+        assert_eq!(table.disk_guid, Some(disk.disk_guid));
+        assert_eq!(table.first_usable_lba, 34);
+        assert_eq!(table.last_usable_lba, 65536 - 34);
+        assert!(!table.used_backup_gpt);
+        assert_eq!(table.partitions.len(), 2);
+
+        let data = &table.partitions[1];
+        assert_eq!(data.number, 2);
+        assert_eq!((data.first_lba, data.last_lba), (8192, 40959));
+        assert_eq!(
+            data.partition_type,
+            PartitionType::Gpt(Guid::LINUX_FILESYSTEM)
+        );
+        assert_eq!(data.partition_type.name(), Some("Linux filesystem"));
+        assert_eq!(data.unique_guid, Some(disk.partitions[1].unique_guid));
+        assert_eq!(table.partitions[0].name.as_deref(), Some("EFI system"));
+
+        let free: Vec<(u64, u64)> = table
+            .free
+            .iter()
+            .map(|gap| (gap.first_lba, gap.last_lba))
+            .collect();
+        assert_eq!(free, vec![(34, 2047), (4096, 8191), (40960, 65502)]);
+    }
+
+    #[test]
+    fn test_gpt_falls_back_to_backup() {
+        // Damaged primary header
+        let (_, mut image) = gpt_image();
+        image[512 + 32] ^= 0xff;
+        let table = parse(image).unwrap();
+        assert!(table.used_backup_gpt);
+        assert_eq!(table.partitions.len(), 2);
+
+        // Damaged primary entry array
+        let (_, mut image) = gpt_image();
+        image[1024 + 40] ^= 0xff;
+        let table = parse(image).unwrap();
+        assert!(table.used_backup_gpt);
+        assert_eq!(table.partitions[0].last_lba, 4095);
+
+        // Both copies damaged
+        let (_, mut image) = gpt_image();
+        image[512] = 0;
+        let len = image.len();
+        image[len - 512] = 0;
+        match parse(image) {
+            Err(PartitionTableError::InvalidGpt { primary, backup }) => {
+                assert_eq!(primary, "no GPT signature at LBA 1");
+                assert_eq!(backup, "no GPT signature at LBA 65535");
+            }
+            other => panic!("expected InvalidGpt, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_mbr_with_logical_partitions() {
+        let mut image = vec![0; (64 * MIB) as usize];
+        image[440..444].copy_from_slice(&0xdead_beefu32.to_le_bytes());
+        mbr_entry(&mut image, 0, 0, 0x83, 2048, 20480);
+        image[446] = 0x80;
+        mbr_entry(&mut image, 0, 1, 0x05, 30720, 100000);
+        // First EBR: logical at +2048, link to the next EBR at container + 40960
+        mbr_entry(&mut image, 30720, 0, 0x82, 2048, 8192);
+        mbr_entry(&mut image, 30720, 1, 0x05, 40960, 30000);
+        // Second EBR: last logical, no link
+        mbr_entry(&mut image, 71680, 0, 0x8e, 2048, 20000);
+
+        let table = parse(image).unwrap();
+        assert_eq!(table.kind, TableKind::Mbr);
+        assert_eq!(table.disk_signature, Some(0xdead_beef));
+        let summary: Vec<(u32, u64, u64, String, bool)> = table
+            .partitions
+            .iter()
+            .map(|p| {
+                let kind = p.partition_type.to_string();
+                (p.number, p.first_lba, p.last_lba, kind, p.logical)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, 2048, 22527, "0x83".to_string(), false),
+                (2, 30720, 130719, "0x05".to_string(), false),
+                (5, 32768, 40959, "0x82".to_string(), true),
+                (6, 73728, 93727, "0x8e".to_string(), true),
+            ]
+        );
+        assert!(table.partitions[0].bootable);
+        assert_eq!(table.partitions[3].partition_type.name(), Some("Linux LVM"));
+        assert_eq!(table.ebr_lbas, vec![30720, 71680]);
+
+        let free: Vec<(u64, u64)> = table
+            .free
+            .iter()
+            .map(|gap| (gap.first_lba, gap.last_lba))
+            .collect();
+        assert_eq!(
+            free,
+            vec![(1, 2047), (22528, 32767), (40960, 73727), (93728, 131071)]
+        );
+    }
+
+    #[test]
+    fn test_no_partition_table() {
+        assert!(matches!(
+            parse(vec![0; MIB as usize]),
+            Err(PartitionTableError::NoPartitionTable)
+        ));
+        assert!(matches!(
+            parse_partition_table(&mut Cursor::new(vec![0; 4096]), 1000),
+            Err(PartitionTableError::InvalidSectorSize(1000))
+        ));
+
+        // A looping EBR chain ends instead of hanging
+        let mut image = vec![0; (4 * MIB) as usize];
+        mbr_entry(&mut image, 0, 0, 0x0f, 2048, 4096);
+        mbr_entry(&mut image, 2048, 0, 0x83, 63, 100);
+        mbr_entry(&mut image, 2048, 1, 0x05, 0, 4096);
+        let table = parse(image).unwrap();
+        assert_eq!(table.partitions.len(), 2);
+    }
+}
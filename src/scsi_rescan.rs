@@ -0,0 +1,211 @@
+//! SCSI rescan module for auto_mount
+//!
+//! This module asks every SCSI host to rescan its bus, so disks some HBAs don't
+//! announce on insertion show up in `/sys/block`, and waits for them to settle
+
+use crate::device_monitor::DEFAULT_SETTLE_TIME;
+use crate::SystemRoot;
+use std::collections::BTreeSet;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Wildcard channel, target and LUN: scan everything behind a host
+const SCAN_ALL: &str = "- - -";
+
+/// How often `/sys/block` is listed while waiting for disks to settle
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Errors that can occur during a SCSI host rescan
+#[derive(Debug, thiserror::Error)]
+pub enum ScsiRescanError {
+    #[error("No SCSI hosts found")]
+    NoHosts,
+    #[error("Rescan failed on every SCSI host: {0}")]
+    AllHostsFailed(String),
+    #[error("IO error: {0}")]
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for ScsiRescanError {
+    fn from(error: std::io::Error) -> Self {
+        ScsiRescanError::IoError(error)
+    }
+}
+
+/// Configuration for a SCSI host rescan
+#[derive(Debug, Clone)]
+pub struct RescanConfig {
+    /// How long `/sys/block` must stay unchanged before disks count as settled
+    pub settle_time: Duration,
+    /// Give up waiting for disks to settle after this long
+    pub timeout: Duration,
+    /// Where sysfs is read from
+    pub root: SystemRoot,
+}
+
+impl Default for RescanConfig {
+    fn default() -> Self {
+        Self {
+            settle_time: DEFAULT_SETTLE_TIME,
+            timeout: Duration::from_secs(10),
+            root: SystemRoot::default(),
+        }
+    }
+}
+
+/// Result of a SCSI host rescan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RescanResult {
+    /// Hosts that accepted the rescan (`host0`, ...)
+    pub hosts: Vec<String>,
+    /// Hosts whose `scan` attribute couldn't be written, with the error
+    pub failed_hosts: Vec<(String, String)>,
+    /// Kernel names of the block devices that appeared during the rescan
+    pub new_devices: Vec<String>,
+    /// Whether `/sys/block` settled before the timeout
+    pub settled: bool,
+}
+
+/// Rescan every SCSI host and wait for new block devices to settle
+pub fn rescan_scsi_hosts() -> Result<RescanResult, ScsiRescanError> {
+    rescan_scsi_hosts_with_config(&RescanConfig::default())
+}
+
+/// Rescan every SCSI host below the configured root
+pub fn rescan_scsi_hosts_with_config(
+    config: &RescanConfig,
+) -> Result<RescanResult, ScsiRescanError> {
+    let hosts = scsi_hosts(&config.root)?;
+    if hosts.is_empty() {
+        return Err(ScsiRescanError::NoHosts);
+    }
+
+    let before = block_devices(&config.root);
+    let mut result = RescanResult {
+        hosts: Vec::new(),
+        failed_hosts: Vec::new(),
+        new_devices: Vec::new(),
+        settled: false,
+    };
+
+    for host in hosts {
+        let scan = config.root.sys_class_scsi_host().join(&host).join("scan");
+        match fs::write(&scan, SCAN_ALL) {
+            Ok(()) => result.hosts.push(host),
+            Err(error) => result.failed_hosts.push((host, error.to_string())),
+        }
+    }
+    if result.hosts.is_empty() {
+        let failures: Vec<String> = result
+            .failed_hosts
+            .iter()
+            .map(|(host, error)| format!("{}: {}", host, error))
+            .collect();
+        return Err(ScsiRescanError::AllHostsFailed(failures.join(", ")));
+    }
+
+    let (after, settled) = wait_for_settle(config);
+    result.new_devices = after.difference(&before).cloned().collect();
+    result.settled = settled;
+    Ok(result)
+}
+
+/// Poll `/sys/block` until it stays unchanged for the settle time or the timeout passes
+fn wait_for_settle(config: &RescanConfig) -> (BTreeSet<String>, bool) {
+    let start = Instant::now();
+    let mut devices = block_devices(&config.root);
+    let mut last_change = start;
+
+    loop {
+        let now = Instant::now();
+        if now.duration_since(last_change) >= config.settle_time {
+            return (devices, true);
+        }
+        if now.duration_since(start) >= config.timeout {
+            return (devices, false);
+        }
+
+        thread::sleep(POLL_INTERVAL.min(config.settle_time));
+        let current = block_devices(&config.root);
+        if current != devices {
+            devices = current;
+            last_change = Instant::now();
+        }
+    }
+}
+
+/// Names of the SCSI hosts (`host0`, `host1`, ...)
+fn scsi_hosts(root: &SystemRoot) -> Result<Vec<String>, ScsiRescanError> {
+    let mut hosts: Vec<String> = match fs::read_dir(root.sys_class_scsi_host()) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.starts_with("host"))
+            .collect(),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(error) => return Err(error.into()),
+    };
+    hosts.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
+    Ok(hosts)
+}
+
+fn block_devices(root: &SystemRoot) -> BTreeSet<String> {
+    fs::read_dir(root.sys_block())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
+
+    fn quick_config(root: &SystemRoot) -> RescanConfig {
+        RescanConfig {
+            settle_time: Duration::from_millis(300),
+            timeout: Duration::from_secs(5),
+            root: root.clone(),
+        }
+    }
+
+    #[test]
+    fn test_rescan_writes_every_host_and_reports_new_disks() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda"))
+            .file("sys/class/scsi_host/host0/scan", "")
+            .file("sys/class/scsi_host/host10/scan", "")
+            .file("sys/class/scsi_host/host2/scan", "")
+            .build()
+            .unwrap();
+        let root = fixture.root().clone();
+
+        // The disk behind host2 shows up a moment after the scan
+        let sys_block = root.sys_block();
+        let inserter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(150));
+            fs::create_dir_all(sys_block.join("sdb")).unwrap();
+        });
+        let result = rescan_scsi_hosts_with_config(&quick_config(&root)).unwrap();
+        inserter.join().unwrap();
+
+        assert_eq!(result.hosts, vec!["host0", "host2", "host10"]);
+        assert!(result.failed_hosts.is_empty());
+        assert_eq!(result.new_devices, vec!["sdb"]);
+        assert!(result.settled);
+        let scan = root.sys_class_scsi_host().join("host2/scan");
+        assert_eq!(fs::read_to_string(scan).unwrap(), "- - -");
+    }
+
+    #[test]
+    fn test_rescan_without_hosts() {
+        let fixture = FixtureBuilder::new().build().unwrap();
+        assert!(matches!(
+            rescan_scsi_hosts_with_config(&quick_config(fixture.root())),
+            Err(ScsiRescanError::NoHosts)
+        ));
+    }
+}
@@ -0,0 +1,467 @@
+//! Signature probing module for auto_mount
+//!
+//! This module looks for partition tables, filesystems and volume manager
+//! metadata in the first and last MiB of a disk, without calling blkid
+
+use crate::{BlockDevice, SystemRoot};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use strum::Display;
+
+/// How much of each end of the disk is read
+const PROBE_WINDOW: u64 = 1 << 20;
+
+/// Errors that can occur while probing signatures
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+    #[error("IO error: {0}")]
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for SignatureError {
+    fn from(error: std::io::Error) -> Self {
+        SignatureError::IoError(error)
+    }
+}
+
+/// On-disk signature, displayed with the name blkid reports for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum Signature {
+    /// MBR partition table with the number of used entries
+    #[strum(serialize = "dos")]
+    Mbr { partitions: u32 },
+    /// GPT partition table with the number of used entries
+    #[strum(serialize = "gpt")]
+    Gpt { partitions: u32 },
+    #[strum(serialize = "ext2")]
+    Ext2,
+    #[strum(serialize = "ext3")]
+    Ext3,
+    #[strum(serialize = "ext4")]
+    Ext4,
+    #[strum(serialize = "xfs")]
+    Xfs,
+    #[strum(serialize = "btrfs")]
+    Btrfs,
+    #[strum(serialize = "ntfs")]
+    Ntfs,
+    #[strum(serialize = "vfat")]
+    Fat,
+    #[strum(serialize = "swap")]
+    Swap,
+    #[strum(serialize = "LVM2_member")]
+    Lvm2,
+    #[strum(serialize = "linux_raid_member")]
+    MdRaid,
+    #[strum(serialize = "crypto_LUKS")]
+    Luks,
+    #[strum(serialize = "zfs_member")]
+    Zfs,
+}
+
+impl Signature {
+    /// Whether this is a partition table without any partitions in it
+    pub fn is_empty_partition_table(&self) -> bool {
+        matches!(
+            self,
+            Signature::Mbr { partitions: 0 } | Signature::Gpt { partitions: 0 }
+        )
+    }
+}
+
+/// Whether probed signatures describe a blank disk: nothing at all, or only
+/// partition tables without partitions
+pub fn is_blank(signatures: &[Signature]) -> bool {
+    signatures
+        .iter()
+        .all(|signature| signature.is_empty_partition_table())
+}
+
+/// Probe the signatures on a block device
+pub fn probe_device_signatures(device: &BlockDevice) -> Result<Vec<Signature>, SignatureError> {
+    probe_device_signatures_in(&SystemRoot::default(), device)
+}
+
+/// Probe the signatures on a block device, opening its node below the given system root
+pub fn probe_device_signatures_in(
+    root: &SystemRoot,
+    device: &BlockDevice,
+) -> Result<Vec<Signature>, SignatureError> {
+    probe_signatures(root.dev_path(&device.path))
+}
+
+/// Probe the signatures in a device node or disk image
+pub fn probe_signatures<P: AsRef<Path>>(path: P) -> Result<Vec<Signature>, SignatureError> {
+    let image = DiskImage::read(path.as_ref())?;
+    Ok(image.signatures())
+}
+
+/// The first and last MiB of a disk
+struct DiskImage {
+    size: u64,
+    head: Vec<u8>,
+    tail: Vec<u8>,
+    tail_offset: u64,
+}
+
+impl DiskImage {
+    fn read(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        // Block device nodes report a zero length in their metadata, seeking works for both
+        let size = file.seek(SeekFrom::End(0))?;
+
+        let mut head = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        (&mut file).take(PROBE_WINDOW).read_to_end(&mut head)?;
+
+        let tail_offset = size.saturating_sub(PROBE_WINDOW);
+        let mut tail = Vec::new();
+        file.seek(SeekFrom::Start(tail_offset))?;
+        file.take(PROBE_WINDOW).read_to_end(&mut tail)?;
+
+        Ok(DiskImage {
+            size,
+            head,
+            tail,
+            tail_offset,
+        })
+    }
+
+    /// Bytes at an absolute offset, if they fall inside one of the probed windows
+    fn at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        let end = offset.checked_add(len as u64)?;
+        if end <= self.head.len() as u64 {
+            Some(&self.head[offset as usize..end as usize])
+        } else if offset >= self.tail_offset && end <= self.tail_offset + self.tail.len() as u64 {
+            let start = (offset - self.tail_offset) as usize;
+            Some(&self.tail[start..start + len])
+        } else {
+            None
+        }
+    }
+
+    fn matches(&self, offset: u64, magic: &[u8]) -> bool {
+        self.at(offset, magic.len()) == Some(magic)
+    }
+
+    fn u16_le(&self, offset: u64) -> Option<u16> {
+        let bytes = self.at(offset, 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32_le(&self, offset: u64) -> Option<u32> {
+        let bytes = self.at(offset, 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64_le(&self, offset: u64) -> Option<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.at(offset, 8)?);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    fn signatures(&self) -> Vec<Signature> {
+        let mut signatures = Vec::new();
+        let probes: [fn(&DiskImage) -> Option<Signature>; 12] = [
+            DiskImage::probe_partition_table,
+            DiskImage::probe_ext,
+            DiskImage::probe_xfs,
+            DiskImage::probe_btrfs,
+            DiskImage::probe_ntfs,
+            DiskImage::probe_fat,
+            DiskImage::probe_swap,
+            DiskImage::probe_lvm2,
+            DiskImage::probe_md,
+            DiskImage::probe_luks,
+            DiskImage::probe_zfs,
+            DiskImage::probe_backup_gpt,
+        ];
+        for probe in probes {
+            if let Some(signature) = probe(self) {
+                if !signatures.contains(&signature) {
+                    signatures.push(signature);
+                }
+            }
+        }
+        signatures
+    }
+
+    /// GPT (primary header at LBA 1 for 512 or 4096-byte sectors) or a plain MBR
+    fn probe_partition_table(&self) -> Option<Signature> {
+        for sector_size in [512, 4096] {
+            if self.matches(sector_size, b"EFI PART") {
+                return Some(Signature::Gpt {
+                    partitions: self.gpt_partitions(sector_size, sector_size),
+                });
+            }
+        }
+
+        // FAT and NTFS boot sectors carry the same 0x55AA marker
+        if !self.matches(510, &[0x55, 0xaa]) || self.is_boot_sector() {
+            return None;
+        }
+        let partitions = (0..4)
+            .filter(|index| self.at(446 + index * 16 + 4, 1) != Some(&[0]))
+            .count() as u32;
+        Some(Signature::Mbr { partitions })
+    }
+
+    /// A disk whose primary GPT header was wiped still carries the backup in its last sector
+    fn probe_backup_gpt(&self) -> Option<Signature> {
+        if self.probe_partition_table().is_some() {
+            return None;
+        }
+        [512, 4096]
+            .iter()
+            .find(|&&sector_size| {
+                self.size >= sector_size && self.matches(self.size - sector_size, b"EFI PART")
+            })
+            .map(|&sector_size| Signature::Gpt {
+                partitions: self.gpt_partitions(self.size - sector_size, sector_size),
+            })
+    }
+
+    fn gpt_partitions(&self, header: u64, sector_size: u64) -> u32 {
+        let entries_lba = self.u64_le(header + 72).unwrap_or(2);
+        let entry_count = self.u32_le(header + 80).unwrap_or(0).min(1024);
+        let entry_size = u64::from(self.u32_le(header + 84).unwrap_or(128).max(128));
+
+        (0..u64::from(entry_count))
+            .filter_map(|index| {
+                self.at(
+                    entries_lba.saturating_mul(sector_size) + index * entry_size,
+                    16,
+                )
+            })
+            .filter(|type_guid| type_guid.iter().any(|&byte| byte != 0))
+            .count() as u32
+    }
+
+    fn is_boot_sector(&self) -> bool {
+        self.probe_ntfs().is_some() || self.probe_fat().is_some()
+    }
+
+    fn probe_ext(&self) -> Option<Signature> {
+        const SUPERBLOCK: u64 = 1024;
+        const HAS_JOURNAL: u32 = 0x4;
+        const EXT4_INCOMPAT: u32 = 0x40 | 0x80 | 0x200; // extents, 64bit, flex_bg
+
+        if self.u16_le(SUPERBLOCK + 56)? != 0xef53 {
+            return None;
+        }
+        let compat = self.u32_le(SUPERBLOCK + 92)?;
+        let incompat = self.u32_le(SUPERBLOCK + 96)?;
+        Some(if incompat & EXT4_INCOMPAT != 0 {
+            Signature::Ext4
+        } else if compat & HAS_JOURNAL != 0 {
+            Signature::Ext3
+        } else {
+            Signature::Ext2
+        })
+    }
+
+    fn probe_xfs(&self) -> Option<Signature> {
+        self.matches(0, b"XFSB").then_some(Signature::Xfs)
+    }
+
+    fn probe_btrfs(&self) -> Option<Signature> {
+        self.matches(65536 + 64, b"_BHRfS_M")
+            .then_some(Signature::Btrfs)
+    }
+
+    fn probe_ntfs(&self) -> Option<Signature> {
+        self.matches(3, b"NTFS    ").then_some(Signature::Ntfs)
+    }
+
+    fn probe_fat(&self) -> Option<Signature> {
+        let fat = self.matches(54, b"FAT12   ")
+            || self.matches(54, b"FAT16   ")
+            || self.matches(82, b"FAT32   ");
+        fat.then_some(Signature::Fat)
+    }
+
+    fn probe_swap(&self) -> Option<Signature> {
+        [4096, 8192, 16384, 65536]
+            .iter()
+            .any(|page_size| {
+                self.matches(page_size - 10, b"SWAPSPACE2")
+                    || self.matches(page_size - 10, b"SWAP-SPACE")
+            })
+            .then_some(Signature::Swap)
+    }
+
+    /// The LVM2 label may sit in any of the first four sectors
+    fn probe_lvm2(&self) -> Option<Signature> {
+        (0..4)
+            .map(|sector| sector * 512)
+            .any(|offset| {
+                self.matches(offset, b"LABELONE") && self.matches(offset + 24, b"LVM2 001")
+            })
+            .then_some(Signature::Lvm2)
+    }
+
+    /// md superblocks: 1.1 at the start, 1.2 at 4 KiB, 1.0 and 0.90 near the end
+    fn probe_md(&self) -> Option<Signature> {
+        const MD_MAGIC: u32 = 0xa92b4efc;
+
+        let mut offsets = vec![0, 4096];
+        if self.size >= 8192 {
+            offsets.push((self.size - 8192) & !4095);
+        }
+        if self.size >= 65536 {
+            offsets.push((self.size & !65535) - 65536);
+        }
+        offsets
+            .into_iter()
+            .any(|offset| self.u32_le(offset) == Some(MD_MAGIC))
+            .then_some(Signature::MdRaid)
+    }
+
+    fn probe_luks(&self) -> Option<Signature> {
+        self.matches(0, b"LUKS\xba\xbe").then_some(Signature::Luks)
+    }
+
+    /// ZFS keeps four vdev labels, two at each end, each with an uberblock ring at 128 KiB
+    fn probe_zfs(&self) -> Option<Signature> {
+        const LABEL_SIZE: u64 = 256 << 10;
+        const UBERBLOCK_MAGIC: u64 = 0x00bab10c;
+
+        let mut labels = vec![0, LABEL_SIZE];
+        if self.size >= 2 * LABEL_SIZE {
+            labels.push(self.size - 2 * LABEL_SIZE);
+            labels.push(self.size - LABEL_SIZE);
+        }
+        labels
+            .into_iter()
+            .flat_map(|label| (0..128).map(move |slot| label + (128 << 10) + slot * 1024))
+            .any(|offset| {
+                self.u64_le(offset).map_or(false, |magic| {
+                    magic == UBERBLOCK_MAGIC || magic.swap_bytes() == UBERBLOCK_MAGIC
+                })
+            })
+            .then_some(Signature::Zfs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
+
+    const DISK_SIZE: usize = 4 << 20;
+
+    fn probe(image: &[u8]) -> Vec<Signature> {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb"))
+            .file("dev/sdb", image)
+            .build()
+            .unwrap();
+        let device = BlockDevice::from_name_in(fixture.root(), "sdb").unwrap();
+        probe_device_signatures_in(fixture.root(), &device).unwrap()
+    }
+
+    fn image_with(offset: usize, bytes: &[u8]) -> Vec<u8> {
+        let mut image = vec![0; DISK_SIZE];
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+        image
+    }
+
+    #[test]
+    fn test_blank_disk() {
+        assert!(probe(&vec![0; DISK_SIZE]).is_empty());
+        assert!(probe(&[]).is_empty());
+        assert!(is_blank(&[]));
+        assert!(is_blank(&[Signature::Gpt { partitions: 0 }]));
+        assert!(!is_blank(&[Signature::Mbr { partitions: 1 }]));
+        assert!(!is_blank(&[
+            Signature::Mbr { partitions: 0 },
+            Signature::Lvm2
+        ]));
+    }
+
+    #[test]
+    fn test_partition_tables() {
+        let mut mbr = image_with(510, &[0x55, 0xaa]);
+        assert_eq!(probe(&mbr), vec![Signature::Mbr { partitions: 0 }]);
+        mbr[446 + 16 + 4] = 0x83;
+        assert_eq!(probe(&mbr), vec![Signature::Mbr { partitions: 1 }]);
+
+        let mut gpt = image_with(510, &[0x55, 0xaa]);
+        gpt[446 + 4] = 0xee;
+        gpt[512..520].copy_from_slice(b"EFI PART");
+        gpt[512 + 72..512 + 80].copy_from_slice(&2u64.to_le_bytes());
+        gpt[512 + 80..512 + 84].copy_from_slice(&128u32.to_le_bytes());
+        gpt[512 + 84..512 + 88].copy_from_slice(&128u32.to_le_bytes());
+        assert_eq!(probe(&gpt), vec![Signature::Gpt { partitions: 0 }]);
+        gpt[1024 + 128] = 0xaf;
+        assert_eq!(probe(&gpt), vec![Signature::Gpt { partitions: 1 }]);
+
+        let backup = image_with(DISK_SIZE - 512, b"EFI PART");
+        assert_eq!(probe(&backup), vec![Signature::Gpt { partitions: 0 }]);
+    }
+
+    #[test]
+    fn test_filesystems() {
+        let mut ext = image_with(1024 + 56, &[0x53, 0xef]);
+        assert_eq!(probe(&ext), vec![Signature::Ext2]);
+        ext[1024 + 92] = 0x4;
+        assert_eq!(probe(&ext), vec![Signature::Ext3]);
+        ext[1024 + 96] = 0x40;
+        assert_eq!(probe(&ext), vec![Signature::Ext4]);
+
+        assert_eq!(probe(&image_with(0, b"XFSB")), vec![Signature::Xfs]);
+        assert_eq!(
+            probe(&image_with(65536 + 64, b"_BHRfS_M")),
+            vec![Signature::Btrfs]
+        );
+        assert_eq!(
+            probe(&image_with(4086, b"SWAPSPACE2")),
+            vec![Signature::Swap]
+        );
+
+        // Boot sectors are not reported as MBR partition tables
+        let mut ntfs = image_with(3, b"NTFS    ");
+        ntfs[510..512].copy_from_slice(&[0x55, 0xaa]);
+        assert_eq!(probe(&ntfs), vec![Signature::Ntfs]);
+        let mut fat = image_with(82, b"FAT32   ");
+        fat[510..512].copy_from_slice(&[0x55, 0xaa]);
+        assert_eq!(probe(&fat), vec![Signature::Fat]);
+    }
+
+    #[test]
+    fn test_volume_metadata() {
+        let mut lvm = image_with(512, b"LABELONE");
+        lvm[512 + 24..512 + 32].copy_from_slice(b"LVM2 001");
+        assert_eq!(probe(&lvm), vec![Signature::Lvm2]);
+
+        let md_magic = 0xa92b4efcu32.to_le_bytes();
+        assert_eq!(probe(&image_with(4096, &md_magic)), vec![Signature::MdRaid]);
+        // 0.90 superblock near the end of the disk
+        assert_eq!(
+            probe(&image_with(DISK_SIZE - 65536, &md_magic)),
+            vec![Signature::MdRaid]
+        );
+
+        assert_eq!(
+            probe(&image_with(0, b"LUKS\xba\xbe")),
+            vec![Signature::Luks]
+        );
+
+        let uberblock = 0x00bab10cu64.to_le_bytes();
+        assert_eq!(
+            probe(&image_with(
+                DISK_SIZE - (256 << 10) + (128 << 10),
+                &uberblock
+            )),
+            vec![Signature::Zfs]
+        );
+    }
+
+    #[test]
+    fn test_signature_names() {
+        assert_eq!(Signature::Mbr { partitions: 2 }.to_string(), "dos");
+        assert_eq!(Signature::Lvm2.to_string(), "LVM2_member");
+        assert_eq!(Signature::Zfs.to_string(), "zfs_member");
+    }
+}
@@ -3,15 +3,23 @@
 //! This module provides high-level functions that make intelligent decisions
 //! about partition table types, filesystem choices, etc.
 
+use crate::mount_manager::mount_devices_with_config;
+use crate::partition_manager::{ensure_safe, MBR_MAX_BYTES};
 use crate::{
-    change_devices_to_gpt, create_partition, filter_unmounted_hdd_devices, find_connected_satas,
-    format_devices, mount_devices, DeviceDiscoveryError, DeviceFilterError, FilesystemError,
-    MountError, PartitionError,
+    check_health, create_block_device_partitions_with_report, find_block_devices_in,
+    format_block_devices_with_config, rescan_scsi_hosts_with_config, BlockDevice,
+    DeviceDiscoveryError, DeviceFilter, DeviceFilterError, FilesystemError, FilesystemType,
+    FilterReport, FormatConfig, HealthThresholds, MountError, MountManagerConfig, MountResult,
+    PartitionConfig, PartitionError, PartitionLayout, PlacedPartition, RejectReason,
+    RejectedDevice, RescanConfig, ScsiRescanError, SystemRoot, TableKind, Transport,
 };
+use std::path::PathBuf;
 
 /// Errors that can occur during smart mounting
 #[derive(Debug, thiserror::Error)]
 pub enum SmartMountError {
+    #[error("SCSI host rescan failed: {0}")]
+    Rescan(#[from] ScsiRescanError),
     #[error("Device discovery failed: {0}")]
     DeviceDiscovery(#[from] DeviceDiscoveryError),
     #[error("Device filtering failed: {0}")]
@@ -26,15 +34,62 @@ pub enum SmartMountError {
     Mount(#[from] MountError),
 }
 
+/// What to do with zoned (SMR/ZNS) disks, which ext4 can't use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZonedPolicy {
+    /// Leave zoned disks out, with the reason in the report
+    #[default]
+    Refuse,
+    /// Format the whole disk, unpartitioned, as btrfs in zoned mode
+    Btrfs,
+    /// Format the whole disk, unpartitioned, as f2fs in zoned mode
+    F2fs,
+}
+
+impl ZonedPolicy {
+    fn filesystem(&self) -> Option<FilesystemType> {
+        match self {
+            ZonedPolicy::Refuse => None,
+            ZonedPolicy::Btrfs => Some(FilesystemType::Btrfs),
+            ZonedPolicy::F2fs => Some(FilesystemType::F2fs),
+        }
+    }
+}
+
 /// Configuration for smart mounting
 #[derive(Debug, Clone)]
 pub struct MountConfig {
     /// Force GPT even for small disks
     pub force_gpt: bool,
-    /// Minimum disk size (in GB) to automatically use GPT
+    /// Minimum disk size (in GB) to automatically use GPT, decided disk by disk
     pub gpt_threshold_gb: u64,
-    /// Skip GPT conversion entirely
+    /// Use MBR on every disk, even with `force_gpt` set; disks over 2 TiB are rejected instead
     pub skip_gpt: bool,
+    /// Which discovered disks to use; unused HDDs by default
+    pub filter: DeviceFilter,
+    /// Rescan every SCSI host before discovery, for HBAs that don't
+    /// announce hot-inserted disks on their own
+    pub rescan_scsi_hosts: bool,
+    /// Also use disks attached through USB bridges and docks
+    pub include_usb: bool,
+    /// Check SMART health against these thresholds before partitioning;
+    /// no health check when `None`
+    pub health: Option<HealthThresholds>,
+    /// How to handle host-aware and host-managed zoned disks
+    pub zoned: ZonedPolicy,
+    /// Partitions created on every regular disk, each formatted with its own filesystem
+    pub layout: PartitionLayout,
+    /// Partition alignment in bytes (1 MiB, 4 MiB, ...); derived from each disk's
+    /// physical sector and I/O sizes when `None`
+    pub alignment: Option<u64>,
+    /// Where the old partition table of every disk is saved before it is
+    /// replaced; `/var/backups/auto_mount` when `None`
+    pub backup_dir: Option<PathBuf>,
+    /// Disks (`/dev/sdb` or `sdb`) that may be repartitioned even though
+    /// existing signatures were found on them
+    pub allow_non_blank: Vec<String>,
+    /// Where sysfs, procfs, /dev and /etc are read from
+    pub root: SystemRoot,
 }
 
 impl Default for MountConfig {
@@ -43,10 +98,52 @@ impl Default for MountConfig {
             force_gpt: false,
             gpt_threshold_gb: 2000, // 2TB threshold
             skip_gpt: false,
+            filter: DeviceFilter::unmounted_hdd(),
+            rescan_scsi_hosts: false,
+            include_usb: false,
+            health: None,
+            zoned: ZonedPolicy::default(),
+            layout: PartitionLayout::default(),
+            alignment: None,
+            backup_dir: None,
+            allow_non_blank: Vec::new(),
+            root: SystemRoot::default(),
         }
     }
 }
 
+/// Partition table chosen for one disk, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableDecision {
+    pub kind: TableKind,
+    pub reason: String,
+}
+
+/// How one accepted disk is provisioned
+#[derive(Debug, Clone)]
+pub struct DevicePlan {
+    pub device: BlockDevice,
+    /// Filesystem of the whole disk, or of the layout's first formatted partition
+    pub filesystem: FilesystemType,
+    /// Format the disk itself instead of partitioning it
+    pub whole_disk: bool,
+    /// Partition table written to the disk; `None` for whole-disk filesystems
+    pub table: Option<TableDecision>,
+    /// Why the plan differs from the default partition + ext4 layout
+    pub reason: Option<String>,
+}
+
+/// What a smart auto-mount run did
+#[derive(Debug, Clone)]
+pub struct SmartMountReport {
+    /// Disks left out by the filter, the health check or the zoned policy, with the reason
+    pub rejected: Vec<RejectedDevice>,
+    pub plans: Vec<DevicePlan>,
+    /// Every partition created, with its start and end sector
+    pub partitions: Vec<PlacedPartition>,
+    pub mounts: Vec<MountResult>,
+}
+
 /// Smart auto-mount with intelligent decisions
 pub fn smart_auto_mount() -> Result<(), SmartMountError> {
     smart_auto_mount_with_config(MountConfig::default())
@@ -54,71 +151,285 @@ pub fn smart_auto_mount() -> Result<(), SmartMountError> {
 
 /// Smart auto-mount with custom configuration
 pub fn smart_auto_mount_with_config(config: MountConfig) -> Result<(), SmartMountError> {
-    // Find and filter devices
-    let devices = find_connected_satas()?;
-    if devices.is_empty() {
+    smart_auto_mount_with_report(config).map(|_| ())
+}
+
+/// Smart auto-mount with custom configuration, reporting rejected disks and mounts
+pub fn smart_auto_mount_with_report(
+    config: MountConfig,
+) -> Result<SmartMountReport, SmartMountError> {
+    if config.rescan_scsi_hosts {
+        let rescan_config = RescanConfig {
+            root: config.root.clone(),
+            ..Default::default()
+        };
+        rescan_scsi_hosts_with_config(&rescan_config)?;
+    }
+
+    let mut report = discover_devices(&config)?;
+    if let Some(thresholds) = &config.health {
+        report = check_health(report, thresholds);
+    }
+    let (plans, mut rejected) = plan_devices(report.accepted, &config);
+    rejected.splice(0..0, report.rejected);
+    if plans.is_empty() {
         return Err(SmartMountError::NoDevicesFound);
     }
 
-    let devices = filter_unmounted_hdd_devices(devices)?;
+    let partition_config = PartitionConfig {
+        allow_non_blank: config.allow_non_blank.clone(),
+        layout: config.layout.clone(),
+        alignment: config.alignment,
+        backup_dir: config.backup_dir.clone(),
+        root: config.root.clone(),
+        ..Default::default()
+    };
+    let whole_disks: Vec<&DevicePlan> = plans.iter().filter(|plan| plan.whole_disk).collect();
+    let mut placed = Vec::new();
+    let mut mounts = Vec::new();
+
+    // Refuse system, busy and non-blank disks, whether they get GPT, MBR or a
+    // whole-disk filesystem, before writing to any of them
+    let planned: Vec<BlockDevice> = plans.iter().map(|plan| plan.device.clone()).collect();
+    ensure_safe(&planned, &partition_config)?;
+
+    // Partition each disk with the table decided for it, then format and mount
+    // the partitions
+    for kind in [TableKind::Gpt, TableKind::Mbr] {
+        let devices: Vec<BlockDevice> = plans
+            .iter()
+            .filter(|plan| plan.table.as_ref().map(|table| table.kind) == Some(kind))
+            .map(|plan| plan.device.clone())
+            .collect();
+        if devices.is_empty() {
+            continue;
+        }
+        let table_config = PartitionConfig {
+            table: kind,
+            ..partition_config.clone()
+        };
+        let created = create_block_device_partitions_with_report(&devices, &table_config)?;
+        let partitions = created
+            .iter()
+            .map(|partition| BlockDevice::from_path_in(&config.root, &partition.path))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(PartitionError::from)?;
+        placed.extend(created);
+        mounts.extend(format_layout_partitions(&partitions, &config)?);
+    }
+
+    // Zoned disks take the filesystem directly, without a partition table
+    for filesystem in [FilesystemType::Btrfs, FilesystemType::F2fs] {
+        let disks: Vec<BlockDevice> = whole_disks
+            .iter()
+            .filter(|plan| plan.filesystem == filesystem)
+            .map(|plan| plan.device.clone())
+            .collect();
+        if disks.is_empty() {
+            continue;
+        }
+        format_block_devices_with_config(&disks, &format_config(&config, &filesystem))?;
+        mounts.extend(mount_filesystems(&disks, &filesystem, &config)?);
+    }
+
+    Ok(SmartMountReport {
+        rejected,
+        plans,
+        partitions: placed,
+        mounts,
+    })
+}
+
+/// Find every disk below the root and let the filter pick; USB disks are
+/// rejected unless `include_usb` is set
+fn discover_devices(config: &MountConfig) -> Result<FilterReport, SmartMountError> {
+    let (usb, devices): (Vec<BlockDevice>, Vec<BlockDevice>) = find_block_devices_in(&config.root)?
+        .into_iter()
+        .partition(|device| !config.include_usb && device.transport == Transport::Usb);
     if devices.is_empty() {
         return Err(SmartMountError::NoDevicesFound);
     }
 
-    // Decide whether to use GPT
-    if should_use_gpt(&devices, &config)? {
-        change_devices_to_gpt(&devices)?;
-    }
+    let mut report = config.filter.apply_in(&config.root, devices)?;
+    report
+        .rejected
+        .extend(usb.into_iter().map(|device| RejectedDevice {
+            device,
+            reason: RejectReason::Transport(Transport::Usb),
+        }));
+    Ok(report)
+}
 
-    // Create partitions, format, and mount
-    let devices = create_partition(&devices)?;
-    format_devices(&devices)?;
-    mount_devices(&devices)?;
+/// Format and mount the partitions of every disk, which come disk by disk in layout
+/// order; partitions without a filesystem in the layout are left alone
+fn format_layout_partitions(
+    partitions: &[BlockDevice],
+    config: &MountConfig,
+) -> Result<Vec<MountResult>, SmartMountError> {
+    let groups = layout_groups(partitions, &config.layout);
 
-    Ok(())
+    // Mounting records the partitions in fstab, which makes their disk a system
+    // disk, so every group is formatted before the first one is mounted
+    for (filesystem, group) in &groups {
+        format_block_devices_with_config(group, &format_config(config, filesystem))?;
+    }
+    let mut mounts = Vec::new();
+    for (filesystem, group) in &groups {
+        mounts.extend(mount_filesystems(group, filesystem, config)?);
+    }
+    Ok(mounts)
 }
 
-/// Determine if GPT should be used based on device sizes and configuration
-fn should_use_gpt(devices: &[String], config: &MountConfig) -> Result<bool, SmartMountError> {
-    if config.skip_gpt {
-        return Ok(false);
+/// Format configuration carrying the mount configuration's root and opt-ins
+fn format_config(config: &MountConfig, filesystem: &FilesystemType) -> FormatConfig {
+    FormatConfig {
+        filesystem: filesystem.clone(),
+        allow_non_blank: config.allow_non_blank.clone(),
+        root: config.root.clone(),
     }
+}
 
-    if config.force_gpt {
-        return Ok(true);
+/// Group the partitions of every disk by the filesystem the layout gives them,
+/// in order of first appearance in the layout
+fn layout_groups(
+    partitions: &[BlockDevice],
+    layout: &PartitionLayout,
+) -> Vec<(FilesystemType, Vec<BlockDevice>)> {
+    let specs = &layout.partitions;
+    let mut groups: Vec<(FilesystemType, Vec<BlockDevice>)> = Vec::new();
+    for (index, partition) in partitions.iter().enumerate() {
+        let filesystem = match &specs[index % specs.len()].filesystem {
+            Some(filesystem) => filesystem,
+            None => continue,
+        };
+        match groups
+            .iter_mut()
+            .find(|(group_fs, _)| group_fs == filesystem)
+        {
+            Some((_, group)) => group.push(partition.clone()),
+            None => groups.push((filesystem.clone(), vec![partition.clone()])),
+        }
     }
+    groups
+}
+
+/// Decide the layout of every accepted disk, rejecting zoned disks the policy can't
+/// place and disks that can't take the MBR they were asked to get
+fn plan_devices(
+    devices: Vec<BlockDevice>,
+    config: &MountConfig,
+) -> (Vec<DevicePlan>, Vec<RejectedDevice>) {
+    let mut plans = Vec::new();
+    let mut rejected = Vec::new();
+    let layout_filesystem = config
+        .layout
+        .partitions
+        .iter()
+        .find_map(|spec| spec.filesystem.clone())
+        .unwrap_or(FilesystemType::Ext4);
 
-    // Check device sizes to determine if GPT is needed
     for device in devices {
-        if let Ok(size_gb) = device_size_gb(device) {
-            if size_gb >= config.gpt_threshold_gb {
-                return Ok(true);
+        if !device.zoned.is_zoned() {
+            match table_decision(&device, config) {
+                Ok(table) => plans.push(DevicePlan {
+                    device,
+                    filesystem: layout_filesystem.clone(),
+                    whole_disk: false,
+                    table: Some(table),
+                    reason: None,
+                }),
+                Err(reason) => rejected.push(RejectedDevice { device, reason }),
+            }
+            continue;
+        }
+
+        match config.zoned.filesystem() {
+            Some(filesystem) => {
+                let reason = format!(
+                    "{} zoned disk: whole-disk {} in zoned mode",
+                    device.zoned, filesystem
+                );
+                plans.push(DevicePlan {
+                    device,
+                    filesystem,
+                    whole_disk: true,
+                    table: None,
+                    reason: Some(reason),
+                });
+            }
+            None => {
+                let reason = RejectReason::Zoned(device.zoned);
+                rejected.push(RejectedDevice { device, reason });
             }
         }
     }
 
-    Ok(false)
+    (plans, rejected)
 }
 
-/// Calculate device size in GB
-fn device_size_gb(device: &str) -> Result<u64, SmartMountError> {
-    use std::process::Command;
+/// Mount formatted devices, recording the filesystem in fstab
+fn mount_filesystems(
+    devices: &[BlockDevice],
+    filesystem: &FilesystemType,
+    config: &MountConfig,
+) -> Result<Vec<MountResult>, SmartMountError> {
+    let paths: Vec<String> = devices.iter().map(|device| device.path.clone()).collect();
+    let mount_config = MountManagerConfig {
+        filesystem_type: filesystem.to_string(),
+        root: config.root.clone(),
+        ..Default::default()
+    };
+    Ok(mount_devices_with_config(&paths, mount_config)?)
+}
 
-    let output = Command::new("sudo")
-        .args(["blockdev", "--getsize64", device])
-        .output()
-        .map_err(|e| SmartMountError::Partition(PartitionError::IoError(e)))?;
+/// Choose the partition table for one disk; the reason to reject the disk when MBR
+/// is required but the disk is too large or the layout has too many partitions
+fn table_decision(
+    device: &BlockDevice,
+    config: &MountConfig,
+) -> Result<TableDecision, RejectReason> {
+    let decision = |kind, reason: String| Ok(TableDecision { kind, reason });
+    let partitions = config.layout.partitions.len();
 
-    if !output.status.success() {
-        return Ok(0); // Default to 0 if we can't determine size
+    if config.skip_gpt {
+        if device.size_bytes > MBR_MAX_BYTES {
+            return Err(RejectReason::TooLargeForMbr(device.size_bytes));
+        }
+        if partitions > 4 {
+            return Err(RejectReason::TooManyPartitionsForMbr(partitions));
+        }
+        return decision(TableKind::Mbr, "GPT skipped".to_string());
     }
-
-    let size_bytes = String::from_utf8_lossy(&output.stdout)
-        .trim()
-        .parse::<u64>()
-        .unwrap_or(0);
-
-    Ok(size_bytes / (1024 * 1024 * 1024)) // Convert to GB
+    if config.force_gpt {
+        return decision(TableKind::Gpt, "GPT forced".to_string());
+    }
+    if partitions > 4 {
+        return decision(
+            TableKind::Gpt,
+            format!("{} partitions don't fit in an MBR", partitions),
+        );
+    }
+    if device.size_bytes > MBR_MAX_BYTES {
+        return decision(TableKind::Gpt, "over 2 TiB".to_string());
+    }
+    if device.size_gb() >= config.gpt_threshold_gb {
+        return decision(
+            TableKind::Gpt,
+            format!(
+                "{} GB reaches the {} GB threshold",
+                device.size_gb(),
+                config.gpt_threshold_gb
+            ),
+        );
+    }
+    decision(
+        TableKind::Mbr,
+        format!(
+            "{} GB is below the {} GB threshold",
+            device.size_gb(),
+            config.gpt_threshold_gb
+        ),
+    )
 }
 
 /// Simple auto-mount without GPT conversion (for compatibility)
@@ -142,6 +453,8 @@ pub fn gpt_auto_mount() -> Result<(), SmartMountError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
+    use crate::{PartitionSize, PartitionSpec, ZonedModel};
 
     #[test]
     fn test_mount_config_default() {
@@ -149,25 +462,235 @@ mod tests {
         assert!(!config.force_gpt);
         assert_eq!(config.gpt_threshold_gb, 2000);
         assert!(!config.skip_gpt);
+        assert!(!config.rescan_scsi_hosts);
+        assert!(!config.include_usb);
+        assert!(config.health.is_none());
+        assert_eq!(config.zoned, ZonedPolicy::Refuse);
+        assert_eq!(config.layout, PartitionLayout::default());
+        assert_eq!(config.alignment, None);
+        assert_eq!(config.backup_dir, None);
+        assert!(config.allow_non_blank.is_empty());
+    }
+
+    #[test]
+    fn test_zoned_disks_follow_policy() {
+        let mut smr = BlockDevice::fake("sdc", 20 << 40);
+        smr.zoned = ZonedModel::HostManaged;
+        let devices = vec![BlockDevice::fake("sdb", 4 << 40), smr];
+
+        let mut config = MountConfig::default();
+        let (plans, rejected) = plan_devices(devices.clone(), &config);
+        assert_eq!(plans.len(), 1);
+        assert!(!plans[0].whole_disk);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].device.name, "sdc");
+        assert_eq!(
+            rejected[0].reason.to_string(),
+            "host-managed zoned disk refused"
+        );
+
+        config.zoned = ZonedPolicy::F2fs;
+        let (plans, rejected) = plan_devices(devices, &config);
+        assert!(rejected.is_empty());
+        assert_eq!(plans[1].filesystem, FilesystemType::F2fs);
+        assert!(plans[1].whole_disk);
+        assert_eq!(
+            plans[1].reason.as_deref(),
+            Some("host-managed zoned disk: whole-disk f2fs in zoned mode")
+        );
+    }
+
+    #[test]
+    fn test_layout_groups_by_filesystem() {
+        let layout = PartitionLayout::new()
+            .partition(PartitionSpec::new(PartitionSize::Bytes(1 << 30)))
+            .partition(PartitionSpec::swap(PartitionSize::Bytes(1 << 30)))
+            .partition(
+                PartitionSpec::new(PartitionSize::Remaining).filesystem(Some(FilesystemType::Xfs)),
+            );
+        let partitions: Vec<BlockDevice> = ["sdb1", "sdb2", "sdb3", "sdc1", "sdc2", "sdc3"]
+            .iter()
+            .map(|name| BlockDevice::fake(name, 1 << 30))
+            .collect();
+
+        let groups: Vec<(FilesystemType, Vec<String>)> = layout_groups(&partitions, &layout)
+            .into_iter()
+            .map(|(filesystem, group)| {
+                (
+                    filesystem,
+                    group.into_iter().map(|device| device.name).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (
+                    FilesystemType::Ext4,
+                    vec!["sdb1".to_string(), "sdc1".to_string()]
+                ),
+                (
+                    FilesystemType::Xfs,
+                    vec!["sdb3".to_string(), "sdc3".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_discovers_every_transport() {
+        let fixture =
+            FixtureBuilder::new()
+                .disk(FakeDisk::new("sdb"))
+                .disk(FakeDisk::new("sdc").device_path(
+                    "pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0",
+                ))
+                .disk(FakeDisk::new("nvme0n1").rotational(false))
+                .disk(FakeDisk::new("vdb"))
+                .build()
+                .unwrap();
+        let mut config = MountConfig {
+            root: fixture.root().clone(),
+            ..Default::default()
+        };
+        let names = |devices: Vec<BlockDevice>| -> Vec<String> {
+            devices.into_iter().map(|device| device.name).collect()
+        };
+
+        let report = discover_devices(&config).unwrap();
+        assert_eq!(names(report.accepted), vec!["sdb", "vdb"]);
+        let rejected: Vec<String> = report
+            .rejected
+            .iter()
+            .map(|rejected| format!("{}: {}", rejected.device.name, rejected.reason))
+            .collect();
+        assert_eq!(
+            rejected,
+            vec![
+                "nvme0n1: not a rotational disk".to_string(),
+                "sdc: usb transport not accepted".to_string(),
+            ]
+        );
+
+        config.include_usb = true;
+        config.filter = DeviceFilter::new();
+        let report = discover_devices(&config).unwrap();
+        assert_eq!(names(report.accepted), vec!["sdb", "sdc", "vdb", "nvme0n1"]);
     }
 
     #[test]
-    fn test_should_use_gpt_force() {
+    fn test_checks_every_disk_before_writing() {
+        let mut lvm = vec![0; 1 << 20];
+        lvm[512..520].copy_from_slice(b"LABELONE");
+        lvm[536..544].copy_from_slice(b"LVM2 001");
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb").size_bytes(3 << 40))
+            .disk(FakeDisk::new("sdc"))
+            .file("dev/sdb", vec![0; 8 << 20])
+            .file("dev/sdc", lvm)
+            .build()
+            .unwrap();
         let config = MountConfig {
-            force_gpt: true,
+            root: fixture.root().clone(),
             ..Default::default()
         };
-        let devices = vec!["/dev/sda".to_string()];
-        assert!(should_use_gpt(&devices, &config).unwrap());
+
+        // sdb would get GPT and sdc MBR; sdc's refusal leaves sdb untouched
+        match smart_auto_mount_with_report(config) {
+            Err(SmartMountError::Partition(PartitionError::NotBlank { device, .. })) => {
+                assert_eq!(device, "/dev/sdc");
+            }
+            other => panic!("expected NotBlank, got {:?}", other.map(|_| ())),
+        }
+        let image = std::fs::read(fixture.path().join("dev/sdb")).unwrap();
+        assert!(image.iter().all(|&byte| byte == 0));
     }
 
     #[test]
-    fn test_should_use_gpt_skip() {
+    fn test_table_decided_per_disk() {
+        let config = MountConfig::default();
+        let small = BlockDevice::fake("sda", 500 * 1024 * 1024 * 1024);
+        let large = BlockDevice::fake("sdb", 8 << 40);
+        let (plans, rejected) = plan_devices(vec![small, large], &config);
+        assert!(rejected.is_empty());
+
+        let tables: Vec<TableDecision> = plans.into_iter().filter_map(|plan| plan.table).collect();
+        assert_eq!(
+            tables,
+            vec![
+                TableDecision {
+                    kind: TableKind::Mbr,
+                    reason: "500 GB is below the 2000 GB threshold".to_string(),
+                },
+                TableDecision {
+                    kind: TableKind::Gpt,
+                    reason: "over 2 TiB".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_table_decision_overrides() {
+        let small = BlockDevice::fake("sda", 1 << 30);
+        let large = BlockDevice::fake("sdb", 3 << 40);
+
+        let config = MountConfig {
+            force_gpt: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            table_decision(&small, &config).unwrap().kind,
+            TableKind::Gpt
+        );
+
+        let config = MountConfig {
+            layout: PartitionLayout::equal(5),
+            ..Default::default()
+        };
+        assert_eq!(
+            table_decision(&small, &config).unwrap().kind,
+            TableKind::Gpt
+        );
+
+        let config = MountConfig {
+            skip_gpt: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            table_decision(&small, &config).unwrap().kind,
+            TableKind::Mbr
+        );
+        let (plans, rejected) = plan_devices(vec![small.clone(), large], &config);
+        assert_eq!(plans.len(), 1);
+        assert_eq!(rejected[0].device.name, "sdb");
+        assert_eq!(rejected[0].reason, RejectReason::TooLargeForMbr(3 << 40));
+
+        // skip_gpt wins when both are set, as it always has
+        let config = MountConfig {
+            force_gpt: true,
+            skip_gpt: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            table_decision(&small, &config),
+            Ok(TableDecision {
+                kind: TableKind::Mbr,
+                reason: "GPT skipped".to_string(),
+            })
+        );
+
         let config = MountConfig {
             skip_gpt: true,
+            layout: PartitionLayout::equal(5),
             ..Default::default()
         };
-        let devices = vec!["/dev/sda".to_string()];
-        assert!(!should_use_gpt(&devices, &config).unwrap());
+        let (plans, rejected) = plan_devices(vec![BlockDevice::fake("sda", 1 << 30)], &config);
+        assert!(plans.is_empty());
+        assert_eq!(rejected[0].reason, RejectReason::TooManyPartitionsForMbr(5));
+        assert_eq!(
+            rejected[0].reason.to_string(),
+            "5 partitions don't fit in an MBR"
+        );
     }
 }
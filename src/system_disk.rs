@@ -0,0 +1,319 @@
+//! System disk module for auto_mount
+//!
+//! This module finds the disks the running system depends on: those backing
+//! `/`, `/boot`, `/boot/efi`, active swap and every fstab entry, followed down
+//! through partitions and device-mapper or md stacks
+
+use crate::device_usage::{read_mounts, read_swaps, read_trimmed, unescape};
+use crate::{DeviceNumber, SystemRoot};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+
+/// Mount points whose backing disks are always system disks
+const SYSTEM_MOUNT_POINTS: [&str; 3] = ["/", "/boot", "/boot/efi"];
+
+/// Errors that can occur during system disk detection
+#[derive(Debug, thiserror::Error)]
+pub enum SystemDiskError {
+    #[error("IO error: {0}")]
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for SystemDiskError {
+    fn from(error: std::io::Error) -> Self {
+        SystemDiskError::IoError(error)
+    }
+}
+
+/// What the running system uses a disk for
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SystemRole {
+    /// Backs the mounted `/`, `/boot` or `/boot/efi`
+    Mount(String),
+    /// Backs an active swap area
+    Swap,
+    /// Backs the `/etc/fstab` entry for the given mount point
+    Fstab(String),
+}
+
+impl fmt::Display for SystemRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SystemRole::Mount(mount_point) => write!(f, "backs {}", mount_point),
+            SystemRole::Swap => write!(f, "backs active swap"),
+            SystemRole::Fstab(mount_point) => write!(f, "backs fstab entry {}", mount_point),
+        }
+    }
+}
+
+/// A whole disk the running system depends on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemDisk {
+    /// Kernel name of the whole disk (`sda`)
+    pub name: String,
+    pub roles: Vec<SystemRole>,
+}
+
+/// Find the disks backing the running system
+pub fn find_system_disks() -> Result<Vec<SystemDisk>, SystemDiskError> {
+    find_system_disks_in(&SystemRoot::default())
+}
+
+/// Find the disks backing the system described by the given root
+pub fn find_system_disks_in(root: &SystemRoot) -> Result<Vec<SystemDisk>, SystemDiskError> {
+    let numbers = device_numbers(root);
+    let mut roles: BTreeMap<String, BTreeSet<SystemRole>> = BTreeMap::new();
+    let mut assign = |name: Option<String>, role: SystemRole| {
+        for disk in name
+            .map(|name| backing_disks(root, &name))
+            .unwrap_or_default()
+        {
+            roles.entry(disk).or_default().insert(role.clone());
+        }
+    };
+
+    for mount in read_mounts(root)? {
+        if SYSTEM_MOUNT_POINTS.contains(&mount.mount_point.as_str()) {
+            // btrfs reports an anonymous device number, so fall back to the source path
+            let name = mount
+                .device_number
+                .and_then(|number| numbers.get(&number).cloned())
+                .or_else(|| resolve_path(root, &mount.source));
+            assign(name, SystemRole::Mount(mount.mount_point));
+        }
+    }
+    for swap in read_swaps(root)? {
+        assign(resolve_path(root, &swap), SystemRole::Swap);
+    }
+    for (spec, mount_point) in read_fstab(root)? {
+        assign(resolve_spec(root, &spec), SystemRole::Fstab(mount_point));
+    }
+
+    Ok(roles
+        .into_iter()
+        .map(|(name, roles)| SystemDisk {
+            name,
+            roles: roles.into_iter().collect(),
+        })
+        .collect())
+}
+
+/// Roles of the system disks underneath a device (a disk, partition or stacked device)
+pub(crate) fn system_roles(
+    root: &SystemRoot,
+    system_disks: &[SystemDisk],
+    name: &str,
+) -> Vec<SystemRole> {
+    let mut roles = BTreeSet::new();
+    for disk in backing_disks(root, name) {
+        if let Some(system_disk) = system_disks
+            .iter()
+            .find(|system_disk| system_disk.name == disk)
+        {
+            roles.extend(system_disk.roles.iter().cloned());
+        }
+    }
+    roles.into_iter().collect()
+}
+
+/// Roles joined for error messages
+pub(crate) fn describe_roles(roles: &[SystemRole]) -> String {
+    let roles: Vec<String> = roles.iter().map(ToString::to_string).collect();
+    roles.join(", ")
+}
+
+/// Kernel names of the whole disks underneath a device, following slaves of
+/// dm/md devices and the parent disk of partitions
+fn backing_disks(root: &SystemRoot, name: &str) -> Vec<String> {
+    let mut disks = BTreeSet::new();
+    collect_backing_disks(root, name, &mut disks, &mut BTreeSet::new());
+    disks.into_iter().collect()
+}
+
+fn collect_backing_disks(
+    root: &SystemRoot,
+    name: &str,
+    disks: &mut BTreeSet<String>,
+    visited: &mut BTreeSet<String>,
+) {
+    if !visited.insert(name.to_string()) {
+        return;
+    }
+
+    let dir = root.sys_class_block().join(name);
+    let slaves: Vec<String> = fs::read_dir(dir.join("slaves"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+
+    if !slaves.is_empty() {
+        for slave in slaves {
+            collect_backing_disks(root, &slave, disks, visited);
+        }
+    } else if dir.join("partition").exists() {
+        // A partition's sysfs directory sits inside its disk's directory
+        let parent = fs::canonicalize(&dir)
+            .ok()
+            .and_then(|dir| Some(dir.parent()?.file_name()?.to_string_lossy().to_string()));
+        if let Some(parent) = parent {
+            collect_backing_disks(root, &parent, disks, visited);
+        }
+    } else {
+        disks.insert(name.to_string());
+    }
+}
+
+/// Kernel names of every block device, keyed by device number
+fn device_numbers(root: &SystemRoot) -> BTreeMap<DeviceNumber, String> {
+    fs::read_dir(root.sys_class_block())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let number = read_trimmed(&entry.path().join("dev"))?.parse().ok()?;
+            Some((number, entry.file_name().to_string_lossy().to_string()))
+        })
+        .collect()
+}
+
+/// Kernel name behind a `/dev/...` path, following udev symlinks and
+/// `/dev/mapper` names
+fn resolve_path(root: &SystemRoot, path: &str) -> Option<String> {
+    if !path.starts_with("/dev/") {
+        return None;
+    }
+
+    let node = root.dev_path(path);
+    let resolved = fs::canonicalize(&node).unwrap_or(node);
+    let name = resolved.file_name()?.to_string_lossy().to_string();
+    if root.sys_class_block().join(&name).exists() {
+        return Some(name);
+    }
+
+    let dm_name = path.strip_prefix("/dev/mapper/")?;
+    fs::read_dir(root.sys_class_block())
+        .ok()?
+        .flatten()
+        .find(|entry| read_trimmed(&entry.path().join("dm/name")).as_deref() == Some(dm_name))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+}
+
+/// Kernel name behind an fstab device spec (`/dev/sda1`, `UUID=...`, `LABEL=...`)
+pub(crate) fn resolve_spec(root: &SystemRoot, spec: &str) -> Option<String> {
+    let links = [
+        ("UUID=", "/dev/disk/by-uuid/"),
+        ("LABEL=", "/dev/disk/by-label/"),
+        ("PARTUUID=", "/dev/disk/by-partuuid/"),
+        ("PARTLABEL=", "/dev/disk/by-partlabel/"),
+    ];
+    for (tag, directory) in links {
+        if let Some(value) = spec.strip_prefix(tag) {
+            let value = value.trim_matches('"');
+            return resolve_path(root, &format!("{}{}", directory, value));
+        }
+    }
+    resolve_path(root, spec)
+}
+
+/// Device spec and mount point of every `/etc/fstab` entry
+fn read_fstab(root: &SystemRoot) -> io::Result<Vec<(String, String)>> {
+    let fstab = match fs::read_to_string(root.fstab()) {
+        Ok(fstab) => fstab,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+
+    Ok(fstab
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((unescape(fields.next()?), unescape(fields.next()?)))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
+
+    #[test]
+    fn test_mounts_and_swap() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").partition(1 << 30).partition(1 << 30))
+            .disk(FakeDisk::new("sdb").partition(1 << 30))
+            .disk(FakeDisk::new("sdc"))
+            .mount("sda1", "/", "ext4")
+            .mount("sdb1", "/data", "ext4")
+            .swap("sda2")
+            .build()
+            .unwrap();
+
+        let disks = find_system_disks_in(fixture.root()).unwrap();
+        assert_eq!(
+            disks,
+            vec![SystemDisk {
+                name: "sda".to_string(),
+                roles: vec![SystemRole::Mount("/".to_string()), SystemRole::Swap],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_root_on_lvm_over_raid() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").partition(1 << 30))
+            .disk(FakeDisk::new("sdb").partition(1 << 30))
+            .disk(FakeDisk::new("sdc"))
+            .md("md0", &["sda1", "sdb1"])
+            .dm("dm-0", "vg0-root", &["md0"])
+            .mount("dm-0", "/", "xfs")
+            .build()
+            .unwrap();
+        let root = fixture.root();
+
+        let disks = find_system_disks_in(root).unwrap();
+        let names: Vec<&str> = disks.iter().map(|disk| disk.name.as_str()).collect();
+        assert_eq!(names, vec!["sda", "sdb"]);
+        assert_eq!(
+            system_roles(root, &disks, "sdb1"),
+            vec![SystemRole::Mount("/".to_string())]
+        );
+        assert!(system_roles(root, &disks, "sdc").is_empty());
+    }
+
+    #[test]
+    fn test_unmounted_fstab_entries() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sda").partition(1 << 20).partition(1 << 30))
+            .disk(FakeDisk::new("sdb").partition(1 << 30))
+            .disk(FakeDisk::new("sdc"))
+            .dm("dm-0", "vg1-home", &["sdb1"])
+            .symlink("dev/disk/by-uuid/3A1F-22C4", "../../sda1")
+            .file(
+                "etc/fstab",
+                "# /boot/efi was on /dev/sda1 during installation\n\
+                 UUID=3A1F-22C4  /boot/efi  vfat  umask=0077,noauto  0 1\n\
+                 /dev/mapper/vg1-home  /home  ext4  defaults  0 2\n\
+                 UUID=missing  /mnt/usb  ext4  noauto  0 0\n\
+                 tmpfs  /tmp  tmpfs  defaults  0 0\n",
+            )
+            .build()
+            .unwrap();
+
+        let disks = find_system_disks_in(fixture.root()).unwrap();
+        assert_eq!(disks.len(), 2);
+        assert_eq!(
+            disks[0].roles,
+            vec![SystemRole::Fstab("/boot/efi".to_string())]
+        );
+        assert_eq!(disks[1].name, "sdb");
+        assert_eq!(disks[1].roles[0].to_string(), "backs fstab entry /home");
+    }
+}
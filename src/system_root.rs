@@ -0,0 +1,190 @@
+//! System root module for auto_mount
+//!
+//! This module describes where sysfs, procfs, /dev, /etc and /var are found, so every
+//! other module can be pointed at a fake tree instead of the live system
+
+use std::path::{Path, PathBuf};
+
+/// Locations of the system trees auto_mount reads from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemRoot {
+    prefix: PathBuf,
+    sys: PathBuf,
+    proc: PathBuf,
+    dev: PathBuf,
+    etc: PathBuf,
+    var: PathBuf,
+}
+
+impl Default for SystemRoot {
+    fn default() -> Self {
+        SystemRoot::new("/")
+    }
+}
+
+impl SystemRoot {
+    /// All trees below a common prefix (`<prefix>/sys`, `<prefix>/proc`, ...)
+    pub fn new<P: AsRef<Path>>(prefix: P) -> Self {
+        let prefix = prefix.as_ref();
+        SystemRoot {
+            prefix: prefix.to_path_buf(),
+            sys: prefix.join("sys"),
+            proc: prefix.join("proc"),
+            dev: prefix.join("dev"),
+            etc: prefix.join("etc"),
+            var: prefix.join("var"),
+        }
+    }
+
+    /// Override the sysfs mount point
+    pub fn with_sys<P: Into<PathBuf>>(mut self, sys: P) -> Self {
+        self.sys = sys.into();
+        self
+    }
+
+    /// Override the procfs mount point
+    pub fn with_proc<P: Into<PathBuf>>(mut self, proc: P) -> Self {
+        self.proc = proc.into();
+        self
+    }
+
+    /// Override the device node directory
+    pub fn with_dev<P: Into<PathBuf>>(mut self, dev: P) -> Self {
+        self.dev = dev.into();
+        self
+    }
+
+    /// Override the configuration directory holding fstab
+    pub fn with_etc<P: Into<PathBuf>>(mut self, etc: P) -> Self {
+        self.etc = etc.into();
+        self
+    }
+
+    /// Override the variable data directory holding partition table backups
+    pub fn with_var<P: Into<PathBuf>>(mut self, var: P) -> Self {
+        self.var = var.into();
+        self
+    }
+
+    pub fn sys(&self) -> &Path {
+        &self.sys
+    }
+
+    pub fn proc(&self) -> &Path {
+        &self.proc
+    }
+
+    pub fn dev(&self) -> &Path {
+        &self.dev
+    }
+
+    pub fn etc(&self) -> &Path {
+        &self.etc
+    }
+
+    pub fn var(&self) -> &Path {
+        &self.var
+    }
+
+    /// Whether every tree is the live system's, so commands acting on the
+    /// running system (`mount -a`, `blkid`) see the same disks and fstab
+    pub fn is_live(&self) -> bool {
+        *self == SystemRoot::default()
+    }
+
+    /// Location of any other absolute path below the prefix, e.g. a mount point
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.prefix.join(path.trim_start_matches('/'))
+    }
+
+    /// `/sys/block`, listing whole disks only
+    pub fn sys_block(&self) -> PathBuf {
+        self.sys.join("block")
+    }
+
+    /// `/sys/class/block`, listing whole disks and partitions alike
+    pub fn sys_class_block(&self) -> PathBuf {
+        self.sys.join("class/block")
+    }
+
+    /// `/sys/class/scsi_host`, one entry per SCSI host adapter
+    pub fn sys_class_scsi_host(&self) -> PathBuf {
+        self.sys.join("class/scsi_host")
+    }
+
+    /// Location of a device node, e.g. `/dev/sda`
+    pub fn dev_node(&self, name: &str) -> PathBuf {
+        self.dev.join(name)
+    }
+
+    /// Location of the device node behind a `/dev/...` path
+    pub fn dev_path(&self, path: &str) -> PathBuf {
+        match path.strip_prefix("/dev/") {
+            Some(relative) => self.dev.join(relative),
+            None => PathBuf::from(path),
+        }
+    }
+
+    /// `/proc/self/mountinfo`
+    pub fn mountinfo(&self) -> PathBuf {
+        self.proc.join("self/mountinfo")
+    }
+
+    /// `/proc/swaps`
+    pub fn swaps(&self) -> PathBuf {
+        self.proc.join("swaps")
+    }
+
+    /// `/etc/fstab`
+    pub fn fstab(&self) -> PathBuf {
+        self.etc.join("fstab")
+    }
+
+    /// `/var/backups/auto_mount`, where partition tables are saved before changes
+    pub fn partition_backups(&self) -> PathBuf {
+        self.var.join("backups/auto_mount")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_root() {
+        let root = SystemRoot::default();
+        assert_eq!(root.sys_block(), Path::new("/sys/block"));
+        assert_eq!(root.sys_class_block(), Path::new("/sys/class/block"));
+        assert_eq!(
+            root.sys_class_scsi_host(),
+            Path::new("/sys/class/scsi_host")
+        );
+        assert_eq!(root.dev_node("sda"), Path::new("/dev/sda"));
+        assert_eq!(root.mountinfo(), Path::new("/proc/self/mountinfo"));
+        assert_eq!(root.swaps(), Path::new("/proc/swaps"));
+        assert_eq!(root.fstab(), Path::new("/etc/fstab"));
+        assert_eq!(
+            root.partition_backups(),
+            Path::new("/var/backups/auto_mount")
+        );
+        assert_eq!(root.path("/mnt/sdb1"), Path::new("/mnt/sdb1"));
+        assert!(root.is_live());
+    }
+
+    #[test]
+    fn test_prefixed_root() {
+        let root = SystemRoot::new("/tmp/fake").with_proc("/tmp/other/proc");
+        assert_eq!(root.sys_block(), Path::new("/tmp/fake/sys/block"));
+        assert_eq!(
+            root.dev_path("/dev/disk/by-id/x"),
+            Path::new("/tmp/fake/dev/disk/by-id/x")
+        );
+        assert_eq!(
+            root.mountinfo(),
+            Path::new("/tmp/other/proc/self/mountinfo")
+        );
+        assert_eq!(root.path("/mnt/sdb1"), Path::new("/tmp/fake/mnt/sdb1"));
+        assert!(!root.is_live());
+        assert!(!SystemRoot::default().with_etc("/tmp/etc").is_live());
+    }
+}
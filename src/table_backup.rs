@@ -0,0 +1,578 @@
+//! Partition table backup module for auto_mount
+//!
+//! This module saves the first and last MiB of a disk and every extended boot
+//! record beyond them, together with an sfdisk-style dump of its table, before
+//! the table is changed, and loads and verifies such backups again so they can
+//! be written back
+
+use crate::partition_manager::partition_path;
+use crate::{parse_partition_table, BlockDevice, PartitionTable, PartitionTableError, SystemRoot};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Raw bytes saved from each end of the disk; covers MBR, GPT and the backup GPT
+const SAVED_BYTES: u64 = 1 << 20;
+const HEAD_FILE: &str = "head.img";
+const TAIL_FILE: &str = "tail.img";
+/// Extended boot records outside head and tail, each as its LBA (little endian) and sector
+const EBR_FILE: &str = "ebr.img";
+const DUMP_FILE: &str = "table.sfdisk";
+
+/// Errors that can occur while saving or loading a partition table backup
+#[derive(Debug, thiserror::Error)]
+pub enum TableBackupError {
+    #[error("Backup {dir} is incomplete: {reason}")]
+    Corrupt { dir: PathBuf, reason: String },
+    #[error("Backup of {expected} bytes doesn't fit a disk of {found} bytes")]
+    SizeMismatch { expected: u64, found: u64 },
+    #[error("Backup uses {expected}-byte sectors but the disk has {found}-byte sectors")]
+    SectorSizeMismatch { expected: u64, found: u64 },
+    #[error("Partition table error: {0}")]
+    PartitionTable(#[from] PartitionTableError),
+    #[error("IO error: {0}")]
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for TableBackupError {
+    fn from(error: std::io::Error) -> Self {
+        TableBackupError::IoError(error)
+    }
+}
+
+/// The saved partition table of one disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableBackup {
+    /// Directory holding the backup files
+    pub dir: PathBuf,
+    /// Disk the backup was taken from, e.g. `/dev/sdb`
+    pub device: String,
+    pub size_bytes: u64,
+    pub sector_size: u64,
+    /// First bytes of the disk
+    pub head: Vec<u8>,
+    /// Last bytes of the disk
+    pub tail: Vec<u8>,
+    /// Extended boot records outside head and tail, by sector
+    pub ebrs: Vec<(u64, Vec<u8>)>,
+    /// sfdisk-style description of the saved table
+    pub dump: String,
+}
+
+impl TableBackup {
+    /// Load a backup directory, checking the saved sectors against their
+    /// checksums and against the table described in the dump
+    pub fn load(dir: &Path) -> Result<TableBackup, TableBackupError> {
+        let corrupt = |reason: String| TableBackupError::Corrupt {
+            dir: dir.to_path_buf(),
+            reason,
+        };
+        let dump = fs::read_to_string(dir.join(DUMP_FILE))?;
+        let header = |key: &str| {
+            let prefix = format!("# {}: ", key);
+            dump.lines()
+                .find_map(|line| line.strip_prefix(&prefix))
+                .map(str::to_string)
+                .ok_or_else(|| corrupt(format!("no {} in {}", key, DUMP_FILE)))
+        };
+        let number = |key: &str| {
+            header(key)?
+                .parse::<u64>()
+                .map_err(|_| corrupt(format!("invalid {} in {}", key, DUMP_FILE)))
+        };
+
+        let mut backup = TableBackup {
+            dir: dir.to_path_buf(),
+            device: header("device")?,
+            size_bytes: number("size-bytes")?,
+            sector_size: number("sector-size")?,
+            head: fs::read(dir.join(HEAD_FILE))?,
+            tail: fs::read(dir.join(TAIL_FILE))?,
+            ebrs: Vec::new(),
+            dump: dump.clone(),
+        };
+
+        let saved = SAVED_BYTES.min(backup.size_bytes) as usize;
+        for (file, data, key) in [
+            (HEAD_FILE, &backup.head, "head-crc32"),
+            (TAIL_FILE, &backup.tail, "tail-crc32"),
+        ] {
+            if data.len() != saved {
+                return Err(corrupt(format!("{} holds {} bytes", file, data.len())));
+            }
+            if format!("{:08x}", crc32fast::hash(data)) != header(key)? {
+                return Err(corrupt(format!("{} checksum mismatch", file)));
+            }
+        }
+
+        let ebrs = fs::read(dir.join(EBR_FILE))?;
+        if format!("{:08x}", crc32fast::hash(&ebrs)) != header("ebr-crc32")? {
+            return Err(corrupt(format!("{} checksum mismatch", EBR_FILE)));
+        }
+        let record = 8 + backup.sector_size as usize;
+        if ebrs.len() % record != 0 {
+            return Err(corrupt(format!("{} holds {} bytes", EBR_FILE, ebrs.len())));
+        }
+        backup.ebrs = ebrs
+            .chunks(record)
+            .map(|chunk| {
+                let mut lba = [0; 8];
+                lba.copy_from_slice(&chunk[..8]);
+                (u64::from_le_bytes(lba), chunk[8..].to_vec())
+            })
+            .collect();
+
+        let expected = render_dump(&backup.device, backup.sector_size, &backup.table()?);
+        if table_lines(&expected) != table_lines(&dump) {
+            return Err(corrupt(format!(
+                "{} doesn't match the saved sectors",
+                DUMP_FILE
+            )));
+        }
+        Ok(backup)
+    }
+
+    /// The table held by the saved sectors; `None` for a disk without one
+    pub fn table(&self) -> Result<Option<PartitionTable>, TableBackupError> {
+        let mut image = SavedImage {
+            head: &self.head,
+            tail: &self.tail,
+            ebrs: &self.ebrs,
+            sector_size: self.sector_size,
+            size_bytes: self.size_bytes,
+            position: 0,
+        };
+        match parse_partition_table(&mut image, self.sector_size) {
+            Ok(table) => Ok(Some(table)),
+            Err(PartitionTableError::NoPartitionTable | PartitionTableError::InvalidGpt { .. }) => {
+                Ok(None)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Check the backup can be written to a disk of the given geometry
+    pub fn check_target(&self, size_bytes: u64, sector_size: u64) -> Result<(), TableBackupError> {
+        if size_bytes != self.size_bytes {
+            return Err(TableBackupError::SizeMismatch {
+                expected: self.size_bytes,
+                found: size_bytes,
+            });
+        }
+        if sector_size != self.sector_size {
+            return Err(TableBackupError::SectorSizeMismatch {
+                expected: self.sector_size,
+                found: sector_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Save the partition table of a block device into a new directory below `dir`
+pub fn backup_partition_table(
+    device: &BlockDevice,
+    dir: &Path,
+) -> Result<TableBackup, TableBackupError> {
+    backup_partition_table_in(&SystemRoot::default(), device, dir)
+}
+
+/// Save the partition table of a block device, opening its node below the given
+/// system root, into a new directory `<dir>/<disk>-<timestamp>`
+pub fn backup_partition_table_in(
+    root: &SystemRoot,
+    device: &BlockDevice,
+    dir: &Path,
+) -> Result<TableBackup, TableBackupError> {
+    let mut file = File::open(root.dev_path(&device.path))?;
+    let size_bytes = file.seek(SeekFrom::End(0))?;
+    let saved = SAVED_BYTES.min(size_bytes);
+    let mut head = vec![0; saved as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut head)?;
+    let mut tail = vec![0; saved as usize];
+    file.seek(SeekFrom::Start(size_bytes - saved))?;
+    file.read_exact(&mut tail)?;
+
+    // Logical partitions are chained through EBRs anywhere on the disk
+    let sector_size = u64::from(device.logical_sector_size.max(512));
+    let ebr_lbas = match parse_partition_table(&mut file, sector_size) {
+        Ok(table) => table.ebr_lbas,
+        Err(PartitionTableError::NoPartitionTable | PartitionTableError::InvalidGpt { .. }) => {
+            Vec::new()
+        }
+        Err(error) => return Err(error.into()),
+    };
+    let mut ebrs = Vec::new();
+    for lba in ebr_lbas {
+        let start = lba * sector_size;
+        if start >= saved && start + sector_size <= size_bytes - saved {
+            let mut sector = vec![0; sector_size as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(&mut sector)?;
+            ebrs.push((lba, sector));
+        }
+    }
+    let ebr_file = encode_ebrs(&ebrs);
+
+    let mut backup = TableBackup {
+        dir: create_backup_dir(dir, &device.name)?,
+        device: device.path.clone(),
+        size_bytes,
+        sector_size,
+        head,
+        tail,
+        ebrs,
+        dump: String::new(),
+    };
+    let mut dump = format!(
+        "# auto_mount partition table backup\n\
+         # device: {}\n\
+         # size-bytes: {}\n\
+         # sector-size: {}\n\
+         # head-crc32: {:08x}\n\
+         # tail-crc32: {:08x}\n\
+         # ebr-crc32: {:08x}\n",
+        backup.device,
+        backup.size_bytes,
+        backup.sector_size,
+        crc32fast::hash(&backup.head),
+        crc32fast::hash(&backup.tail),
+        crc32fast::hash(&ebr_file),
+    );
+    dump.push_str(&render_dump(
+        &backup.device,
+        backup.sector_size,
+        &backup.table()?,
+    ));
+    backup.dump = dump;
+
+    fs::write(backup.dir.join(HEAD_FILE), &backup.head)?;
+    fs::write(backup.dir.join(TAIL_FILE), &backup.tail)?;
+    fs::write(backup.dir.join(EBR_FILE), &ebr_file)?;
+    fs::write(backup.dir.join(DUMP_FILE), &backup.dump)?;
+    Ok(backup)
+}
+
+/// Lay out saved EBRs as the contents of [`EBR_FILE`]
+fn encode_ebrs(ebrs: &[(u64, Vec<u8>)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (lba, sector) in ebrs {
+        data.extend_from_slice(&lba.to_le_bytes());
+        data.extend_from_slice(sector);
+    }
+    data
+}
+
+/// Create `<dir>/<name>-<timestamp>`, numbering it when several backups of a
+/// disk are taken within a second
+fn create_backup_dir(dir: &Path, name: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+
+    let mut attempt = 1;
+    loop {
+        let candidate = match attempt {
+            1 => dir.join(format!("{}-{}", name, timestamp)),
+            _ => dir.join(format!("{}-{}.{}", name, timestamp, attempt)),
+        };
+        match fs::create_dir(&candidate) {
+            Ok(()) => return Ok(candidate),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Describe a table the way `sfdisk --dump` does
+fn render_dump(device: &str, sector_size: u64, table: &Option<PartitionTable>) -> String {
+    let table = match table {
+        Some(table) => table,
+        None => return "# no partition table\n".to_string(),
+    };
+    let mut dump = format!("label: {}\n", table.kind);
+    if let Some(guid) = table.disk_guid {
+        let _ = writeln!(dump, "label-id: {}", guid);
+    }
+    if let Some(signature) = table.disk_signature {
+        let _ = writeln!(dump, "label-id: 0x{:08x}", signature);
+    }
+    let _ = write!(
+        dump,
+        "device: {}\nunit: sectors\nfirst-lba: {}\nlast-lba: {}\nsector-size: {}\n\n",
+        device, table.first_usable_lba, table.last_usable_lba, sector_size
+    );
+
+    for partition in &table.partitions {
+        let _ = write!(
+            dump,
+            "{} : start={}, size={}, type=",
+            partition_path(device, partition.number),
+            partition.first_lba,
+            partition.sectors()
+        );
+        match partition.partition_type {
+            crate::PartitionType::Mbr(id) => {
+                let _ = write!(dump, "{:x}", id);
+            }
+            crate::PartitionType::Gpt(guid) => {
+                let _ = write!(dump, "{}", guid);
+            }
+        }
+        if let Some(guid) = partition.unique_guid {
+            let _ = write!(dump, ", uuid={}", guid);
+        }
+        match partition.name.as_deref() {
+            Some(name) if !name.is_empty() => {
+                let _ = write!(dump, ", name=\"{}\"", name.replace('"', "\\\""));
+            }
+            _ => {}
+        }
+        if partition.bootable {
+            dump.push_str(", bootable");
+        }
+        dump.push('\n');
+    }
+    dump
+}
+
+/// The dump without comments and blank lines
+fn table_lines(dump: &str) -> Vec<&str> {
+    dump.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+/// A disk image made of the saved head, tail and EBRs, with zeros in between
+struct SavedImage<'a> {
+    head: &'a [u8],
+    tail: &'a [u8],
+    ebrs: &'a [(u64, Vec<u8>)],
+    sector_size: u64,
+    size_bytes: u64,
+    position: u64,
+}
+
+impl Read for SavedImage<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let tail_start = self.size_bytes - self.tail.len() as u64;
+        let head_end = self.head.len() as u64;
+        let position = self.position;
+        let count = if position >= self.size_bytes {
+            0
+        } else if position < head_end {
+            let source = &self.head[position as usize..];
+            let count = source.len().min(buffer.len());
+            buffer[..count].copy_from_slice(&source[..count]);
+            count
+        } else if position >= tail_start {
+            let source = &self.tail[(position - tail_start) as usize..];
+            let count = source.len().min(buffer.len());
+            buffer[..count].copy_from_slice(&source[..count]);
+            count
+        } else if let Some((lba, sector)) = self.ebrs.iter().find(|(lba, _)| {
+            position >= lba * self.sector_size && position < (lba + 1) * self.sector_size
+        }) {
+            let source = &sector[(position - lba * self.sector_size) as usize..];
+            let count = source.len().min(buffer.len());
+            buffer[..count].copy_from_slice(&source[..count]);
+            count
+        } else {
+            // Zeros up to the next saved sector
+            let next = self
+                .ebrs
+                .iter()
+                .map(|(lba, _)| lba * self.sector_size)
+                .filter(|&start| start > position)
+                .fold(tail_start, u64::min);
+            let count = ((next - position) as usize).min(buffer.len());
+            buffer[..count].iter_mut().for_each(|byte| *byte = 0);
+            count
+        };
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for SavedImage<'_> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let relative = |base: u64, offset: i64| match offset {
+            0.. => base.checked_add(offset as u64),
+            _ => base.checked_sub(offset.unsigned_abs()),
+        };
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => relative(self.size_bytes, offset),
+            SeekFrom::Current(offset) => relative(self.position, offset),
+        };
+        self.position = target.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before start of image")
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FakeDisk, FixtureBuilder};
+    use crate::{GptDisk, Guid, MbrDisk};
+    use std::fs::OpenOptions;
+
+    #[test]
+    fn test_backup_gpt_round_trip() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb"))
+            .file("dev/sdb", vec![0; 8 << 20])
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let mut image = OpenOptions::new()
+            .write(true)
+            .open(root.dev_path("/dev/sdb"))
+            .unwrap();
+        let mut disk = GptDisk::with_guid(8 << 11, 512, Guid::LINUX_LVM).unwrap();
+        disk.add_partition(Guid::LINUX_FILESYSTEM, 2048, 8191, "data")
+            .unwrap();
+        disk.write(&mut image).unwrap();
+
+        let sdb = BlockDevice::from_name_in(root, "sdb").unwrap();
+        let backups = root.partition_backups();
+        let backup = backup_partition_table_in(root, &sdb, &backups).unwrap();
+        assert!(backup.dir.starts_with(&backups));
+        assert_eq!(backup.head.len(), 1 << 20);
+        assert!(backup.dump.contains("label: gpt\n"));
+        assert!(backup
+            .dump
+            .contains(&format!("label-id: {}\n", Guid::LINUX_LVM)));
+        assert!(backup.dump.contains(&format!(
+            "/dev/sdb1 : start=2048, size=6144, type={}",
+            Guid::LINUX_FILESYSTEM
+        )));
+        assert!(backup.dump.contains("name=\"data\""));
+
+        let loaded = TableBackup::load(&backup.dir).unwrap();
+        assert_eq!(loaded, backup);
+        assert_eq!(loaded.table().unwrap().unwrap().partitions.len(), 1);
+
+        // A second backup in the same second gets its own directory
+        let again = backup_partition_table_in(root, &sdb, &backups).unwrap();
+        assert_ne!(again.dir, backup.dir);
+    }
+
+    #[test]
+    fn test_backup_mbr_and_blank_disks() {
+        let mut mbr = io::Cursor::new(vec![0; 4 << 20]);
+        let mut disk = MbrDisk::with_signature(8 << 10, 512, 0xdead_beef).unwrap();
+        disk.add_partition(0x83, 2048, 4095).unwrap();
+        disk.write(&mut mbr).unwrap();
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb"))
+            .disk(FakeDisk::new("sdc"))
+            .file("dev/sdb", mbr.into_inner())
+            .file("dev/sdc", vec![0; 4096])
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let backups = root.partition_backups();
+
+        let sdb = BlockDevice::from_name_in(root, "sdb").unwrap();
+        let backup = backup_partition_table_in(root, &sdb, &backups).unwrap();
+        assert!(backup.dump.contains("label: dos\nlabel-id: 0xdeadbeef\n"));
+        assert!(backup
+            .dump
+            .contains("/dev/sdb1 : start=2048, size=2048, type=83\n"));
+
+        // Tiny disks are saved whole, twice
+        let sdc = BlockDevice::from_name_in(root, "sdc").unwrap();
+        let backup = backup_partition_table_in(root, &sdc, &backups).unwrap();
+        assert_eq!(backup.head.len(), 4096);
+        assert!(backup.dump.ends_with("# no partition table\n"));
+        assert_eq!(
+            TableBackup::load(&backup.dir).unwrap().table().unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_backup_saves_ebr_chain() {
+        let mut image = vec![0; 8 << 20];
+        let mut entry = |sector: usize, slot: usize, type_id: u8, start: u32, len: u32| {
+            let offset = sector * 512 + 446 + slot * 16;
+            image[offset + 4] = type_id;
+            image[offset + 8..offset + 12].copy_from_slice(&start.to_le_bytes());
+            image[offset + 12..offset + 16].copy_from_slice(&len.to_le_bytes());
+            image[sector * 512 + 510..sector * 512 + 512].copy_from_slice(&[0x55, 0xaa]);
+        };
+        // Both EBRs lie between the saved first and last MiB
+        entry(0, 0, 0x05, 4096, 8192);
+        entry(4096, 0, 0x83, 64, 1000);
+        entry(4096, 1, 0x05, 4096, 2048);
+        entry(8192, 0, 0x8e, 64, 1000);
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb"))
+            .file("dev/sdb", image)
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let sdb = BlockDevice::from_name_in(root, "sdb").unwrap();
+
+        let backup = backup_partition_table_in(root, &sdb, &root.partition_backups()).unwrap();
+        let lbas: Vec<u64> = backup.ebrs.iter().map(|(lba, _)| *lba).collect();
+        assert_eq!(lbas, vec![4096, 8192]);
+        assert!(backup
+            .dump
+            .contains("/dev/sdb5 : start=4160, size=1000, type=83\n"));
+        assert!(backup
+            .dump
+            .contains("/dev/sdb6 : start=8256, size=1000, type=8e\n"));
+        assert_eq!(TableBackup::load(&backup.dir).unwrap(), backup);
+
+        fs::write(backup.dir.join(EBR_FILE), []).unwrap();
+        assert!(matches!(
+            TableBackup::load(&backup.dir),
+            Err(TableBackupError::Corrupt { .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_rejects_damaged_backups() {
+        let fixture = FixtureBuilder::new()
+            .disk(FakeDisk::new("sdb"))
+            .file("dev/sdb", vec![0; 4 << 20])
+            .build()
+            .unwrap();
+        let root = fixture.root();
+        let sdb = BlockDevice::from_name_in(root, "sdb").unwrap();
+        let backup = backup_partition_table_in(root, &sdb, &root.partition_backups()).unwrap();
+
+        let mut head = backup.head.clone();
+        head[510] = 0x55;
+        fs::write(backup.dir.join(HEAD_FILE), &head).unwrap();
+        assert!(matches!(
+            TableBackup::load(&backup.dir),
+            Err(TableBackupError::Corrupt { .. })
+        ));
+
+        fs::write(backup.dir.join(HEAD_FILE), &head[..512]).unwrap();
+        assert!(matches!(
+            TableBackup::load(&backup.dir),
+            Err(TableBackupError::Corrupt { .. })
+        ));
+
+        assert!(matches!(
+            backup.check_target(8 << 20, 512),
+            Err(TableBackupError::SizeMismatch { .. })
+        ));
+        assert!(matches!(
+            backup.check_target(4 << 20, 4096),
+            Err(TableBackupError::SectorSizeMismatch { .. })
+        ));
+    }
+}